@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::http::Validators;
+use crate::utils::write_file_atomic;
+
+/// How old a cached response may be before it is no longer used, by default.
+pub const DEFAULT_CACHE_MAX_AGE_HOURS: u64 = 168;
+
+/// When a cached response is used in place of the server's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheFallback {
+    /// Always ask the server (the default). Responses are still cached.
+    #[default]
+    Never,
+    /// Use the cache when the server can't be reached or is failing.
+    WhenUnavailable,
+    /// Never contact the server; only use the cache.
+    Always,
+}
+
+/// Where successful responses are cached, and when they are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheSettings {
+    /// Directory holding one file per cached request.
+    pub dir: PathBuf,
+    /// When the cache is used instead of the server.
+    pub fallback: CacheFallback,
+    /// How old a cached response may be and still be used.
+    pub max_age: Duration,
+}
+
+/// The last successful, validated response to a request, as stored on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    /// The full URL requested, including the query string.
+    pub url: String,
+    /// When the response was fetched, or last confirmed unchanged, as a Unix
+    /// timestamp.
+    pub fetched_at: i64,
+    /// The validators the response was served with.
+    #[serde(flatten)]
+    pub validators: Validators,
+    /// The response body, exactly as sent, so its signature can be checked
+    /// again.
+    pub body: String,
+    /// The signature the response was served with, if any.
+    pub signature: Option<String>,
+}
+
+impl CachedResponse {
+    /// How long ago the response was fetched.
+    pub fn age(&self) -> Duration {
+        let fetched_at = DateTime::from_timestamp(self.fetched_at, 0).unwrap_or_default();
+        (Utc::now() - fetched_at).to_std().unwrap_or_default()
+    }
+}
+
+/// The server responses a written file was last synced from, so a sync that
+/// finds none of them changed can leave the file alone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetState {
+    /// What was written where, e.g. `ssh /root/.ssh/authorized_keys (Managed)`.
+    pub target: String,
+    /// Each server's URL, and the validators of its response.
+    pub versions: Vec<(String, Validators)>,
+    /// A hash of the file as written, to notice it being changed since.
+    pub digest: u64,
+}
+
+/// The default cache directory (e.g. `~/.cache/keys` on Linux).
+pub fn default_cache_dir() -> Option<PathBuf> {
+    ProjectDirs::from("io.github", "danielemery", "keys").map(|dirs| dirs.cache_dir().to_path_buf())
+}
+
+impl CacheSettings {
+    /// The file caching the response to `url`. URLs are hashed rather than
+    /// escaped into the name, so long filter query strings still fit.
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(url.as_bytes())))
+    }
+
+    /// Cache the response `body`, served with `signature` and `validators`,
+    /// as the last good response to `url`.
+    pub fn store(
+        &self,
+        url: &str,
+        body: &str,
+        signature: Option<&str>,
+        validators: &Validators,
+    ) -> Result<()> {
+        let entry = CachedResponse {
+            url: url.to_string(),
+            fetched_at: Utc::now().timestamp(),
+            validators: validators.clone(),
+            body: body.to_string(),
+            signature: signature.map(str::to_string),
+        };
+        let contents = serde_json::to_vec_pretty(&entry).context("Failed to serialize response")?;
+        write_file_atomic(&self.path(url), &contents)
+    }
+
+    /// The cached response to `url`, or `None` if nothing is cached.
+    pub fn load(&self, url: &str) -> Result<Option<CachedResponse>> {
+        let path = self.path(url);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let entry: CachedResponse = read_entry(&path)?;
+        // Guard against a hash collision handing back another URL's response
+        if entry.url != url {
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    /// The file recording what `target` was last synced from.
+    fn target_path(&self, target: &str) -> PathBuf {
+        self.dir
+            .join("targets")
+            .join(format!("{:016x}.json", fnv1a(target.as_bytes())))
+    }
+
+    /// Record what a target was last synced from.
+    pub fn store_target(&self, state: &TargetState) -> Result<()> {
+        let contents =
+            serde_json::to_vec_pretty(state).context("Failed to serialize sync state")?;
+        write_file_atomic(&self.target_path(&state.target), &contents)
+    }
+
+    /// What `target` was last synced from, or `None` if it never has been.
+    pub fn load_target(&self, target: &str) -> Result<Option<TargetState>> {
+        let path = self.target_path(target);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let state: TargetState = read_entry(&path)?;
+        Ok(Some(state).filter(|state| state.target == target))
+    }
+}
+
+/// A hash of the contents of the file at `path`, or `None` if it can't be
+/// read.
+pub fn file_digest(path: &Path) -> Option<u64> {
+    fs::read(path).ok().map(|contents| fnv1a(&contents))
+}
+
+fn read_entry<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let contents = fs::read(path)
+        .with_context(|| format!("Failed to read cached response: {}", path.display()))?;
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("Failed to parse cached response: {}", path.display()))
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust releases,
+/// so cache file names don't change when the CLI is rebuilt.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A short human-readable duration, e.g. `45s`, `12m`, `3h 5m` or `2d 4h`.
+pub fn describe_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn settings(dir: &Path) -> CacheSettings {
+        CacheSettings {
+            dir: dir.to_path_buf(),
+            fallback: CacheFallback::WhenUnavailable,
+            max_age: Duration::from_secs(DEFAULT_CACHE_MAX_AGE_HOURS * 3600),
+        }
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = settings(&temp_dir.path().join("cache"));
+        let url = "https://keys.example.com/keys?user=alice";
+        let body = r#"{"version": "1.2.3", "keys": []}"#;
+
+        assert_eq!(cache.load(url).unwrap(), None);
+
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            version: Some("1.2.3".to_string()),
+            ..Validators::default()
+        };
+        cache
+            .store(url, body, Some("c2lnbmF0dXJl"), &validators)
+            .unwrap();
+        let entry = cache.load(url).unwrap().unwrap();
+        assert_eq!(entry.url, url);
+        assert_eq!(entry.validators, validators);
+        assert_eq!(entry.body, body);
+        assert_eq!(entry.signature.as_deref(), Some("c2lnbmF0dXJl"));
+        assert!(entry.age() < Duration::from_secs(60));
+
+        // Each query string is cached separately
+        assert_eq!(
+            cache
+                .load("https://keys.example.com/keys?user=bob")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_corrupt_entry() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = settings(temp_dir.path());
+        let url = "https://keys.example.com/keys";
+        fs::write(cache.path(url), "not json").unwrap();
+
+        let err = cache.load(url).unwrap_err();
+        assert!(err.to_string().contains("Failed to parse cached response"));
+    }
+
+    #[test]
+    fn test_store_and_load_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = settings(temp_dir.path());
+        let target = "ssh /home/alice/.ssh/authorized_keys (Managed)";
+        let state = TargetState {
+            target: target.to_string(),
+            versions: vec![(
+                "https://keys.example.com".to_string(),
+                Validators {
+                    etag: Some("\"v1\"".to_string()),
+                    ..Validators::default()
+                },
+            )],
+            digest: fnv1a(b"ssh-ed25519 AAAA alice@laptop\n"),
+        };
+
+        assert_eq!(cache.load_target(target).unwrap(), None);
+        cache.store_target(&state).unwrap();
+        assert_eq!(cache.load_target(target).unwrap(), Some(state));
+        assert_eq!(
+            cache
+                .load_target("ssh /home/alice/.ssh/authorized_keys (Force)")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_describe_age() {
+        assert_eq!(describe_age(Duration::from_secs(45)), "45s");
+        assert_eq!(describe_age(Duration::from_secs(720)), "12m");
+        assert_eq!(describe_age(Duration::from_secs(3 * 3600 + 300)), "3h 5m");
+        assert_eq!(
+            describe_age(Duration::from_secs(2 * 86400 + 4 * 3600)),
+            "2d 4h"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::commands::Server;
+use crate::commands::policy::KeyPolicy;
+use crate::commands::ssh_keys::{
+    KeyFilter, SSHKey, TagOptions, fetch_keys_from_servers, key_fingerprint, key_type,
+};
+
+/// How long `authorized-keys-command` waits for the server by default. sshd
+/// waits for the command without a timeout of its own, holding up the login.
+pub const DEFAULT_AUTHORIZED_KEYS_TIMEOUT_SECS: u64 = 5;
+
+/// The key a client offered, as sshd passes it to `AuthorizedKeysCommand`
+/// with the `%t` and `%f` tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferedKey {
+    /// The key type, e.g. `ssh-ed25519`.
+    pub key_type: String,
+    /// The key's fingerprint, e.g. `SHA256:KNrHOH...`.
+    pub fingerprint: String,
+}
+
+impl OfferedKey {
+    /// Whether the SSH key line `ssh_line` is this key.
+    fn matches(&self, ssh_line: &str) -> bool {
+        key_type(ssh_line).as_deref() == Some(self.key_type.as_str())
+            && key_fingerprint(ssh_line).as_deref() == Some(self.fingerprint.as_str())
+    }
+}
+
+/// Print `user`'s keys, in authorized_keys format with the options their tags
+/// map to in `tag_options`, for sshd's `AuthorizedKeysCommand`, narrowed down
+/// to `offered` if given. Keys that break `policy` are left out, or with a
+/// strict policy, none are printed.
+///
+/// Only key lines are ever printed to stdout, since sshd reads each line as a
+/// key. A failure prints nothing there, so sshd falls back to its other
+/// sources of keys, and is reported on stderr and to syslog, since sshd
+/// discards the command's stderr unless it is debugging.
+pub fn authorized_keys_command(
+    servers: &[Server],
+    user: &str,
+    filter: &KeyFilter,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    offered: Option<&OfferedKey>,
+) -> Result<()> {
+    let result = print_authorized_keys(servers, user, filter, tag_options, policy, offered);
+    if let Err(err) = &result {
+        log_to_syslog(&format!("failed to fetch keys for {user}: {err:#}"));
+    }
+    result
+}
+
+fn print_authorized_keys(
+    servers: &[Server],
+    user: &str,
+    filter: &KeyFilter,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    offered: Option<&OfferedKey>,
+) -> Result<()> {
+    let filter = KeyFilter {
+        users: vec![user.to_string()],
+        ..filter.clone()
+    };
+    // The server filters by user too, but an older server that ignores the
+    // query must not be able to grant another user's keys
+    let keys_response = filter.apply(&fetch_keys_from_servers(servers, &filter)?);
+    let keys_response = policy.enforce_ssh_keys(&keys_response)?;
+
+    let offered_keys: Vec<SSHKey> = keys_response
+        .keys
+        .into_iter()
+        .filter(|key| offered.is_none_or(|offered| offered.matches(&key.key)))
+        .collect();
+    let lines: String = tag_options
+        .server_lines(&offered_keys)
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(lines.as_bytes())
+        .and_then(|()| stdout.flush())
+        .context("Failed to print the keys")
+}
+
+/// Log `message` to syslog as an error in the authpriv facility, alongside
+/// sshd's own messages. Best effort: there may be no syslog daemon.
+#[cfg(unix)]
+fn log_to_syslog(message: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    // LOG_AUTHPRIV (10) << 3 | LOG_ERR (3)
+    const PRIORITY: u32 = (10 << 3) | 3;
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    let line = format!(
+        "<{PRIORITY}>keys[{}]: authorized-keys-command: {message}",
+        std::process::id()
+    );
+    let _ = socket.send_to(line.as_bytes(), "/dev/log");
+}
+
+#[cfg(not(unix))]
+fn log_to_syslog(_message: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offered_key_matches() {
+        let offered = OfferedKey {
+            key_type: "ssh-ed25519".to_string(),
+            fingerprint: "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys".to_string(),
+        };
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U";
+        assert!(offered.matches(key));
+        assert!(offered.matches(&format!("from=\"10.0.0.0/8\" {key} alice@laptop")));
+        assert!(!offered.matches("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExample"));
+
+        let wrong_type = OfferedKey {
+            key_type: "ssh-rsa".to_string(),
+            ..offered
+        };
+        assert!(!wrong_type.matches(key));
+    }
+}
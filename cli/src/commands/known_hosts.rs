@@ -1,27 +1,35 @@
-use std::io::IsTerminal;
+use std::path::Path;
 
 use anyhow::{Context, Result};
 use colored::Colorize;
-use reqwest::header::ACCEPT;
-use serde::Deserialize;
-
-use crate::utils::{ColumnConfig, backup_existing_file, pretty_print_table};
-
-#[derive(Debug, Deserialize)]
+use serde::{Deserialize, Serialize};
+
+use crate::commands::policy::KeyPolicy;
+use crate::commands::{
+    ChangeSummary, FingerprintHash, LastSync, OutputFormat, Server, ServerResponses, WriteMode,
+    WriteOptions, fetch_from_servers, managed_owner, report_conflicts, response_versions,
+};
+use crate::http::{Fetched, HttpClient};
+use crate::utils::{
+    ColumnConfig, ManagedFile, PublicKey, pretty_print_table, print_unified_diff,
+    replace_file_with_backup,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct KnownHostsResponse {
     pub version: String,
     #[serde(rename = "knownHosts")]
     pub hosts: Vec<KnownHost>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct KnownHost {
     pub name: Option<String>,
     pub hosts: Vec<String>,
     pub keys: Vec<HostKey>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HostKey {
     #[serde(rename = "type")]
     pub key_type: String,
@@ -30,10 +38,20 @@ pub struct HostKey {
     pub revoked: Option<bool>,
     #[serde(rename = "cert-authority")]
     pub cert_authority: Option<bool>,
+    /// The servers the key was fetched from, when several are merged
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
 }
 
-/// Function to pretty print the known hosts with formatted columns and colors
-pub fn pretty_print_known_hosts(response: &KnownHostsResponse) {
+/// Function to pretty print the known hosts with formatted columns and colors,
+/// showing each key's fingerprint with `fingerprint_hash`
+pub fn pretty_print_known_hosts(response: &KnownHostsResponse, fingerprint_hash: FingerprintHash) {
+    let fingerprints: Vec<Vec<String>> = response
+        .hosts
+        .iter()
+        .map(|host| host_key_fingerprints(host, fingerprint_hash))
+        .collect();
+
     // Find the maximum width for name and hosts columns for better formatting
     let max_name_len = response
         .hosts
@@ -72,7 +90,7 @@ pub fn pretty_print_known_hosts(response: &KnownHostsResponse) {
         .max(7); // "COMMENT" header is 7 chars
 
     // Define the columns
-    let columns = vec![
+    let mut columns = vec![
         ColumnConfig {
             header: "NAME".to_string(),
             color: |s| s.green(),
@@ -99,20 +117,50 @@ pub fn pretty_print_known_hosts(response: &KnownHostsResponse) {
             width: max_comment_len,
         },
         ColumnConfig {
-            header: "KEY".to_string(),
+            header: "FINGERPRINT".to_string(),
             color: |s| s.red(),
-            width: 50, // Key is typically long, so use a reasonable default width
+            width: fingerprints
+                .iter()
+                .flatten()
+                .map(String::len)
+                .max()
+                .unwrap_or(11)
+                .max(11),
         },
     ];
 
+    // Show where each key came from when several servers were merged
+    let merged = response
+        .hosts
+        .iter()
+        .flat_map(|h| h.keys.iter())
+        .any(|k| !k.sources.is_empty());
+    if merged {
+        columns.insert(
+            5,
+            ColumnConfig {
+                header: "SOURCE".to_string(),
+                color: |s| s.cyan(),
+                width: response
+                    .hosts
+                    .iter()
+                    .flat_map(|h| h.keys.iter())
+                    .map(|k| k.sources.join(", ").len())
+                    .max()
+                    .unwrap_or(6)
+                    .max(6),
+            },
+        );
+    }
+
     // Prepare the rows - flattening the nested structure
     let mut rows: Vec<Vec<String>> = Vec::new();
 
-    for host in &response.hosts {
+    for (host, fingerprints) in response.hosts.iter().zip(fingerprints) {
         let name = host.name.clone().unwrap_or_default();
         let hosts_str = host.hosts.join(",");
 
-        for key in &host.keys {
+        for (key, fingerprint) in host.keys.iter().zip(fingerprints) {
             // Create flags string based on boolean values
             let mut flags = Vec::new();
             if key.revoked.unwrap_or(false) {
@@ -126,14 +174,18 @@ pub fn pretty_print_known_hosts(response: &KnownHostsResponse) {
             // Get comment or empty string
             let comment = key.comment.clone().unwrap_or_default();
 
-            rows.push(vec![
+            let mut row = vec![
                 name.clone(),
                 hosts_str.clone(),
                 key.key_type.clone(),
                 flags_str,
                 comment,
-                key.key.clone(),
-            ]);
+            ];
+            if merged {
+                row.push(key.sources.join(", "));
+            }
+            row.push(fingerprint);
+            rows.push(row);
         }
     }
 
@@ -149,42 +201,23 @@ pub fn pretty_print_known_hosts(response: &KnownHostsResponse) {
 
 /// Private function to fetch known hosts from the server
 ///
-/// This function handles the HTTP request to the known hosts server,
-/// validates the response, and parses the JSON into a KnownHostsResponse.
+/// This function requests the known hosts through the shared HTTP client,
+/// then validates the parsed KnownHostsResponse.
 ///
 /// # Arguments
+/// * `client` - The HTTP client to send the request with
 /// * `server_url` - The base URL of the keys server
 ///
 /// # Returns
-/// * `Result<KnownHostsResponse>` - The parsed known hosts response or an error
-fn fetch_known_hosts_from_server(server_url: &str) -> Result<KnownHostsResponse> {
+/// * `Result<Fetched<KnownHostsResponse>>` - The parsed known hosts
+///   response, with the validators it was served with, or an error
+pub(crate) fn fetch_known_hosts_from_server(
+    client: &HttpClient,
+    server_url: &str,
+) -> Result<Fetched<KnownHostsResponse>> {
     let url = format!("{server_url}/known_hosts");
 
-    let client = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .context("Failed to build HTTP client")?;
-    let response = client
-        .get(&url)
-        .header(ACCEPT, "application/json")
-        .send()
-        .context("Failed to send request to known hosts server")?;
-
-    let status = response.status();
-
-    if !status.is_success() {
-        return Err(anyhow::anyhow!(
-            "Server returned error code: {} - {}",
-            status.as_u16(),
-            status.canonical_reason().unwrap_or("Unknown")
-        ));
-    }
-
-    let known_hosts_response = response
-        .json::<KnownHostsResponse>()
-        .context("Failed to parse JSON response")?;
-    validate_known_hosts_response(&known_hosts_response)?;
-    Ok(known_hosts_response)
+    client.fetch_json_versioned(&url, &[], validate_known_hosts_response)
 }
 
 /// Reject server entries that would produce malformed or forged known_hosts
@@ -230,18 +263,194 @@ fn validate_known_hosts_response(response: &KnownHostsResponse) -> Result<()> {
                     "Server known_hosts entry has a comment with an illegal line break: {comment:?}"
                 ));
             }
+
+            PublicKey::from_parts(&key.key_type, &key.key).with_context(|| {
+                format!(
+                    "Server returned an invalid {} key for known host {}",
+                    key.key_type,
+                    host.name.as_deref().unwrap_or(&host.hosts[0])
+                )
+            })?;
         }
     }
 
     Ok(())
 }
 
-pub fn fetch_known_hosts(server_url: &str) -> Result<()> {
-    let known_hosts_response = fetch_known_hosts_from_server(server_url)?;
+/// Fetch known hosts from every server in `servers` and merge them.
+///
+/// With a single server this is just [`fetch_known_hosts_from_server`]. With
+/// several, every server must respond, so one being down can't silently drop
+/// its entries from a forced write; see [`merge_known_hosts_responses`] for
+/// how they combine.
+pub(crate) fn fetch_known_hosts_from_servers(servers: &[Server]) -> Result<KnownHostsResponse> {
+    Ok(merge_fetched_known_hosts(&fetch_known_hosts_responses(
+        servers,
+    )?))
+}
+
+/// Fetch known hosts from every server in `servers`, without merging them
+/// yet.
+pub(crate) fn fetch_known_hosts_responses(
+    servers: &[Server],
+) -> Result<ServerResponses<KnownHostsResponse>> {
+    fetch_from_servers(servers, "known hosts", fetch_known_hosts_from_server)
+}
+
+/// Merge the known hosts fetched from each server, reporting any conflicts.
+/// A single server's response is used as is.
+pub(crate) fn merge_fetched_known_hosts(
+    responses: &ServerResponses<KnownHostsResponse>,
+) -> KnownHostsResponse {
+    if let [(_, fetched)] = responses.as_slice() {
+        return fetched.response.clone();
+    }
+
+    let responses: Vec<(&str, KnownHostsResponse)> = responses
+        .iter()
+        .map(|(url, fetched)| (url.as_str(), fetched.response.clone()))
+        .collect();
+    let (known_hosts_response, conflicts) = merge_known_hosts_responses(&responses);
+    report_conflicts(&conflicts);
+    known_hosts_response
+}
+
+/// Merge the known hosts served by several servers into one response,
+/// recording on each key the servers it came from.
+///
+/// Entries are matched by their host patterns, key type and key. An entry
+/// served by more than one server is kept once: if the servers disagree on
+/// whether it is `@revoked` it is treated as revoked, and if they disagree on
+/// whether it is a `@cert-authority` it is treated as not being one, so a
+/// merge never trusts a key more than every server does. Each disagreement,
+/// and a key revoked for a host pattern on one server but trusted for the same
+/// pattern on another, is reported as a conflict.
+fn merge_known_hosts_responses(
+    responses: &[(&str, KnownHostsResponse)],
+) -> (KnownHostsResponse, Vec<String>) {
+    let mut hosts: Vec<KnownHost> = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for (source, response) in responses {
+        for host in &response.hosts {
+            for key in &host.keys {
+                let identity = server_entry_identity(host, key);
+                let revoked = key.revoked.unwrap_or(false);
+
+                let existing = hosts.iter_mut().find_map(|h| {
+                    let hosts_str = h.hosts.join(",");
+                    h.keys
+                        .iter_mut()
+                        .find(|k| format!("{hosts_str} {} {}", k.key_type, k.key) == identity)
+                });
+                if let Some(merged) = existing {
+                    if merged.revoked.unwrap_or(false) != revoked {
+                        let (revoked_on, trusted_on) = if revoked {
+                            (source.to_string(), merged.sources.join(", "))
+                        } else {
+                            (merged.sources.join(", "), source.to_string())
+                        };
+                        conflicts.push(format!(
+                            "{identity} is revoked on {revoked_on} but not on {trusted_on}; treating it as revoked"
+                        ));
+                        merged.revoked = Some(true);
+                    }
+                    if merged.cert_authority.unwrap_or(false) != key.cert_authority.unwrap_or(false)
+                    {
+                        conflicts.push(format!(
+                            "{identity} is a cert authority on only some servers ({} and {source} disagree); treating it as not one",
+                            merged.sources.join(", ")
+                        ));
+                        merged.cert_authority = Some(false);
+                    }
+                    if !merged.sources.iter().any(|s| s == source) {
+                        merged.sources.push(source.to_string());
+                    }
+                    continue;
+                }
+
+                // A key revoked for a pattern another server trusts it for (or
+                // the reverse) ends up on separate lines, which OpenSSH
+                // resolves in favour of the revocation, but is still worth
+                // reporting.
+                for other in &hosts {
+                    let shares_pattern = other.hosts.iter().any(|p| host.hosts.contains(p));
+                    let disagrees = other.keys.iter().find(|k| {
+                        k.key_type == key.key_type
+                            && k.key == key.key
+                            && k.revoked.unwrap_or(false) != revoked
+                    });
+                    if shares_pattern && let Some(other_key) = disagrees {
+                        let (revoked_on, trusted_on) = if revoked {
+                            (source.to_string(), other_key.sources.join(", "))
+                        } else {
+                            (other_key.sources.join(", "), source.to_string())
+                        };
+                        conflicts.push(format!(
+                            "{} {} for {} is revoked on {revoked_on} but not on {trusted_on}",
+                            key.key_type,
+                            key.key,
+                            other.hosts.join(",")
+                        ));
+                    }
+                }
+
+                let key = HostKey {
+                    sources: vec![source.to_string()],
+                    ..key.clone()
+                };
+                match hosts
+                    .iter_mut()
+                    .find(|h| h.hosts == host.hosts && h.name == host.name)
+                {
+                    Some(merged_host) => merged_host.keys.push(key),
+                    None => hosts.push(KnownHost {
+                        keys: vec![key],
+                        ..host.clone()
+                    }),
+                }
+            }
+        }
+    }
+
+    let version = responses
+        .iter()
+        .map(|(source, response)| format!("{} ({source})", response.version))
+        .collect::<Vec<_>>()
+        .join(", ");
+    (KnownHostsResponse { version, hosts }, conflicts)
+}
+
+pub fn fetch_known_hosts(
+    servers: &[Server],
+    output: OutputFormat,
+    fingerprint_hash: FingerprintHash,
+) -> Result<()> {
+    let known_hosts_response = fetch_known_hosts_from_servers(servers)?;
+    let listed = ListedHosts {
+        version: &known_hosts_response.version,
+        hosts: known_hosts_response
+            .hosts
+            .iter()
+            .map(|host| ListedHost {
+                name: &host.name,
+                hosts: &host.hosts,
+                keys: host
+                    .keys
+                    .iter()
+                    .zip(host_key_fingerprints(host, fingerprint_hash))
+                    .map(|(key, fingerprint)| ListedHostKey { key, fingerprint })
+                    .collect(),
+            })
+            .collect(),
+    };
+    if output.print_structured(&listed)? {
+        return Ok(());
+    }
 
-    // Check if the output is being piped (not connected to a terminal)
-    // Use raw/minimal output when piped to another command
-    if !std::io::stdout().is_terminal() {
+    // Use raw/minimal output when piped to another command (or when asked
+    // to), and a table otherwise
+    if !output.is_table() {
         for host in &known_hosts_response.hosts {
             for key in &host.keys {
                 // Reuse the shared formatter so the piped output matches what
@@ -253,11 +462,42 @@ pub fn fetch_known_hosts(server_url: &str) -> Result<()> {
     }
 
     // Use the pretty print function for interactive terminal output
-    pretty_print_known_hosts(&known_hosts_response);
+    pretty_print_known_hosts(&known_hosts_response, fingerprint_hash);
 
     Ok(())
 }
 
+/// The fingerprint of each of `host`'s keys, with `fingerprint_hash`.
+fn host_key_fingerprints(host: &KnownHost, fingerprint_hash: FingerprintHash) -> Vec<String> {
+    host.keys
+        .iter()
+        .map(|key| fingerprint_hash.fingerprint(PublicKey::from_parts(&key.key_type, &key.key)))
+        .collect()
+}
+
+/// The known hosts as `--output json` and `yaml` print them, each key with
+/// its fingerprint.
+#[derive(Serialize)]
+struct ListedHosts<'a> {
+    version: &'a str,
+    #[serde(rename = "knownHosts")]
+    hosts: Vec<ListedHost<'a>>,
+}
+
+#[derive(Serialize)]
+struct ListedHost<'a> {
+    name: &'a Option<String>,
+    hosts: &'a [String],
+    keys: Vec<ListedHostKey<'a>>,
+}
+
+#[derive(Serialize)]
+struct ListedHostKey<'a> {
+    #[serde(flatten)]
+    key: &'a HostKey,
+    fingerprint: String,
+}
+
 /// Helper function to format a host entry in known_hosts format
 fn format_known_hosts_line(host: &KnownHost, key: &HostKey) -> String {
     let hosts_str = host.hosts.join(",");
@@ -270,11 +510,14 @@ fn format_known_hosts_line(host: &KnownHost, key: &HostKey) -> String {
     // trusted).
     let marker = marker_for(key);
 
-    // Format comment if present
-    let comment_str = if let Some(comment) = &key.comment {
-        format!(" # {comment}")
-    } else {
-        String::new()
+    // Format comment if present, noting which servers the key came from when
+    // several were merged
+    let sources = (!key.sources.is_empty()).then(|| format!("from {}", key.sources.join(", ")));
+    let comment_str = match (&key.comment, sources) {
+        (Some(comment), Some(sources)) => format!(" # {comment} ({sources})"),
+        (Some(comment), None) => format!(" # {comment}"),
+        (None, Some(sources)) => format!(" # {sources}"),
+        (None, None) => String::new(),
     };
 
     // Output in OpenSSH known_hosts format with marker and optional comment
@@ -317,34 +560,70 @@ fn extract_known_hosts_identity(line: &str) -> Option<String> {
     Some(format!("{hosts} {key_type} {key}"))
 }
 
-pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Result<()> {
-    // Fetch known hosts from the server
-    let known_hosts_response = fetch_known_hosts_from_server(server_url)?;
+pub fn write_known_hosts(
+    servers: &[Server],
+    file_path: &str,
+    policy: &KeyPolicy,
+    options: &WriteOptions,
+) -> Result<()> {
+    // Fetch known hosts from the servers, and stop there if nothing has
+    // changed since the file was last written
+    let responses = fetch_known_hosts_responses(servers)?;
+    let versions = response_versions(&responses);
+    let last_sync = LastSync::new(
+        servers,
+        file_path,
+        &format!("known-hosts {:?} {policy:?}", options.mode),
+    );
+    if last_sync.unchanged(&versions) {
+        return Ok(());
+    }
+
+    apply_known_hosts(
+        &merge_fetched_known_hosts(&responses),
+        &managed_owner(servers),
+        file_path,
+        policy,
+        options,
+    )?;
+    if !options.dry_run {
+        last_sync.record(versions);
+    }
+    Ok(())
+}
+
+/// Write already-fetched known hosts to a file according to `options`, with
+/// `owner` (see [`managed_owner`]) owning the managed block, skipping keys
+/// that break `policy` or aborting the write. Split out from
+/// [`write_known_hosts`] so `keys sync` can apply a single fetch to several
+/// files.
+pub(crate) fn apply_known_hosts(
+    known_hosts_response: &KnownHostsResponse,
+    owner: &str,
+    file_path: &str,
+    policy: &KeyPolicy,
+    options: &WriteOptions,
+) -> Result<()> {
+    let known_hosts_response = &policy.enforce_known_hosts(known_hosts_response)?;
+    let force = options.mode == WriteMode::Force;
 
     // Expand ~ to home directory if present
     let expanded_path = shellexpand::tilde(file_path);
-    let path = std::path::Path::new(expanded_path.as_ref());
+    let path = Path::new(expanded_path.as_ref());
 
     // Read existing entries if the file exists, skipping blank and comment-only
     // lines so they don't get treated as host entries.
-    let existing_lines = if path.exists() {
+    let existing_content = if path.exists() {
         std::fs::read_to_string(path)
             .with_context(|| format!("Failed to read existing file: {}", path.display()))?
-            .lines()
-            .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>()
     } else {
-        Vec::new()
+        String::new()
     };
-
-    // Create directory if it doesn't exist
-    if let Some(parent) = path.parent()
-        && !parent.exists()
-    {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
-    }
+    let existing_lines = existing_content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
 
     // Flatten server entries into (identity, formatted line) pairs.
     let server_entries: Vec<(String, String)> = known_hosts_response
@@ -361,6 +640,10 @@ pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Resu
         .collect();
     let num_server_entries = server_entries.len();
 
+    if options.mode == WriteMode::Managed {
+        return write_managed_known_hosts(path, &existing_content, &server_entries, owner, options);
+    }
+
     // Identity of each existing line (None if it can't be parsed).
     let existing_identities: Vec<Option<String>> = existing_lines
         .iter()
@@ -374,11 +657,15 @@ pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Resu
             .any(|id| id.as_deref() == Some(identity))
     };
 
-    // Entries present locally but absent from the server (reported in safe mode).
-    let num_local_only = existing_identities
+    // Entries present locally but absent from the server (reported in safe
+    // mode, removed in force mode).
+    let local_only_lines: Vec<&str> = existing_lines
         .iter()
-        .filter(|id| id.as_deref().map(|i| !is_on_server(i)).unwrap_or(true))
-        .count();
+        .zip(&existing_identities)
+        .filter(|(_, id)| id.as_deref().map(|i| !is_on_server(i)).unwrap_or(true))
+        .map(|(line, _)| line.as_str())
+        .collect();
+    let num_local_only = local_only_lines.len();
 
     let mut updated_count = 0;
 
@@ -424,14 +711,46 @@ pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Resu
         format!("{file_content}\n")
     };
 
-    // Back up the existing file before overwriting it, so a bad merge or a
-    // surprising server response can be recovered from.
-    if let Some(backup) = backup_existing_file(path)? {
-        println!("📦 Backed up existing file to {}", backup.display());
+    let num_added = server_entries
+        .iter()
+        .filter(|(identity, _)| !is_present_locally(identity))
+        .count();
+
+    let change = ChangeSummary {
+        existing: existing_lines.len(),
+        remaining: file_content.lines().count(),
+        added: num_added,
+        removed: if force { local_only_lines } else { Vec::new() },
+    };
+
+    // In dry-run mode, show what would change and stop before touching disk.
+    // The change limits are still checked, so a dry run reports a write that
+    // would be refused.
+    if options.dry_run {
+        print_unified_diff(path, &existing_content, &file_content);
+    }
+    options.limits.check(path, "entries", &change)?;
+    if options.dry_run {
+        if force {
+            println!(
+                "🔍 Dry run: would write {} known host entries to {} (removing {} local entries)",
+                num_server_entries,
+                path.display(),
+                num_local_only
+            );
+        } else {
+            println!(
+                "🔍 Dry run: would add {} new known host entries to {} and update {} (keeping {} local entries)",
+                num_added,
+                path.display(),
+                updated_count,
+                num_local_only
+            );
+        }
+        return Ok(());
     }
 
-    std::fs::write(path, &file_content)
-        .with_context(|| format!("Failed to write to file: {}", path.display()))?;
+    replace_file_with_backup(path, file_content.as_bytes(), options.backup_retention)?;
 
     // Report what happened.
     let num_existing = existing_lines.len();
@@ -443,11 +762,6 @@ pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Resu
             num_existing
         );
     } else {
-        let num_added = server_entries
-            .iter()
-            .filter(|(identity, _)| !is_present_locally(identity))
-            .count();
-
         if num_added > 0 {
             let mut message = format!(
                 "✅ Added {} new known host entries to {}",
@@ -482,11 +796,160 @@ pub fn write_known_hosts(server_url: &str, file_path: &str, force: bool) -> Resu
     Ok(())
 }
 
+/// Write the server's entries into the managed block `owner` owns in a
+/// known_hosts file, so the block exactly mirrors the server: entries removed
+/// from the server disappear, and newly `@revoked` keys are rewritten with
+/// their marker.
+///
+/// Lines outside the block, including comments, blank lines, locally learned
+/// hosts, and blocks owned by other servers, are preserved verbatim. Local
+/// lines that duplicate a managed entry are reported, since they would keep
+/// trusting a key even after it is removed from the server.
+fn write_managed_known_hosts(
+    path: &Path,
+    existing_content: &str,
+    server_entries: &[(String, String)],
+    owner: &str,
+    options: &WriteOptions,
+) -> Result<()> {
+    let managed_file = ManagedFile::parse(existing_content, owner)
+        .with_context(|| format!("Failed to parse managed block in {}", path.display()))?;
+
+    let is_host_entry = |line: &&String| !line.trim().is_empty() && !line.trim().starts_with('#');
+
+    // Previous block entries, as (identity, line) pairs.
+    let previous_entries: Vec<(Option<String>, &String)> = managed_file
+        .managed_lines()
+        .iter()
+        .filter(is_host_entry)
+        .map(|line| (extract_known_hosts_identity(line), line))
+        .collect();
+    let was_managed = |identity: &str| {
+        previous_entries
+            .iter()
+            .any(|(id, _)| id.as_deref() == Some(identity))
+    };
+
+    let num_added = server_entries
+        .iter()
+        .filter(|(identity, _)| !was_managed(identity))
+        .count();
+    let num_updated = server_entries
+        .iter()
+        .filter(|(identity, line)| {
+            previous_entries
+                .iter()
+                .any(|(id, previous)| id.as_deref() == Some(identity) && *previous != line)
+        })
+        .count();
+    let removed_lines: Vec<&str> = previous_entries
+        .iter()
+        .filter(|(id, _)| {
+            id.as_deref()
+                .map(|id| !server_entries.iter().any(|(sid, _)| sid == id))
+                .unwrap_or(true)
+        })
+        .map(|(_, line)| line.as_str())
+        .collect();
+    let num_removed = removed_lines.len();
+
+    let unmanaged_entries: Vec<&String> = managed_file
+        .unmanaged_lines()
+        .filter(is_host_entry)
+        .collect();
+    let duplicates: Vec<&String> = unmanaged_entries
+        .iter()
+        .copied()
+        .filter(|line| {
+            extract_known_hosts_identity(line)
+                .map(|id| server_entries.iter().any(|(sid, _)| *sid == id))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    let block_lines: Vec<String> = server_entries
+        .iter()
+        .map(|(_, line)| line.clone())
+        .collect();
+    let file_content = managed_file.render(&block_lines);
+
+    if options.dry_run {
+        print_unified_diff(path, existing_content, &file_content);
+    }
+    let change = ChangeSummary {
+        existing: previous_entries.len() + unmanaged_entries.len(),
+        remaining: server_entries.len() + unmanaged_entries.len(),
+        added: num_added,
+        removed: removed_lines,
+    };
+    options.limits.check(path, "entries", &change)?;
+    if options.dry_run {
+        println!(
+            "🔍 Dry run: would add {}, update {} and remove {} entries in the managed block of {} (leaving {} local entries untouched)",
+            num_added,
+            num_updated,
+            num_removed,
+            path.display(),
+            unmanaged_entries.len()
+        );
+    } else {
+        replace_file_with_backup(path, file_content.as_bytes(), options.backup_retention)?;
+
+        println!(
+            "✅ Synced managed block in {} ({} entries: {} added, {} updated, {} removed; {} local entries untouched)",
+            path.display(),
+            server_entries.len(),
+            num_added,
+            num_updated,
+            num_removed,
+            unmanaged_entries.len()
+        );
+    }
+
+    if !duplicates.is_empty() {
+        println!(
+            "{}  {} local entries outside the managed block duplicate a server entry and will outlive its removal:",
+            "⚠️".yellow().bold(),
+            duplicates.len().to_string().yellow().bold()
+        );
+        for line in duplicates {
+            println!("   {}", line.yellow());
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::list_backups;
     use mockito;
 
+    // Key data for the host keys these tests serve and write: well-formed key
+    // blobs, as keys from the server are checked, but not real keys.
+    const OLD_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEGdMcTbElaNJnzI9ZJt9CKQ=";
+    const NEW_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEEGfBU2RLW6jHjAbd9ROPzc=";
+    const GITHUB_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEGISdDqpcbkt0NFW513yeqI=";
+    const GITLAB_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEFkBsd/+ROrU3F6vhPYPMo4=";
+    const REVOKED_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHHNdr5w4N3ZKTzLboOEBuc=";
+    const CA_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEEnvdpj6n1SRVu8Y5W5pkw4=";
+    const A_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHNLjRYz3dwk3/5j4RFQXG0=";
+    const B_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEErsZv21tt6UndSIJHW4Lc0=";
+    const EXAMPLE_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEEUb40q1eZ3TXhPwh+r3lFg=";
+    const GITHUB_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAILF8dQC2y/5bhasUEMJxSYJ/CXj/E7r/04420K2h6vrx";
+    const NEW_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIPK4QzB4VyT8z8jlIVwWNuclrP1/qH0TqjI4sYFRchp5";
+    const CA_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAICfovnu7ZVWCARix9mAmmrTTWqyCMEtG0Sct6cb5xRKn";
+    const DB_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIFM1rXEMSRuQAPM4Eg7H94F/TgBWevGbOn0A9mlDNt9B";
+    const LAPTOP_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIKZPe/Ev1qS4F1tTSUtMjrA+1eYXrFhqZT6w8Q4lDuqf";
+    const BASTION_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIE5mue7/LDZS9OsWIG3aTCI3H+bnzbyeaDMzD+5Dvdkh";
+    const EXAMPLE_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIJ12QRz3PjJwV/VXkMMabyZaulBlf3DIHjKHg78ftd6t";
+
     // Helper function to create a mock server
     fn setup_mock_server(response_body: &str) -> (String, mockito::ServerGuard) {
         let mut mock_server = mockito::Server::new();
@@ -521,31 +984,37 @@ mod tests {
     #[test]
     fn test_fetch_known_hosts_success() {
         // Setup mock server with known hosts response
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "name": "GitHub",
                     "hosts": ["github.com", "*.github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7",
+                            "key": "{GITHUB_KEY}",
                             "comment": "GitHub RSA key",
                             "revoked": false,
                             "cert-authority": false
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server::new(&server_url)],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
         assert!(
             result.is_ok(),
             "fetch_known_hosts failed: {:?}",
@@ -560,7 +1029,14 @@ mod tests {
             setup_mock_server_with_error(500, r#"{"error": "Internal server error"}"#);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server {
+                retries: 0,
+                ..Server::new(&server_url)
+            }],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
 
         // Should return an error
         assert!(result.is_err());
@@ -575,7 +1051,11 @@ mod tests {
             setup_mock_server(r#"{"version": "1.0.0", "knownHosts": [{"incomplete": true}]}"#);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server::new(&server_url)],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
 
         // Should return an error due to missing required fields
         assert!(result.is_err());
@@ -594,7 +1074,11 @@ mod tests {
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server::new(&server_url)],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
         assert!(
             result.is_ok(),
             "fetch_known_hosts failed: {:?}",
@@ -605,56 +1089,62 @@ mod tests {
     #[test]
     fn test_fetch_known_hosts_multiple_hosts_and_keys() {
         // Setup mock server with multiple hosts and keys
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "2.1.0",
             "knownHosts": [
-                {
+                {{
                     "name": "GitHub",
                     "hosts": ["github.com", "*.github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7GitHub1",
+                            "key": "{GITHUB_KEY}",
                             "comment": "GitHub RSA key"
-                        },
-                        {
+                        }},
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5AAAAIGitHub2",
+                            "key": "{GITHUB_ED25519_KEY}",
                             "comment": "GitHub Ed25519 key"
-                        }
+                        }}
                     ]
-                },
-                {
+                }},
+                {{
                     "name": "GitLab",
                     "hosts": ["gitlab.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7GitLab1",
+                            "key": "{GITLAB_KEY}",
                             "comment": "GitLab RSA key",
                             "revoked": true
-                        }
+                        }}
                     ]
-                },
-                {
+                }},
+                {{
                     "hosts": ["example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5AAAAIExample1",
+                            "key": "{EXAMPLE_ED25519_KEY}",
                             "cert-authority": true
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server::new(&server_url)],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
         assert!(
             result.is_ok(),
             "fetch_known_hosts failed: {:?}",
@@ -665,45 +1155,51 @@ mod tests {
     #[test]
     fn test_fetch_known_hosts_with_flags() {
         // Setup mock server with keys that have revoked and cert-authority flags
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "name": "Test Host",
                     "hosts": ["test.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Revoked",
+                            "key": "{REVOKED_KEY}",
                             "comment": "Revoked key",
                             "revoked": true,
                             "cert-authority": false
-                        },
-                        {
+                        }},
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5AAAAICertAuth",
+                            "key": "{CA_ED25519_KEY}",
                             "comment": "CA key",
                             "revoked": false,
                             "cert-authority": true
-                        },
-                        {
+                        }},
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Both",
+                            "key": "{CA_KEY}",
                             "comment": "Both flags",
                             "revoked": true,
                             "cert-authority": true
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_known_hosts(&server_url);
+        let result = fetch_known_hosts(
+            &[Server::new(&server_url)],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
         assert!(
             result.is_ok(),
             "fetch_known_hosts failed: {:?}",
@@ -723,17 +1219,19 @@ mod tests {
                     keys: vec![
                         HostKey {
                             key_type: "ssh-rsa".to_string(),
-                            key: "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7GitHub".to_string(),
+                            key: GITHUB_KEY.to_string(),
                             comment: Some("GitHub RSA key".to_string()),
                             revoked: Some(false),
                             cert_authority: Some(false),
+                            sources: Vec::new(),
                         },
                         HostKey {
                             key_type: "ssh-ed25519".to_string(),
-                            key: "AAAAC3NzaC1lZDI1NTE5AAAAIGitHub2".to_string(),
+                            key: GITHUB_ED25519_KEY.to_string(),
                             comment: None,
                             revoked: None,
                             cert_authority: Some(true),
+                            sources: Vec::new(),
                         },
                     ],
                 },
@@ -742,10 +1240,11 @@ mod tests {
                     hosts: vec!["example.com".to_string()],
                     keys: vec![HostKey {
                         key_type: "ssh-rsa".to_string(),
-                        key: "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Example".to_string(),
+                        key: EXAMPLE_KEY.to_string(),
                         comment: Some("Example key".to_string()),
                         revoked: Some(true),
                         cert_authority: Some(false),
+                        sources: Vec::new(),
                     }],
                 },
             ],
@@ -754,7 +1253,7 @@ mod tests {
         // This test primarily verifies the function doesn't panic and handles the data correctly
         // Since pretty_print_known_hosts outputs to stdout, we can't easily capture and verify output
         // in this test environment, but we can verify it completes without errors
-        pretty_print_known_hosts(&known_hosts_response);
+        pretty_print_known_hosts(&known_hosts_response, FingerprintHash::Sha256);
     }
 
     #[test]
@@ -766,7 +1265,7 @@ mod tests {
         };
 
         // Should handle empty hosts gracefully
-        pretty_print_known_hosts(&known_hosts_response);
+        pretty_print_known_hosts(&known_hosts_response, FingerprintHash::Sha256);
     }
 
     #[test]
@@ -780,10 +1279,11 @@ mod tests {
                     hosts: vec!["host1.example.com".to_string()],
                     keys: vec![HostKey {
                         key_type: "ssh-rsa".to_string(),
-                        key: "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Host1".to_string(),
+                        key: EXAMPLE_KEY.to_string(),
                         comment: None,
                         revoked: None,
                         cert_authority: None,
+                        sources: Vec::new(),
                     }],
                 },
                 KnownHost {
@@ -791,17 +1291,18 @@ mod tests {
                     hosts: vec!["host2.example.com".to_string()],
                     keys: vec![HostKey {
                         key_type: "ssh-ed25519".to_string(),
-                        key: "AAAAC3NzaC1lZDI1NTE5AAAAIHost2".to_string(),
+                        key: EXAMPLE_ED25519_KEY.to_string(),
                         comment: None,
                         revoked: None,
                         cert_authority: None,
+                        sources: Vec::new(),
                     }],
                 },
             ],
         };
 
         // Should handle missing names gracefully
-        pretty_print_known_hosts(&known_hosts_response);
+        pretty_print_known_hosts(&known_hosts_response, FingerprintHash::Sha256);
     }
 
     #[test]
@@ -820,10 +1321,11 @@ mod tests {
                     keys: vec![
                         HostKey {
                             key_type: "ssh-rsa".to_string(),
-                            key: "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7VeryLongKeyDataThatShouldTestTheKeyColumnWidthHandling".to_string(),
+                            key: EXAMPLE_KEY.to_string(),
                             comment: Some("This is a very long comment that should test the comment column width handling and make sure everything aligns properly".to_string()),
                             revoked: Some(true),
                             cert_authority: Some(true),
+                            sources: Vec::new(),
                         }
                     ],
                 }
@@ -831,32 +1333,34 @@ mod tests {
         };
 
         // Should handle long data gracefully
-        pretty_print_known_hosts(&known_hosts_response);
+        pretty_print_known_hosts(&known_hosts_response, FingerprintHash::Sha256);
     }
 
     #[test]
     fn test_deserialize_known_hosts_response() {
         // Test JSON deserialization
-        let json_data = r#"
-        {
+        let json_data = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "name": "Test",
                     "hosts": ["test.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Test",
+                            "key": "{EXAMPLE_KEY}",
                             "comment": "Test comment",
                             "revoked": true,
                             "cert-authority": false
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let result: Result<KnownHostsResponse, _> = serde_json::from_str(json_data);
         assert!(result.is_ok());
@@ -868,10 +1372,7 @@ mod tests {
         assert_eq!(response.hosts[0].hosts, vec!["test.com"]);
         assert_eq!(response.hosts[0].keys.len(), 1);
         assert_eq!(response.hosts[0].keys[0].key_type, "ssh-rsa");
-        assert_eq!(
-            response.hosts[0].keys[0].key,
-            "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7Test"
-        );
+        assert_eq!(response.hosts[0].keys[0].key, EXAMPLE_KEY);
         assert_eq!(
             response.hosts[0].keys[0].comment,
             Some("Test comment".to_string())
@@ -883,22 +1384,24 @@ mod tests {
     #[test]
     fn test_deserialize_known_hosts_response_minimal() {
         // Test JSON deserialization with minimal required fields
-        let json_data = r#"
-        {
+        let json_data = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["minimal.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5AAAAIMinimal"
-                        }
+                            "key": "{EXAMPLE_ED25519_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let result: Result<KnownHostsResponse, _> = serde_json::from_str(json_data);
         assert!(result.is_ok());
@@ -910,10 +1413,7 @@ mod tests {
         assert_eq!(response.hosts[0].hosts, vec!["minimal.com"]);
         assert_eq!(response.hosts[0].keys.len(), 1);
         assert_eq!(response.hosts[0].keys[0].key_type, "ssh-ed25519");
-        assert_eq!(
-            response.hosts[0].keys[0].key,
-            "AAAAC3NzaC1lZDI1NTE5AAAAIMinimal"
-        );
+        assert_eq!(response.hosts[0].keys[0].key, EXAMPLE_ED25519_KEY);
         assert_eq!(response.hosts[0].keys[0].comment, None);
         assert_eq!(response.hosts[0].keys[0].revoked, None);
         assert_eq!(response.hosts[0].keys[0].cert_authority, None);
@@ -945,7 +1445,14 @@ mod tests {
     #[test]
     fn test_fetch_known_hosts_network_error() {
         // Test with invalid URL to simulate network error
-        let result = fetch_known_hosts("http://invalid-url-that-does-not-exist.local");
+        let result = fetch_known_hosts(
+            &[Server {
+                retries: 0,
+                ..Server::new("http://invalid-url-that-does-not-exist.local")
+            }],
+            OutputFormat::Auto,
+            FingerprintHash::Sha256,
+        );
         assert!(result.is_err());
     }
 
@@ -958,15 +1465,16 @@ mod tests {
             hosts: vec!["github.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2EAAAADAQABAAAB".to_string(),
+                key: GITHUB_KEY.to_string(),
                 comment: None,
                 revoked: None,
                 cert_authority: None,
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
-        assert_eq!(result, "github.com ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAB");
+        assert_eq!(result, format!("github.com ssh-rsa {GITHUB_KEY}"));
     }
 
     #[test]
@@ -980,17 +1488,18 @@ mod tests {
             ],
             keys: vec![HostKey {
                 key_type: "ssh-ed25519".to_string(),
-                key: "AAAAC3NzaC1lZDI1NTE5".to_string(),
+                key: GITHUB_ED25519_KEY.to_string(),
                 comment: None,
                 revoked: None,
                 cert_authority: None,
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "github.com,*.github.com,192.30.255.112 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"
+            format!("github.com,*.github.com,192.30.255.112 ssh-ed25519 {GITHUB_ED25519_KEY}")
         );
     }
 
@@ -1001,17 +1510,18 @@ mod tests {
             hosts: vec!["example.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2ETEST".to_string(),
+                key: EXAMPLE_KEY.to_string(),
                 comment: Some("Example RSA key".to_string()),
                 revoked: None,
                 cert_authority: None,
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "example.com ssh-rsa AAAAB3NzaC1yc2ETEST # Example RSA key"
+            format!("example.com ssh-rsa {EXAMPLE_KEY} # Example RSA key")
         );
     }
 
@@ -1022,17 +1532,18 @@ mod tests {
             hosts: vec!["revoked.example.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2EREVOKED".to_string(),
+                key: REVOKED_KEY.to_string(),
                 comment: None,
                 revoked: Some(true),
                 cert_authority: None,
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "@revoked revoked.example.com ssh-rsa AAAAB3NzaC1yc2EREVOKED"
+            format!("@revoked revoked.example.com ssh-rsa {REVOKED_KEY}")
         );
     }
 
@@ -1043,17 +1554,18 @@ mod tests {
             hosts: vec!["ca.example.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-ed25519".to_string(),
-                key: "AAAAC3NzaC1lZDI1NTE5CA".to_string(),
+                key: CA_ED25519_KEY.to_string(),
                 comment: None,
                 revoked: None,
                 cert_authority: Some(true),
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "@cert-authority ca.example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5CA"
+            format!("@cert-authority ca.example.com ssh-ed25519 {CA_ED25519_KEY}")
         );
     }
 
@@ -1064,10 +1576,11 @@ mod tests {
             hosts: vec!["both.example.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2EBOTH".to_string(),
+                key: CA_KEY.to_string(),
                 comment: None,
                 revoked: Some(true),
                 cert_authority: Some(true),
+                sources: Vec::new(),
             }],
         };
 
@@ -1076,7 +1589,7 @@ mod tests {
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "@revoked both.example.com ssh-rsa AAAAB3NzaC1yc2EBOTH"
+            format!("@revoked both.example.com ssh-rsa {CA_KEY}")
         );
         assert!(!result.contains("@cert-authority"));
     }
@@ -1088,10 +1601,11 @@ mod tests {
             hosts: vec!["full.example.com".to_string(), "192.168.1.1".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2EFULL".to_string(),
+                key: REVOKED_KEY.to_string(),
                 comment: Some("Full example with all options".to_string()),
                 revoked: Some(true),
                 cert_authority: Some(true),
+                sources: Vec::new(),
             }],
         };
 
@@ -1099,7 +1613,9 @@ mod tests {
         let result = format_known_hosts_line(&host, &host.keys[0]);
         assert_eq!(
             result,
-            "@revoked full.example.com,192.168.1.1 ssh-rsa AAAAB3NzaC1yc2EFULL # Full example with all options"
+            format!(
+                "@revoked full.example.com,192.168.1.1 ssh-rsa {REVOKED_KEY} # Full example with all options"
+            )
         );
     }
 
@@ -1110,16 +1626,17 @@ mod tests {
             hosts: vec!["noflags.example.com".to_string()],
             keys: vec![HostKey {
                 key_type: "ssh-rsa".to_string(),
-                key: "AAAAB3NzaC1yc2ENOFLAGS".to_string(),
+                key: EXAMPLE_KEY.to_string(),
                 comment: None,
                 revoked: Some(false),
                 cert_authority: Some(false),
+                sources: Vec::new(),
             }],
         };
 
         let result = format_known_hosts_line(&host, &host.keys[0]);
         // When flags are explicitly false, they should not be included
-        assert_eq!(result, "noflags.example.com ssh-rsa AAAAB3NzaC1yc2ENOFLAGS");
+        assert_eq!(result, format!("noflags.example.com ssh-rsa {EXAMPLE_KEY}"));
         assert!(!result.contains("@revoked"));
         assert!(!result.contains("@cert-authority"));
     }
@@ -1132,24 +1649,26 @@ mod tests {
         use tempfile::tempdir;
 
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "name": "GitHub",
                     "hosts": ["github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EAAAADAQABAAABgQC7",
+                            "key": "{GITHUB_KEY}",
                             "comment": "GitHub RSA key"
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Create temp directory
@@ -1157,7 +1676,12 @@ mod tests {
         let file_path = temp_dir.path().join("known_hosts");
 
         // Call function
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(
             result.is_ok(),
             "write_known_hosts failed: {:?}",
@@ -1169,7 +1693,7 @@ mod tests {
 
         // Verify file contents
         let contents = fs::read_to_string(&file_path).unwrap();
-        assert!(contents.contains("github.com ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQC7"));
+        assert!(contents.contains(&format!("github.com ssh-rsa {GITHUB_KEY}")));
         assert!(contents.contains("# GitHub RSA key"));
     }
 
@@ -1179,22 +1703,24 @@ mod tests {
         use tempfile::tempdir;
 
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5"
-                        }
+                            "key": "{GITHUB_ED25519_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Create temp directory with nested path
@@ -1209,7 +1735,12 @@ mod tests {
         assert!(!file_path.parent().unwrap().exists());
 
         // Call function
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(
             result.is_ok(),
             "write_known_hosts failed: {:?}",
@@ -1219,7 +1750,7 @@ mod tests {
         // Verify file and parent directories were created
         assert!(file_path.exists());
         let contents = fs::read_to_string(&file_path).unwrap();
-        assert!(contents.contains("example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"));
+        assert!(contents.contains(&format!("example.com ssh-ed25519 {GITHUB_ED25519_KEY}")));
     }
 
     #[test]
@@ -1228,43 +1759,50 @@ mod tests {
         use tempfile::tempdir;
 
         // Setup mock server with multiple hosts and keys
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "name": "GitHub",
                     "hosts": ["github.com", "*.github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EGitHub1"
-                        },
-                        {
+                            "key": "{GITHUB_KEY}"
+                        }},
+                        {{
                             "type": "ssh-ed25519",
-                            "key": "AAAAC3NzaC1lZDI1NTE5GitHub3"
-                        }
+                            "key": "{GITHUB_ED25519_KEY}"
+                        }}
                     ]
-                },
-                {
+                }},
+                {{
                     "name": "GitLab",
                     "hosts": ["gitlab.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "AAAAB3NzaC1yc2EGitLab1"
-                        }
+                            "key": "{GITLAB_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
@@ -1272,11 +1810,11 @@ mod tests {
 
         // Should have 3 lines (2 keys for GitHub, 1 for GitLab)
         assert_eq!(lines.len(), 3);
-        assert!(contents.contains("github.com,*.github.com ssh-rsa AAAAB3NzaC1yc2EGitHub1"));
-        assert!(
-            contents.contains("github.com,*.github.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5GitHub3")
-        );
-        assert!(contents.contains("gitlab.com ssh-rsa AAAAB3NzaC1yc2EGitLab1"));
+        assert!(contents.contains(&format!("github.com,*.github.com ssh-rsa {GITHUB_KEY}")));
+        assert!(contents.contains(&format!(
+            "github.com,*.github.com ssh-ed25519 {GITHUB_ED25519_KEY}"
+        )));
+        assert!(contents.contains(&format!("gitlab.com ssh-rsa {GITLAB_KEY}")));
     }
 
     #[test]
@@ -1284,44 +1822,53 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["revoked.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "REVOKED_KEY",
+                            "key": "{REVOKED_KEY}",
                             "revoked": true
-                        }
+                        }}
                     ]
-                },
-                {
+                }},
+                {{
                     "hosts": ["ca.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "CA_KEY",
+                            "key": "{CA_KEY}",
                             "cert-authority": true
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
-        assert!(contents.contains("@revoked revoked.example.com ssh-rsa REVOKED_KEY"));
-        assert!(contents.contains("@cert-authority ca.example.com ssh-rsa CA_KEY"));
+        assert!(contents.contains(&format!(
+            "@revoked revoked.example.com ssh-rsa {REVOKED_KEY}"
+        )));
+        assert!(contents.contains(&format!("@cert-authority ca.example.com ssh-rsa {CA_KEY}")));
     }
 
     #[test]
@@ -1340,7 +1887,12 @@ mod tests {
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         // File should exist but be empty
@@ -1354,39 +1906,49 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["new.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "NEW_KEY"
-                        }
+                            "key": "{NEW_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
         // Create existing file with different content
-        fs::write(&file_path, "old.example.com ssh-rsa OLD_KEY\n").unwrap();
-
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), true);
+        fs::write(&file_path, format!("old.example.com ssh-rsa {OLD_KEY}\n")).unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
         // With --force the old content should be gone
         assert!(!contents.contains("old.example.com"));
-        assert!(!contents.contains("OLD_KEY"));
+        assert!(!contents.contains(OLD_KEY));
         // New content should be present
-        assert!(contents.contains("new.example.com ssh-rsa NEW_KEY"));
+        assert!(contents.contains(&format!("new.example.com ssh-rsa {NEW_KEY}")));
     }
 
     #[test]
@@ -1394,37 +1956,49 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["new.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "NEW_KEY"
-                        }
+                            "key": "{NEW_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let existing_content = "old.example.com ssh-rsa OLD_KEY\n";
-        fs::write(&file_path, existing_content).unwrap();
-
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), true);
+        let existing_content = format!("old.example.com ssh-rsa {OLD_KEY}\n");
+        fs::write(&file_path, &existing_content).unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+        );
         assert!(result.is_ok());
 
-        // A `.bak` with the pre-write contents sits alongside the file.
-        let backup_path = file_path.with_file_name("known_hosts.bak");
-        assert!(backup_path.exists(), "backup was not created");
-        assert_eq!(fs::read_to_string(&backup_path).unwrap(), existing_content);
+        // A timestamped `.bak` with the pre-write contents sits alongside the
+        // file.
+        let backups = list_backups(&file_path).unwrap();
+        assert_eq!(backups.len(), 1, "backup was not created");
+        let backup_path = &backups[0].path;
+        assert_eq!(fs::read_to_string(backup_path).unwrap(), existing_content);
     }
 
     #[test]
@@ -1437,10 +2011,11 @@ mod tests {
                 hosts: vec![],
                 keys: vec![HostKey {
                     key_type: "ssh-rsa".to_string(),
-                    key: "AAAA".to_string(),
+                    key: EXAMPLE_KEY.to_string(),
                     comment: None,
                     revoked: None,
                     cert_authority: None,
+                    sources: Vec::new(),
                 }],
             }],
         };
@@ -1454,10 +2029,11 @@ mod tests {
                 hosts: vec!["exa mple.com".to_string()],
                 keys: vec![HostKey {
                     key_type: "ssh-rsa".to_string(),
-                    key: "AAAA".to_string(),
+                    key: EXAMPLE_KEY.to_string(),
                     comment: None,
                     revoked: None,
                     cert_authority: None,
+                    sources: Vec::new(),
                 }],
             }],
         };
@@ -1471,14 +2047,34 @@ mod tests {
                 hosts: vec!["example.com".to_string()],
                 keys: vec![HostKey {
                     key_type: "ssh-rsa".to_string(),
-                    key: "AAAA".to_string(),
+                    key: EXAMPLE_KEY.to_string(),
                     comment: Some("ok\nevil.com ssh-rsa INJECTED".to_string()),
                     revoked: None,
                     cert_authority: None,
+                    sources: Vec::new(),
                 }],
             }],
         };
         assert!(validate_known_hosts_response(&bad_comment).is_err());
+
+        // A declared type that doesn't match the key itself.
+        let mismatched_type = KnownHostsResponse {
+            version: "1.0.0".to_string(),
+            hosts: vec![KnownHost {
+                name: Some("Oak".to_string()),
+                hosts: vec!["oak.example.com".to_string()],
+                keys: vec![HostKey {
+                    key_type: "ssh-ed25519".to_string(),
+                    key: EXAMPLE_KEY.to_string(),
+                    comment: None,
+                    revoked: None,
+                    cert_authority: None,
+                    sources: Vec::new(),
+                }],
+            }],
+        };
+        let err = validate_known_hosts_response(&mismatched_type).unwrap_err();
+        assert!(format!("{err:#}").contains("for known host Oak"), "{err:#}");
     }
 
     #[test]
@@ -1490,10 +2086,11 @@ mod tests {
                 hosts: vec!["github.com".to_string(), "*.github.com".to_string()],
                 keys: vec![HostKey {
                     key_type: "ssh-rsa".to_string(),
-                    key: "AAAAB3NzaC1yc2E".to_string(),
+                    key: EXAMPLE_KEY.to_string(),
                     comment: Some("a normal comment".to_string()),
                     revoked: None,
                     cert_authority: None,
+                    sources: Vec::new(),
                 }],
             }],
         };
@@ -1526,49 +2123,64 @@ mod tests {
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
-        fs::write(&file_path, "old.example.com ssh-rsa OLD_KEY\n").unwrap();
-
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), true);
+        fs::write(&file_path, format!("old.example.com ssh-rsa {OLD_KEY}\n")).unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+        );
         assert!(result.is_err());
 
         // Existing file untouched, no backup created.
         assert_eq!(
             fs::read_to_string(&file_path).unwrap(),
-            "old.example.com ssh-rsa OLD_KEY\n"
+            format!("old.example.com ssh-rsa {OLD_KEY}\n")
         );
-        assert!(!file_path.with_file_name("known_hosts.bak").exists());
+        assert!(list_backups(&file_path).unwrap().is_empty());
     }
 
     #[test]
     fn test_write_known_hosts_no_backup_for_new_file() {
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["new.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "NEW_KEY"
-                        }
+                            "key": "{NEW_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         assert!(
-            !file_path.with_file_name("known_hosts.bak").exists(),
+            list_backups(&file_path).unwrap().is_empty(),
             "no backup should be created when the file did not previously exist"
         );
     }
@@ -1578,39 +2190,46 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["new.example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "NEW_KEY"
-                        }
+                            "key": "{NEW_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
         // Existing file has a local-only entry not present on the server
-        fs::write(&file_path, "old.example.com ssh-rsa OLD_KEY\n").unwrap();
+        fs::write(&file_path, format!("old.example.com ssh-rsa {OLD_KEY}\n")).unwrap();
 
         // Default (additive) mode
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
         // Local-only entry is preserved
-        assert!(contents.contains("old.example.com ssh-rsa OLD_KEY"));
+        assert!(contents.contains(&format!("old.example.com ssh-rsa {OLD_KEY}")));
         // Server entry is added
-        assert!(contents.contains("new.example.com ssh-rsa NEW_KEY"));
+        assert!(contents.contains(&format!("new.example.com ssh-rsa {NEW_KEY}")));
     }
 
     #[test]
@@ -1618,36 +2237,45 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "SHARED_KEY"
-                        }
+                            "key": "{GITHUB_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
         // Existing file already contains the same entry (no comment locally)
-        fs::write(&file_path, "github.com ssh-rsa SHARED_KEY\n").unwrap();
+        fs::write(&file_path, format!("github.com ssh-rsa {GITHUB_KEY}\n")).unwrap();
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
         // The entry should appear exactly once, not duplicated
-        let occurrences = contents.matches("github.com ssh-rsa SHARED_KEY").count();
+        let occurrences = contents
+            .matches(&format!("github.com ssh-rsa {GITHUB_KEY}"))
+            .count();
         assert_eq!(occurrences, 1);
     }
 
@@ -1656,37 +2284,49 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["github.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "SHARED_KEY",
+                            "key": "{GITHUB_KEY}",
                             "comment": "GitHub RSA key"
-                        }
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
         // Existing entry matches the server key but lacks the comment
-        fs::write(&file_path, "github.com ssh-rsa SHARED_KEY\n").unwrap();
+        fs::write(&file_path, format!("github.com ssh-rsa {GITHUB_KEY}\n")).unwrap();
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
         // The matching entry is refreshed with the server's comment, not duplicated
-        assert_eq!(contents.matches("github.com ssh-rsa SHARED_KEY").count(), 1);
+        assert_eq!(
+            contents
+                .matches(&format!("github.com ssh-rsa {GITHUB_KEY}"))
+                .count(),
+            1
+        );
         assert!(contents.contains("# GitHub RSA key"));
     }
 
@@ -1700,7 +2340,15 @@ mod tests {
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server {
+                retries: 0,
+                ..Server::new(&server_url)
+            }],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_err());
 
         // File should not be created on error
@@ -1712,32 +2360,443 @@ mod tests {
         use std::fs;
         use tempfile::tempdir;
 
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "knownHosts": [
-                {
+                {{
                     "hosts": ["example.com"],
                     "keys": [
-                        {
+                        {{
                             "type": "ssh-rsa",
-                            "key": "TEST_KEY"
-                        }
+                            "key": "{EXAMPLE_KEY}"
+                        }}
                     ]
-                }
+                }}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
         let temp_dir = tempdir().unwrap();
         let file_path = temp_dir.path().join("known_hosts");
 
-        let result = write_known_hosts(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+        );
         assert!(result.is_ok());
 
         let contents = fs::read_to_string(&file_path).unwrap();
         // File should end with a newline (POSIX standard)
         assert!(contents.ends_with('\n'));
     }
+
+    #[test]
+    fn test_write_known_hosts_dry_run_writes_nothing() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "knownHosts": [
+                {{
+                    "hosts": ["new.example.com"],
+                    "keys": [
+                        {{
+                            "type": "ssh-rsa",
+                            "key": "{NEW_KEY}"
+                        }}
+                    ]
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+        fs::write(&file_path, format!("old.example.com ssh-rsa {OLD_KEY}\n")).unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                dry_run: true,
+                ..WriteOptions::default()
+            },
+        );
+        assert!(result.is_ok());
+
+        // The file is untouched and no backup is made.
+        let contents = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(contents, format!("old.example.com ssh-rsa {OLD_KEY}\n"));
+        assert!(list_backups(&file_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_known_hosts_managed_mode_mirrors_server() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        // `old.example.com` was removed from the server and `github.com`'s key
+        // was revoked; a locally learned host must survive untouched.
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "knownHosts": [
+                {{
+                    "hosts": ["github.com"],
+                    "keys": [
+                        {{
+                            "type": "ssh-rsa",
+                            "key": "{GITHUB_KEY}",
+                            "revoked": true
+                        }}
+                    ]
+                }},
+                {{
+                    "hosts": ["new.example.com"],
+                    "keys": [
+                        {{
+                            "type": "ssh-ed25519",
+                            "key": "{NEW_ED25519_KEY}"
+                        }}
+                    ]
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+        fs::write(
+            &file_path,
+            format!(
+                "# learned locally\n\
+                 laptop.lan ssh-ed25519 {LAPTOP_KEY}\n\
+                 # BEGIN keys-managed {server_url}\n\
+                 github.com ssh-rsa {GITHUB_KEY}\n\
+                 old.example.com ssh-rsa {OLD_KEY}\n\
+                 # END keys-managed\n"
+            ),
+        )
+        .unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+        );
+        assert!(
+            result.is_ok(),
+            "write_known_hosts failed: {:?}",
+            result.err()
+        );
+
+        let contents = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "# learned locally\n\
+                 laptop.lan ssh-ed25519 {LAPTOP_KEY}\n\
+                 # BEGIN keys-managed {server_url}\n\
+                 @revoked github.com ssh-rsa {GITHUB_KEY}\n\
+                 new.example.com ssh-ed25519 {NEW_ED25519_KEY}\n\
+                 # END keys-managed\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_merge_known_hosts_responses_tracks_sources_and_conflicts() {
+        let team: KnownHostsResponse = serde_json::from_str(
+            &format!(r#"{{
+                "version": "1.0.0",
+                "knownHosts": [
+                    {{"hosts": ["github.com"], "keys": [
+                        {{"type": "ssh-ed25519", "key": "{GITHUB_ED25519_KEY}", "comment": "GitHub"}}
+                    ]}},
+                    {{"hosts": ["db.example.com"], "keys": [
+                        {{"type": "ssh-ed25519", "key": "{DB_KEY}"}}
+                    ]}}
+                ]
+            }}"#),
+        )
+        .unwrap();
+        let break_glass: KnownHostsResponse = serde_json::from_str(&format!(
+            r#"{{
+                "version": "2.0.0",
+                "knownHosts": [
+                    {{"hosts": ["github.com"], "keys": [
+                        {{"type": "ssh-ed25519", "key": "{GITHUB_ED25519_KEY}", "revoked": true}}
+                    ]}},
+                    {{"hosts": ["db.example.com", "10.0.0.5"], "keys": [
+                        {{"type": "ssh-ed25519", "key": "{DB_KEY}", "revoked": true}}
+                    ]}}
+                ]
+            }}"#
+        ))
+        .unwrap();
+
+        let (merged, conflicts) = merge_known_hosts_responses(&[
+            ("https://team.example.com", team),
+            ("https://glass.example.com", break_glass),
+        ]);
+
+        assert_eq!(
+            merged.version,
+            "1.0.0 (https://team.example.com), 2.0.0 (https://glass.example.com)"
+        );
+        let lines: Vec<String> = merged
+            .hosts
+            .iter()
+            .flat_map(|host| {
+                host.keys
+                    .iter()
+                    .map(|key| format_known_hosts_line(host, key))
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                format!(
+                    "@revoked github.com ssh-ed25519 {GITHUB_ED25519_KEY} # GitHub (from https://team.example.com, https://glass.example.com)"
+                ),
+                format!("db.example.com ssh-ed25519 {DB_KEY} # from https://team.example.com"),
+                format!(
+                    "@revoked db.example.com,10.0.0.5 ssh-ed25519 {DB_KEY} # from https://glass.example.com"
+                ),
+            ]
+        );
+        assert_eq!(
+            conflicts,
+            vec![
+                format!(
+                    "github.com ssh-ed25519 {GITHUB_ED25519_KEY} is revoked on https://glass.example.com but not \
+                 on https://team.example.com; treating it as revoked"
+                ),
+                format!(
+                    "ssh-ed25519 {DB_KEY} for db.example.com is revoked on https://glass.example.com but \
+                 not on https://team.example.com"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_known_hosts_managed_mode_merges_servers() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let (team_url, _team) = setup_mock_server(&format!(
+            r#"{{"version": "1.0.0", "knownHosts": [
+                {{"hosts": ["github.com"], "keys": [{{"type": "ssh-ed25519", "key": "{GITHUB_ED25519_KEY}"}}]}}
+            ]}}"#
+        ));
+        let (glass_url, _glass) = setup_mock_server(&format!(
+            r#"{{"version": "1.0.0", "knownHosts": [
+                {{"hosts": ["github.com"], "keys": [{{"type": "ssh-ed25519", "key": "{GITHUB_ED25519_KEY}"}}]}},
+                {{"hosts": ["bastion"], "keys": [{{"type": "ssh-ed25519", "key": "{BASTION_KEY}"}}]}}
+            ]}}"#
+        ));
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+
+        write_known_hosts(
+            &[Server::new(&team_url), Server::new(&glass_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            format!(
+                "# BEGIN keys-managed {team_url} {glass_url}\n\
+                 github.com ssh-ed25519 {GITHUB_ED25519_KEY} # from {team_url}, {glass_url}\n\
+                 bastion ssh-ed25519 {BASTION_KEY} # from {glass_url}\n\
+                 # END keys-managed\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_known_hosts_managed_mode_keeps_duplicate_local_entry() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "knownHosts": [
+                {{
+                    "hosts": ["github.com"],
+                    "keys": [
+                        {{
+                            "type": "ssh-rsa",
+                            "key": "{GITHUB_KEY}"
+                        }}
+                    ]
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+        fs::write(
+            &file_path,
+            format!("github.com ssh-rsa {GITHUB_KEY} # added by hand\n"),
+        )
+        .unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+        );
+        assert!(
+            result.is_ok(),
+            "write_known_hosts failed: {:?}",
+            result.err()
+        );
+
+        // The local duplicate is only warned about, never removed.
+        let contents = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "github.com ssh-rsa {GITHUB_KEY} # added by hand\n\
+                 \n\
+                 # BEGIN keys-managed {server_url}\n\
+                 github.com ssh-rsa {GITHUB_KEY}\n\
+                 # END keys-managed\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_known_hosts_force_refuses_large_change() {
+        use crate::commands::ChangeLimits;
+        use std::fs;
+        use tempfile::tempdir;
+
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "knownHosts": [
+                {{
+                    "hosts": ["new.example.com"],
+                    "keys": [
+                        {{
+                            "type": "ssh-rsa",
+                            "key": "{NEW_KEY}"
+                        }}
+                    ]
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+        let existing_content =
+            format!("a.example.com ssh-rsa {A_KEY}\nb.example.com ssh-rsa {B_KEY}\n");
+        fs::write(&file_path, &existing_content).unwrap();
+
+        // Replacing both entries with a new one changes 150% of the file.
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                limits: ChangeLimits {
+                    max_change_percent: Some(50),
+                    ..ChangeLimits::default()
+                },
+                ..WriteOptions::default()
+            },
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("add or remove 3 entries (150% of 2)"),
+            "unexpected error: {err}"
+        );
+        assert!(err.contains(&format!("   a.example.com ssh-rsa {A_KEY}")));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), existing_content);
+    }
+
+    #[test]
+    fn test_write_known_hosts_managed_mode_refuses_too_many_removals() {
+        use crate::commands::ChangeLimits;
+        use std::fs;
+        use tempfile::tempdir;
+
+        let (server_url, _server) = setup_mock_server(r#"{"version": "1.0.0", "knownHosts": []}"#);
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("known_hosts");
+        let existing_content = format!(
+            "# BEGIN keys-managed {server_url}\n\
+             a.example.com ssh-rsa {A_KEY}\n\
+             b.example.com ssh-rsa {B_KEY}\n\
+             # END keys-managed\n"
+        );
+        fs::write(&file_path, &existing_content).unwrap();
+
+        let result = write_known_hosts(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                limits: ChangeLimits {
+                    max_removals: Some(1),
+                    ..ChangeLimits::default()
+                },
+                ..WriteOptions::default()
+            },
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("remove 2 of 2 entries, more than --max-removals 1"),
+            "unexpected error: {err}"
+        );
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), existing_content);
+    }
 }
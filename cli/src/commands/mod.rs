@@ -1,11 +1,545 @@
+pub mod authorized_keys_command;
 pub mod known_hosts;
 pub mod pgp_keys;
+pub mod policy;
+pub mod profiles;
+pub mod restore;
 pub mod ssh_keys;
+pub mod sync;
+pub mod version;
+
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{CacheSettings, TargetState, file_digest};
+use crate::http::{
+    Auth, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_RETRIES, DEFAULT_TIMEOUT_SECS, Fetched, HttpClient,
+    Validators, Verifier,
+};
+use crate::utils::{DEFAULT_BACKUP_RETENTION, PublicKey, to_yaml};
+use crate::version::VersionReq;
 
 // Re-export the main command functions for easier imports
+pub use authorized_keys_command::authorized_keys_command;
 pub use known_hosts::fetch_known_hosts;
 pub use known_hosts::write_known_hosts;
 pub use pgp_keys::fetch_pgp_keys;
 pub use pgp_keys::import_pgp_keys;
+pub use profiles::list_profiles;
+pub use restore::list_file_backups;
+pub use restore::restore_file;
 pub use ssh_keys::fetch_ssh_keys;
 pub use ssh_keys::write_ssh_keys;
+pub use sync::sync_targets;
+pub use version::show_versions;
+
+/// The keys server to fetch from, and how to connect to it.
+#[derive(Debug, Clone)]
+pub struct Server {
+    /// Base URL of the server (e.g. `https://keys.example.com`).
+    pub url: String,
+    /// How long to wait for each response before giving up.
+    pub timeout: Duration,
+    /// How long to wait for a connection before giving up.
+    pub connect_timeout: Duration,
+    /// How many times a request that fails to connect, times out or gets a
+    /// 5xx or 429 response is retried.
+    pub retries: u32,
+    /// Where responses are cached, and when the cache stands in for the
+    /// server. `None` disables caching.
+    pub cache: Option<CacheSettings>,
+    /// The versions the server must report for its responses to be used.
+    pub expected_version: Option<VersionReq>,
+    /// How requests authenticate, shared by every server so secrets are
+    /// resolved once. `None` sends no credentials.
+    pub auth: Option<Arc<Auth>>,
+    /// The signatures responses must carry. `None` accepts unsigned
+    /// responses.
+    pub verifier: Option<Arc<Verifier>>,
+}
+
+impl Server {
+    /// A server at `url` with the default connection settings.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            retries: DEFAULT_RETRIES,
+            cache: None,
+            expected_version: None,
+            auth: None,
+            verifier: None,
+        }
+    }
+
+    /// Build the HTTP client used to talk to the server.
+    pub(crate) fn client(&self) -> Result<HttpClient> {
+        let mut client = match &self.auth {
+            Some(auth) => HttpClient::authenticated(
+                self.timeout,
+                self.connect_timeout,
+                self.retries,
+                auth.clone(),
+            )?,
+            None => HttpClient::new(self.timeout, self.connect_timeout, self.retries)?,
+        };
+        if let Some(cache) = &self.cache {
+            client = client.with_cache(cache.clone());
+        }
+        if let Some(expected_version) = &self.expected_version {
+            client = client.with_expected_version(expected_version.clone());
+        }
+        if let Some(verifier) = &self.verifier {
+            client = client.with_verifier(verifier.clone());
+        }
+        Ok(client)
+    }
+}
+
+/// The owner of the managed block written from `servers`: the server's URL,
+/// or every URL space-separated when several servers are merged, so a merged
+/// block never clobbers one written from a single server.
+pub(crate) fn managed_owner(servers: &[Server]) -> String {
+    servers
+        .iter()
+        .map(|server| server.url.trim_end_matches('/'))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Each server's URL and response, in the order the servers were given.
+pub(crate) type ServerResponses<T> = Vec<(String, Fetched<T>)>;
+
+/// Fetch `what` from every one of `servers` with `fetch`. With several
+/// servers, an error names the one that failed.
+pub(crate) fn fetch_from_servers<T>(
+    servers: &[Server],
+    what: &str,
+    fetch: impl Fn(&HttpClient, &str) -> Result<Fetched<T>>,
+) -> Result<ServerResponses<T>> {
+    if servers.is_empty() {
+        return Err(anyhow::anyhow!("No keys server configured"));
+    }
+
+    servers
+        .iter()
+        .map(|server| {
+            let fetched = fetch(&server.client()?, &server.url);
+            let fetched = if servers.len() > 1 {
+                fetched.with_context(|| format!("Failed to fetch {what} from {}", server.url))?
+            } else {
+                fetched?
+            };
+            Ok((server.url.clone(), fetched))
+        })
+        .collect()
+}
+
+/// The validators of each server's response, as recorded by [`LastSync`].
+pub(crate) fn response_versions<T>(responses: &ServerResponses<T>) -> Vec<(String, Validators)> {
+    responses
+        .iter()
+        .map(|(url, fetched)| (url.clone(), fetched.validators.clone()))
+        .collect()
+}
+
+/// What a written file was last synced from, kept in the cache directory so
+/// a sync that finds nothing changed on the servers can skip the file
+/// entirely: no merge, no backup and no rewrite, so its mtime is left alone.
+pub(crate) struct LastSync<'a> {
+    cache: Option<&'a CacheSettings>,
+    target: String,
+    path: PathBuf,
+}
+
+impl<'a> LastSync<'a> {
+    /// The last sync of `file_path` from `servers`, where `settings`
+    /// describes everything else that decides what is written to it (such as
+    /// the write mode and filters).
+    pub(crate) fn new(servers: &'a [Server], file_path: &str, settings: &str) -> Self {
+        let path = PathBuf::from(shellexpand::tilde(file_path).as_ref());
+        Self {
+            cache: servers.first().and_then(|server| server.cache.as_ref()),
+            target: format!("{} {settings}", path.display()),
+            path,
+        }
+    }
+
+    /// Whether the file was last synced from these same responses and hasn't
+    /// been changed since, in which case syncing it again would change
+    /// nothing. Reports the skip when it is.
+    pub(crate) fn unchanged(&self, versions: &[(String, Validators)]) -> bool {
+        let Some(cache) = self.cache else {
+            return false;
+        };
+        let Ok(Some(state)) = cache.load_target(&self.target) else {
+            return false;
+        };
+        let same_responses = state.versions.len() == versions.len()
+            && state.versions.iter().zip(versions).all(
+                |((url, validators), (other_url, other_validators))| {
+                    url == other_url && validators.same_as(other_validators)
+                },
+            );
+        if !same_responses || file_digest(&self.path) != Some(state.digest) {
+            return false;
+        }
+
+        let server_versions: Vec<&str> = versions
+            .iter()
+            .map(|(_, validators)| validators.version.as_deref().unwrap_or("unknown"))
+            .collect();
+        println!(
+            "✅ {} is unchanged since version {}; nothing to do",
+            self.path.display(),
+            server_versions.join(", ")
+        );
+        true
+    }
+
+    /// Record that the file was just synced from `versions`. Failing to is
+    /// only a warning, as it just means the next sync can't be skipped.
+    pub(crate) fn record(&self, versions: Vec<(String, Validators)>) {
+        let Some(cache) = self.cache else {
+            return;
+        };
+        let Some(digest) = file_digest(&self.path) else {
+            return;
+        };
+        let state = TargetState {
+            target: self.target.clone(),
+            versions,
+            digest,
+        };
+        if let Err(err) = cache.store_target(&state) {
+            eprintln!(
+                "{}  Failed to record the sync of {}: {err:#}",
+                "⚠️".yellow().bold(),
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Warn about disagreements found while merging several servers' responses.
+/// They go to stderr so piped output stays clean.
+pub(crate) fn report_conflicts(conflicts: &[String]) {
+    for conflict in conflicts {
+        eprintln!("{}  Conflict: {conflict}", "⚠️".yellow().bold());
+    }
+}
+
+/// How fetched keys and hosts are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// A table on a terminal, raw lines when piped (the default).
+    #[default]
+    Auto,
+    /// A formatted table with colors.
+    Table,
+    /// One raw entry per line, for piping to other tools.
+    Raw,
+    /// The full response as JSON, with every field.
+    Json,
+    /// The full response as YAML, with every field.
+    Yaml,
+}
+
+impl OutputFormat {
+    /// Whether to print a table rather than raw lines.
+    pub(crate) fn is_table(self) -> bool {
+        match self {
+            OutputFormat::Auto => std::io::stdout().is_terminal(),
+            OutputFormat::Table => true,
+            OutputFormat::Raw | OutputFormat::Json | OutputFormat::Yaml => false,
+        }
+    }
+
+    /// Print `response` as JSON or YAML if that is the format, returning
+    /// whether it was printed.
+    pub(crate) fn print_structured<T: Serialize>(self, response: &T) -> Result<bool> {
+        match self {
+            OutputFormat::Json => {
+                let json = serde_json::to_string_pretty(response)
+                    .context("Failed to serialize the response as JSON")?;
+                println!("{json}");
+            }
+            OutputFormat::Yaml => {
+                let value = serde_json::to_value(response)
+                    .context("Failed to serialize the response as YAML")?;
+                print!("{}", to_yaml(&value));
+            }
+            OutputFormat::Auto | OutputFormat::Table | OutputFormat::Raw => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// The hash key fingerprints are shown with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FingerprintHash {
+    /// `SHA256:...`, as OpenSSH shows by default.
+    #[default]
+    Sha256,
+    /// The legacy `MD5:aa:bb:...` form of older OpenSSH versions.
+    Md5,
+}
+
+impl FingerprintHash {
+    /// The fingerprint of `key` with this hash, or a placeholder if `key`
+    /// isn't a valid key.
+    pub(crate) fn fingerprint(self, key: Result<PublicKey>) -> String {
+        let fingerprint = key.and_then(|key| match self {
+            FingerprintHash::Sha256 => Ok(key.fingerprint()),
+            FingerprintHash::Md5 => key.md5_fingerprint(),
+        });
+        fingerprint.unwrap_or_else(|_| "invalid key".to_string())
+    }
+}
+
+/// How a write combines the server's entries with what is already in the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    /// Keep every existing entry and add the server's new ones (the default).
+    #[default]
+    Additive,
+    /// Own a marker-delimited block in the file that exactly mirrors the
+    /// server, leaving everything outside the block untouched.
+    Managed,
+    /// Replace the whole file with the server's entries.
+    Force,
+}
+
+/// Options shared by the commands that write a local file (`ssh --write` and
+/// `known-hosts --write`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// How the server's entries are combined with the existing file.
+    pub mode: WriteMode,
+    /// Print a diff of what would change instead of writing anything.
+    pub dry_run: bool,
+    /// How many timestamped backups of the file to keep (`0` disables backups).
+    pub backup_retention: usize,
+    /// Thresholds that abort a write making a suspiciously large change.
+    pub limits: ChangeLimits,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            mode: WriteMode::default(),
+            dry_run: false,
+            backup_retention: DEFAULT_BACKUP_RETENTION,
+            limits: ChangeLimits::default(),
+        }
+    }
+}
+
+/// Thresholds on how much a single write may change a file, so a misconfigured
+/// server or filter can't silently strip most of its entries. Unset limits are
+/// not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChangeLimits {
+    /// The fewest entries the written file may contain.
+    pub min_entries: Option<usize>,
+    /// The most entries a write may remove.
+    pub max_removals: Option<usize>,
+    /// The most entries a write may add or remove, as a percentage of the
+    /// entries already in the file. Not checked when the file has no entries.
+    pub max_change_percent: Option<u32>,
+}
+
+/// How a write would change the entries of a file, for checking against
+/// [`ChangeLimits`].
+#[derive(Debug)]
+pub(crate) struct ChangeSummary<'a> {
+    /// Entries in the file before the write.
+    pub existing: usize,
+    /// Entries in the file after the write.
+    pub remaining: usize,
+    /// Entries the write adds.
+    pub added: usize,
+    /// The lines of the entries the write removes.
+    pub removed: Vec<&'a str>,
+}
+
+impl ChangeLimits {
+    /// Refuse a change to `path` that exceeds any of the limits, listing the
+    /// entries that would have been removed. `entries` names what the file
+    /// holds (e.g. `keys`), for the error message.
+    pub(crate) fn check(&self, path: &Path, entries: &str, change: &ChangeSummary) -> Result<()> {
+        let num_removed = change.removed.len();
+        let mut reasons = Vec::new();
+
+        if let Some(min_entries) = self.min_entries
+            && change.remaining < min_entries
+        {
+            reasons.push(format!(
+                "it would leave {} {entries}, fewer than --min-keys {min_entries}",
+                change.remaining
+            ));
+        }
+        if let Some(max_removals) = self.max_removals
+            && num_removed > max_removals
+        {
+            reasons.push(format!(
+                "it would remove {num_removed} of {} {entries}, more than --max-removals {max_removals}",
+                change.existing
+            ));
+        }
+        if let Some(max_change_percent) = self.max_change_percent
+            && change.existing > 0
+        {
+            let changed = change.added + num_removed;
+            if changed * 100 > max_change_percent as usize * change.existing {
+                reasons.push(format!(
+                    "it would add or remove {changed} {entries} ({}% of {}), more than --max-change-percent {max_change_percent}",
+                    changed * 100 / change.existing,
+                    change.existing
+                ));
+            }
+        }
+
+        if reasons.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "Refusing to write {} because {}",
+            path.display(),
+            reasons.join(", and ")
+        );
+        if !change.removed.is_empty() {
+            message.push_str(&format!("\nThese {entries} would have been removed:"));
+            for line in &change.removed {
+                message.push_str(&format!("\n   {line}"));
+            }
+        }
+        Err(anyhow::anyhow!(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(existing: usize, added: usize, removed: Vec<&str>) -> ChangeSummary<'_> {
+        ChangeSummary {
+            existing,
+            remaining: existing + added - removed.len(),
+            added,
+            removed,
+        }
+    }
+
+    #[test]
+    fn test_change_limits_unset_allow_anything() {
+        let change = change(2, 0, vec!["ssh-rsa A a@b", "ssh-rsa B c@d"]);
+        assert!(
+            ChangeLimits::default()
+                .check(Path::new("authorized_keys"), "keys", &change)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_change_limits_min_entries() {
+        let limits = ChangeLimits {
+            min_entries: Some(2),
+            ..ChangeLimits::default()
+        };
+
+        assert!(
+            limits
+                .check(
+                    Path::new("authorized_keys"),
+                    "keys",
+                    &change(3, 0, vec!["x"])
+                )
+                .is_ok()
+        );
+        let err = limits
+            .check(
+                Path::new("authorized_keys"),
+                "keys",
+                &change(3, 0, vec!["x", "y"]),
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("leave 1 keys, fewer than --min-keys 2"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_change_limits_max_removals_lists_removed_entries() {
+        let limits = ChangeLimits {
+            max_removals: Some(1),
+            ..ChangeLimits::default()
+        };
+
+        let err = limits
+            .check(
+                Path::new("known_hosts"),
+                "entries",
+                &change(
+                    4,
+                    0,
+                    vec!["a.example.com ssh-rsa A", "b.example.com ssh-rsa B"],
+                ),
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("remove 2 of 4 entries, more than --max-removals 1"),
+            "{err}"
+        );
+        assert!(err.contains("These entries would have been removed:"));
+        assert!(err.contains("\n   a.example.com ssh-rsa A\n   b.example.com ssh-rsa B"));
+    }
+
+    #[test]
+    fn test_change_limits_max_change_percent() {
+        let limits = ChangeLimits {
+            max_change_percent: Some(50),
+            ..ChangeLimits::default()
+        };
+
+        // Exactly at the limit is allowed; anything over it is refused.
+        assert!(
+            limits
+                .check(
+                    Path::new("authorized_keys"),
+                    "keys",
+                    &change(4, 1, vec!["x"])
+                )
+                .is_ok()
+        );
+        let err = limits
+            .check(
+                Path::new("authorized_keys"),
+                "keys",
+                &change(4, 2, vec!["x"]),
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("add or remove 3 keys (75% of 4)"), "{err}");
+
+        // A new or empty file has no baseline to compare against.
+        assert!(
+            limits
+                .check(Path::new("authorized_keys"), "keys", &change(0, 10, vec![]))
+                .is_ok()
+        );
+    }
+}
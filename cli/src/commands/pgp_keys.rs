@@ -1,23 +1,24 @@
-use std::io::{IsTerminal, Write};
+use std::io::Write;
 use std::process::{Command, Stdio};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
-use reqwest::header::ACCEPT;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use crate::commands::{OutputFormat, Server};
+use crate::http::HttpClient;
 use crate::utils::{ColumnConfig, pretty_print_table};
 
 /// The GnuPG executable used to import PGP keys into the local keyring.
 const GPG_BIN: &str = "gpg";
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PGPKeysResponse {
     pub version: String,
     pub keys: Vec<PGPKey>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PGPKey {
     pub name: String,
     pub key: String,
@@ -68,44 +69,29 @@ pub fn pretty_print_pgp_keys(keys_response: &PGPKeysResponse) {
 /// Fetch the PGP keys from the server and parse the JSON response.
 ///
 /// # Arguments
+/// * `client` - The HTTP client to send the request with
 /// * `server_url` - The base URL of the keys server
 ///
 /// # Returns
 /// * `Result<PGPKeysResponse>` - The parsed keys response or an error
-fn fetch_pgp_keys_from_server(server_url: &str) -> Result<PGPKeysResponse> {
+pub(crate) fn fetch_pgp_keys_from_server(
+    client: &HttpClient,
+    server_url: &str,
+) -> Result<PGPKeysResponse> {
     let url = format!("{server_url}/pgp");
 
-    let client = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .context("Failed to build HTTP client")?;
-    let response = client
-        .get(&url)
-        .header(ACCEPT, "application/json")
-        .send()
-        .context("Failed to send request to PGP keys server")?;
-
-    let status = response.status();
-
-    if !status.is_success() {
-        return Err(anyhow::anyhow!(
-            "Server returned error code: {} - {}",
-            status.as_u16(),
-            status.canonical_reason().unwrap_or("Unknown")
-        ));
-    }
-
-    response
-        .json::<PGPKeysResponse>()
-        .context("Failed to parse JSON response")
+    client.fetch_json(&url, &[], |_: &PGPKeysResponse| Ok(()))
 }
 
-pub fn fetch_pgp_keys(server_url: &str) -> Result<()> {
-    let keys_response = fetch_pgp_keys_from_server(server_url)?;
+pub fn fetch_pgp_keys(server: &Server, output: OutputFormat) -> Result<()> {
+    let keys_response = fetch_pgp_keys_from_server(&server.client()?, &server.url)?;
+    if output.print_structured(&keys_response)? {
+        return Ok(());
+    }
 
-    // Check if the output is being piped (not connected to a terminal)
-    // Use raw/minimal output when piped to another command
-    if !std::io::stdout().is_terminal() {
+    // Use raw/minimal output when piped to another command (or when asked
+    // to), and a table otherwise
+    if !output.is_table() {
         for key in &keys_response.keys {
             println!("{}", key.key);
         }
@@ -134,15 +120,20 @@ fn format_keys_for_import(keys_response: &PGPKeysResponse) -> String {
 ///
 /// This requires the `gpg` executable (GnuPG) to be installed and available on
 /// the PATH. A clear, actionable error is returned if it is missing.
-pub fn import_pgp_keys(server_url: &str) -> Result<()> {
-    let keys_response = fetch_pgp_keys_from_server(server_url)?;
+pub fn import_pgp_keys(server: &Server) -> Result<()> {
+    let keys_response = fetch_pgp_keys_from_server(&server.client()?, &server.url)?;
+    apply_pgp_keys(&keys_response)
+}
 
+/// Import already-fetched PGP keys into the local GnuPG keyring. Split out
+/// from [`import_pgp_keys`] so `keys sync` can reuse its fetch.
+pub(crate) fn apply_pgp_keys(keys_response: &PGPKeysResponse) -> Result<()> {
     if keys_response.keys.is_empty() {
         println!("No PGP keys found on the server; nothing to import.");
         return Ok(());
     }
 
-    let key_material = format_keys_for_import(&keys_response);
+    let key_material = format_keys_for_import(keys_response);
     run_gpg_import(GPG_BIN, &key_material)?;
 
     println!(
@@ -262,7 +253,7 @@ mod tests {
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
         assert!(result.is_ok(), "fetch_pgp_keys failed: {:?}", result.err());
     }
 
@@ -292,7 +283,7 @@ mod tests {
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
         assert!(result.is_ok(), "fetch_pgp_keys failed: {:?}", result.err());
     }
 
@@ -309,7 +300,7 @@ mod tests {
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
         assert!(result.is_ok(), "fetch_pgp_keys failed: {:?}", result.err());
     }
 
@@ -320,7 +311,13 @@ mod tests {
             setup_mock_server_with_error(500, r#"{"error": "Internal server error"}"#);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(
+            &Server {
+                retries: 0,
+                ..Server::new(&server_url)
+            },
+            OutputFormat::Auto,
+        );
 
         // Should return an error
         assert!(result.is_err());
@@ -335,7 +332,7 @@ mod tests {
             setup_mock_server(r#"{"version": "1.0.0", "keys": [{"incomplete": true}]}"#);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
 
         // Should return an error due to missing required fields
         assert!(result.is_err());
@@ -344,7 +341,13 @@ mod tests {
     #[test]
     fn test_fetch_pgp_keys_network_error() {
         // Test with invalid URL to simulate network error
-        let result = fetch_pgp_keys("http://invalid-url-that-does-not-exist.local");
+        let result = fetch_pgp_keys(
+            &Server {
+                retries: 0,
+                ..Server::new("http://invalid-url-that-does-not-exist.local")
+            },
+            OutputFormat::Auto,
+        );
         assert!(result.is_err());
     }
 
@@ -355,12 +358,12 @@ mod tests {
             setup_mock_server_with_error(401, r#"{"error": "Unauthorized"}"#);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
 
         // Should return an error
         assert!(result.is_err());
         let error_msg = result.err().unwrap().to_string();
-        assert!(error_msg.contains("Server returned error code: 401"));
+        assert!(error_msg.contains("requires authentication (401)"));
     }
 
     #[test]
@@ -369,7 +372,7 @@ mod tests {
         let (server_url, _server) = setup_mock_server_with_error(404, r#"{"error": "Not found"}"#);
 
         // Call function
-        let result = fetch_pgp_keys(&server_url);
+        let result = fetch_pgp_keys(&Server::new(&server_url), OutputFormat::Auto);
 
         // Should return an error
         assert!(result.is_err());
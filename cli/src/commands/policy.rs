@@ -0,0 +1,418 @@
+use anyhow::Result;
+use colored::Colorize;
+use serde::Deserialize;
+
+use crate::commands::known_hosts::{KnownHost, KnownHostsResponse};
+use crate::commands::ssh_keys::KeysResponse;
+use crate::utils::PublicKey;
+
+/// The algorithms `[policy] allowed_algorithms` can name.
+const ALGORITHMS: &[&str] = &[
+    "ssh-ed25519",
+    "sk-ssh-ed25519@openssh.com",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "ssh-rsa",
+    "ssh-dss",
+];
+
+/// What happens to server entries that break the `[policy]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    /// Leave them out, with a warning, and write the rest (the default).
+    #[default]
+    Skip,
+    /// Fail without writing anything.
+    Abort,
+}
+
+/// Rules the keys written to authorized_keys and known_hosts files must meet,
+/// under `[policy]`, e.g. to keep weak keys off sensitive hosts
+///
+/// The policy only judges the server's entries: lines already in a file are
+/// left to the write mode. An empty policy allows every key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPolicy {
+    /// The key algorithms allowed, e.g. `ssh-ed25519`, or every algorithm
+    /// when empty. A certificate is judged by the key it certifies.
+    #[serde(default)]
+    pub allowed_algorithms: Vec<String>,
+    /// The smallest RSA modulus allowed, in bits
+    pub min_rsa_bits: Option<u64>,
+    /// Tags whose keys must be hardware security keys (`sk-*`)
+    #[serde(default)]
+    pub hardware_key_tags: Vec<String>,
+    /// Whether entries breaking the policy are skipped or abort the run
+    #[serde(default)]
+    pub strictness: Strictness,
+}
+
+impl KeyPolicy {
+    /// Check that `allowed_algorithms` names known algorithms, so a typo
+    /// doesn't reject every key.
+    pub fn validate(&self) -> Result<()> {
+        for algorithm in &self.allowed_algorithms {
+            if !ALGORITHMS.contains(&algorithm.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "[policy] allowed_algorithms: unknown algorithm {algorithm:?} (expected one of {})",
+                    ALGORITHMS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Why `key`, carrying `tags`, breaks this policy, or `None` if it
+    /// doesn't.
+    fn violation(&self, key: &PublicKey, tags: &[String]) -> Option<String> {
+        let algorithm = key.algorithm();
+        if !self.allowed_algorithms.is_empty()
+            && !self
+                .allowed_algorithms
+                .iter()
+                .any(|allowed| allowed == algorithm)
+        {
+            return Some(format!("{algorithm} keys aren't allowed"));
+        }
+        if let (Some(min_bits), Some(bits)) = (self.min_rsa_bits, key.rsa_bits())
+            && bits < min_bits
+        {
+            return Some(format!(
+                "the RSA key is {bits} bits, under the {min_bits}-bit minimum"
+            ));
+        }
+        if !key.is_security_key()
+            && let Some(tag) = tags.iter().find(|tag| self.hardware_key_tags.contains(tag))
+        {
+            return Some(format!(
+                "keys tagged {tag} must be hardware security keys (sk-*), not {algorithm}"
+            ));
+        }
+        None
+    }
+
+    /// The keys in `keys_response` that meet this policy. The rest are
+    /// reported, or fail the run if `strictness` is `abort`.
+    pub(crate) fn enforce_ssh_keys(&self, keys_response: &KeysResponse) -> Result<KeysResponse> {
+        let mut rejected = Vec::new();
+        let keys = keys_response
+            .keys
+            .iter()
+            .filter(|key| {
+                let violation = match PublicKey::parse(&key.key) {
+                    Ok(public_key) => self.violation(&public_key, &key.tags),
+                    Err(err) => Some(format!("{err:#}")),
+                };
+                if let Some(reason) = &violation {
+                    rejected.push(format!("key {}@{}: {reason}", key.user, key.name));
+                }
+                violation.is_none()
+            })
+            .cloned()
+            .collect();
+        self.report(&rejected)?;
+        Ok(KeysResponse {
+            version: keys_response.version.clone(),
+            keys,
+        })
+    }
+
+    /// The known hosts in `response` whose keys meet this policy. The rest
+    /// are reported, or fail the run if `strictness` is `abort`.
+    ///
+    /// `@revoked` entries are always kept, since leaving one out would let
+    /// the revoked key be trusted again.
+    pub(crate) fn enforce_known_hosts(
+        &self,
+        response: &KnownHostsResponse,
+    ) -> Result<KnownHostsResponse> {
+        let mut rejected = Vec::new();
+        let hosts = response
+            .hosts
+            .iter()
+            .map(|host| KnownHost {
+                keys: host
+                    .keys
+                    .iter()
+                    .filter(|key| {
+                        if key.revoked.unwrap_or(false) {
+                            return true;
+                        }
+                        let violation = match PublicKey::from_parts(&key.key_type, &key.key) {
+                            Ok(public_key) => self.violation(&public_key, &[]),
+                            Err(err) => Some(format!("{err:#}")),
+                        };
+                        if let Some(reason) = &violation {
+                            rejected.push(format!(
+                                "{} key for host {}: {reason}",
+                                key.key_type,
+                                host.name.as_deref().unwrap_or(&host.hosts[0])
+                            ));
+                        }
+                        violation.is_none()
+                    })
+                    .cloned()
+                    .collect(),
+                ..host.clone()
+            })
+            .filter(|host| !host.keys.is_empty())
+            .collect();
+        self.report(&rejected)?;
+        Ok(KnownHostsResponse {
+            version: response.version.clone(),
+            hosts,
+        })
+    }
+
+    /// Report the `rejected` entries on stderr, or fail listing them all if
+    /// `strictness` is `abort`.
+    fn report(&self, rejected: &[String]) -> Result<()> {
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        match self.strictness {
+            Strictness::Skip => {
+                for entry in rejected {
+                    eprintln!(
+                        "{}  Skipping {entry} (against [policy])",
+                        "⚠️".yellow().bold()
+                    );
+                }
+                Ok(())
+            }
+            Strictness::Abort => Err(anyhow::anyhow!(
+                "Server entries break [policy]:\n  {}",
+                rejected.join("\n  ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::known_hosts::HostKey;
+    use crate::commands::ssh_keys::SSHKey;
+    use crate::utils::{Reader, put_string};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+
+    /// From examples/keys-config.yaml
+    const RSA_2048: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC3o6dpyLFuyDfqhc84es4R2xNE+AhsKKqKJNxs6eyLcqIf9dezH8BD9Ye6E0BoupeZwJx9CL3wwZFmdpHEYmdLb1e7PRxx0hf/6nLRBI5+34gKukj3dZtAhZuiGOQ3sKl6iOqCTi499cRBi2TxdH2xS9n0sZCIWFLuvVzyYy+AX9F1hSTCkVhTvQKc3PJCUZHluk83ydvCyQh0wzUYDVSLkNkt03Ptu2tkj8VqTMsc8WPwBsnBwgNqK3FrD45HuFJYSObEO7ZqrHMZXOyys/jgjoAnIJ+CB5ef43PopTe+IQwqilf8JOjl7PWLPXDpnemiBkPKPy6MBGUr0F9mVEaD";
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    const ECDSA: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBEmKSENjQEezOmxkZMy7opKgwFB9nkt5YRrYMjNuG5N87uRgg6CLrbo5wAdT/y6v0mKV0U2w0WZ2YB/++Tpockg=";
+
+    fn key(name: &str, key: &str, tags: &[&str]) -> SSHKey {
+        SSHKey {
+            key: key.to_string(),
+            user: "alice".to_string(),
+            name: name.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            sources: Vec::new(),
+        }
+    }
+
+    fn keys_response() -> KeysResponse {
+        KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![
+                key("old-laptop", RSA_2048, &[]),
+                key("laptop", ED25519, &["admin"]),
+                key("yubikey", ECDSA, &[]),
+            ],
+        }
+    }
+
+    fn names(keys_response: &KeysResponse) -> Vec<&str> {
+        keys_response
+            .keys
+            .iter()
+            .map(|key| key.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_empty_policy_allows_every_key() {
+        let kept = KeyPolicy::default()
+            .enforce_ssh_keys(&keys_response())
+            .unwrap();
+        assert_eq!(names(&kept), vec!["old-laptop", "laptop", "yubikey"]);
+    }
+
+    #[test]
+    fn test_policy_violations() {
+        let policy = KeyPolicy {
+            allowed_algorithms: vec!["ssh-ed25519".to_string(), "ssh-rsa".to_string()],
+            min_rsa_bits: Some(3072),
+            hardware_key_tags: vec!["admin".to_string()],
+            strictness: Strictness::Skip,
+        };
+        let reasons: Vec<Option<String>> = keys_response()
+            .keys
+            .iter()
+            .map(|key| policy.violation(&PublicKey::parse(&key.key).unwrap(), &key.tags))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                Some("the RSA key is 2048 bits, under the 3072-bit minimum".to_string()),
+                Some(
+                    "keys tagged admin must be hardware security keys (sk-*), not ssh-ed25519"
+                        .to_string()
+                ),
+                Some("ecdsa-sha2-nistp256 keys aren't allowed".to_string()),
+            ]
+        );
+
+        // Skipping leaves only the compliant keys
+        let policy = KeyPolicy {
+            min_rsa_bits: Some(3072),
+            ..KeyPolicy::default()
+        };
+        let kept = policy.enforce_ssh_keys(&keys_response()).unwrap();
+        assert_eq!(names(&kept), vec!["laptop", "yubikey"]);
+    }
+
+    #[test]
+    fn test_abort_lists_every_violation() {
+        let policy = KeyPolicy {
+            allowed_algorithms: vec!["ssh-ed25519".to_string()],
+            strictness: Strictness::Abort,
+            ..KeyPolicy::default()
+        };
+        let err = policy.enforce_ssh_keys(&keys_response()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Server entries break [policy]:\n  \
+             key alice@old-laptop: ssh-rsa keys aren't allowed\n  \
+             key alice@yubikey: ecdsa-sha2-nistp256 keys aren't allowed"
+        );
+    }
+
+    /// `RSA_2048` with its modulus stored as `modulus`.
+    fn rsa_key_with_modulus(modulus: impl FnOnce(&[u8]) -> Vec<u8>) -> String {
+        let (_, data) = RSA_2048.split_once(' ').unwrap();
+        let blob = STANDARD.decode(data).unwrap();
+        let mut reader = Reader::new(&blob, "the key");
+        let key_type = reader.string().unwrap();
+        let exponent = reader.mpint("RSA exponent").unwrap();
+        let original = reader.mpint("RSA modulus").unwrap();
+        let mut padded = Vec::new();
+        put_string(&mut padded, key_type);
+        put_string(&mut padded, exponent);
+        put_string(&mut padded, &modulus(original));
+        format!("ssh-rsa {}", STANDARD.encode(padded))
+    }
+
+    #[test]
+    fn test_min_rsa_bits_ignores_zero_padding() {
+        let policy = KeyPolicy {
+            min_rsa_bits: Some(3072),
+            ..KeyPolicy::default()
+        };
+
+        // Leading zero bytes don't add to the key's size
+        let padded = rsa_key_with_modulus(|modulus| [&[0; 129], modulus].concat());
+        assert_eq!(
+            policy.violation(&PublicKey::parse(&padded).unwrap(), &[]),
+            Some("the RSA key is 2048 bits, under the 3072-bit minimum".to_string())
+        );
+
+        let zero = rsa_key_with_modulus(|_| vec![0]);
+        assert_eq!(
+            format!("{:#}", PublicKey::parse(&zero).unwrap_err()),
+            "the key's RSA modulus is zero"
+        );
+        let response = KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![key("laptop", &zero, &[]), key("desktop", ED25519, &[])],
+        };
+        let kept = policy.enforce_ssh_keys(&response).unwrap();
+        assert_eq!(names(&kept), vec!["desktop"]);
+        let err = KeyPolicy {
+            strictness: Strictness::Abort,
+            ..policy
+        }
+        .enforce_ssh_keys(&response)
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Server entries break [policy]:\n  key alice@laptop: the key's RSA modulus is zero"
+        );
+    }
+
+    #[test]
+    fn test_enforce_known_hosts_keeps_revocations() {
+        let host_key = |key: &str, revoked: bool| {
+            let (key_type, key) = key.split_once(' ').unwrap();
+            HostKey {
+                key_type: key_type.to_string(),
+                key: key.to_string(),
+                comment: None,
+                revoked: revoked.then_some(true),
+                cert_authority: None,
+                sources: Vec::new(),
+            }
+        };
+        let response = KnownHostsResponse {
+            version: "1.0.0".to_string(),
+            hosts: vec![
+                KnownHost {
+                    name: Some("Oak".to_string()),
+                    hosts: vec!["oak.example.com".to_string()],
+                    keys: vec![host_key(ED25519, false), host_key(RSA_2048, false)],
+                },
+                KnownHost {
+                    name: None,
+                    hosts: vec!["old.example.com".to_string()],
+                    keys: vec![host_key(ECDSA, false)],
+                },
+                KnownHost {
+                    name: None,
+                    hosts: vec!["stolen.example.com".to_string()],
+                    keys: vec![host_key(RSA_2048, true)],
+                },
+            ],
+        };
+        let policy = KeyPolicy {
+            allowed_algorithms: vec!["ssh-ed25519".to_string()],
+            ..KeyPolicy::default()
+        };
+
+        let kept = policy.enforce_known_hosts(&response).unwrap();
+        let kept: Vec<(&str, &str)> = kept
+            .hosts
+            .iter()
+            .flat_map(|host| {
+                host.keys
+                    .iter()
+                    .map(|key| (host.hosts[0].as_str(), key.key_type.as_str()))
+            })
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                ("oak.example.com", "ssh-ed25519"),
+                ("stolen.example.com", "ssh-rsa"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let policy = KeyPolicy {
+            allowed_algorithms: vec!["ed25519".to_string()],
+            ..KeyPolicy::default()
+        };
+        let err = policy.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("unknown algorithm \"ed25519\""),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::commands::OutputFormat;
+use crate::config::Config;
+use crate::utils::pretty_print::{ColumnConfig, pretty_print_table};
+
+/// The profile `keys` would use: the one given to `--profile`, or the
+/// config's `default_profile`.
+fn selected_profile<'a>(config: &'a Config, selected: Option<&'a str>) -> Option<&'a str> {
+    selected.or(config.default_profile.as_deref())
+}
+
+/// Whether a profile is the default, the one selected, or both.
+fn profile_status(config: &Config, name: &str, selected: Option<&str>) -> String {
+    let mut status = Vec::new();
+    if selected_profile(config, selected) == Some(name) {
+        status.push("selected");
+    }
+    if config.default_profile.as_deref() == Some(name) {
+        status.push("default");
+    }
+    status.join(", ")
+}
+
+/// List the `[profiles.<name>]` sections of the config file, marking the
+/// default profile and the one `--profile` selects.
+pub fn list_profiles(config: &Config, selected: Option<&str>, output: OutputFormat) -> Result<()> {
+    // Fail on a mistyped `--profile` here too, as every other command would
+    if let Some(name) = selected {
+        config.profile(name)?;
+    }
+
+    if !output.is_table() {
+        for name in config.profiles.keys() {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = config
+        .profiles
+        .iter()
+        .map(|(name, profile)| {
+            vec![
+                name.clone(),
+                // A profile without its own URL inherits the top-level one
+                profile
+                    .server_url
+                    .clone()
+                    .unwrap_or_else(|| config.server_url.clone()),
+                profile_status(config, name, selected),
+            ]
+        })
+        .collect();
+
+    let width = |column: usize, header: &str| {
+        rows.iter()
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or(0)
+            .max(header.len())
+    };
+    let columns = vec![
+        ColumnConfig {
+            header: "NAME".to_string(),
+            color: |s| s.green(),
+            width: width(0, "NAME"),
+        },
+        ColumnConfig {
+            header: "SERVER".to_string(),
+            color: |s| s.cyan(),
+            width: width(1, "SERVER"),
+        },
+        ColumnConfig {
+            header: "STATUS".to_string(),
+            color: |s| s.yellow(),
+            width: width(2, "STATUS"),
+        },
+    ];
+
+    pretty_print_table(
+        "Selected profile:",
+        selected_profile(config, selected).unwrap_or("none"),
+        columns,
+        rows,
+        "No profiles defined; add a [profiles.<name>] section to the config file",
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Profile;
+
+    fn config_with_profiles() -> Config {
+        let mut config = Config {
+            default_profile: Some("production".to_string()),
+            ..Config::default()
+        };
+        config
+            .profiles
+            .insert("production".to_string(), Profile::default());
+        config
+            .profiles
+            .insert("staging".to_string(), Profile::default());
+        config
+    }
+
+    #[test]
+    fn test_profile_status_marks_default_and_selected() {
+        let config = config_with_profiles();
+
+        assert_eq!(
+            profile_status(&config, "production", None),
+            "selected, default"
+        );
+        assert_eq!(profile_status(&config, "staging", None), "");
+        assert_eq!(
+            profile_status(&config, "production", Some("staging")),
+            "default"
+        );
+        assert_eq!(
+            profile_status(&config, "staging", Some("staging")),
+            "selected"
+        );
+    }
+
+    #[test]
+    fn test_list_profiles_rejects_unknown_profile() {
+        let config = config_with_profiles();
+
+        let err = list_profiles(&config, Some("prod"), OutputFormat::Raw).unwrap_err();
+        assert!(err.to_string().contains("production, staging"));
+    }
+}
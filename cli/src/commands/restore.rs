@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::commands::WriteOptions;
+use crate::utils::{
+    Backup, backup_existing_file, list_backups, print_unified_diff, write_file_atomic,
+};
+
+/// Find the backup matching `selector`, which may be its label (e.g.
+/// `2026-10-18T02-31-00`), its file name, or its full path. With no selector
+/// the newest backup is chosen.
+fn select_backup<'a>(backups: &'a [Backup], selector: Option<&str>) -> Option<&'a Backup> {
+    match selector {
+        None => backups.first(),
+        Some(selector) => backups.iter().find(|backup| {
+            backup.label() == selector
+                || backup
+                    .path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy() == selector)
+                || backup.path == Path::new(selector)
+        }),
+    }
+}
+
+/// List the available backups of a file, newest first.
+pub fn list_file_backups(file_path: &str) -> Result<()> {
+    let expanded_path = shellexpand::tilde(file_path);
+    let path = Path::new(expanded_path.as_ref());
+
+    let backups = list_backups(path)?;
+    if backups.is_empty() {
+        println!(
+            "{}",
+            format!("No backups found for {}", path.display())
+                .yellow()
+                .italic()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} {} (newest first, UTC)",
+        "Backups of".purple().bold(),
+        path.display()
+    );
+    println!();
+    for (i, backup) in backups.iter().enumerate() {
+        let latest = if i == 0 { " (latest)" } else { "" };
+        println!(
+            "{}{}   {}",
+            backup.label().green(),
+            latest.cyan(),
+            backup.path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Restore a file from one of its backups (the newest by default).
+///
+/// The restore goes through the same safety net as a normal write: the current
+/// file is backed up first (so the restore itself can be undone), the contents
+/// are written atomically with the file's permissions preserved, and with
+/// `dry_run` only a diff is printed.
+pub fn restore_file(file_path: &str, selector: Option<&str>, options: &WriteOptions) -> Result<()> {
+    let expanded_path = shellexpand::tilde(file_path);
+    let path = Path::new(expanded_path.as_ref());
+
+    let backups = list_backups(path)?;
+    let backup = select_backup(&backups, selector).ok_or_else(|| match selector {
+        Some(selector) => anyhow::anyhow!(
+            "No backup of {} matches {selector:?} (run `keys restore {file_path} --list` to see the available backups)",
+            path.display()
+        ),
+        None => anyhow::anyhow!("No backups found for {}", path.display()),
+    })?;
+
+    // Read the backup before anything else touches the directory: backing up
+    // the current file below may prune the oldest backups.
+    let restored_content = std::fs::read(&backup.path)
+        .with_context(|| format!("Failed to read backup: {}", backup.path.display()))?;
+
+    if options.dry_run {
+        let current_content = if path.exists() {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read existing file: {}", path.display()))?
+        } else {
+            String::new()
+        };
+        print_unified_diff(
+            path,
+            &current_content,
+            &String::from_utf8_lossy(&restored_content),
+        );
+        println!(
+            "🔍 Dry run: would restore {} from backup {}",
+            path.display(),
+            backup.label()
+        );
+        return Ok(());
+    }
+
+    if let Some(current_backup) = backup_existing_file(path, options.backup_retention)? {
+        println!("📦 Backed up current file to {}", current_backup.display());
+    }
+
+    write_file_atomic(path, &restored_content)?;
+
+    println!(
+        "✅ Restored {} from backup {}",
+        path.display(),
+        backup.label()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_restore_file_restores_latest_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        fs::write(&path, "current\n").unwrap();
+        fs::write(
+            dir.path().join("authorized_keys.2026-10-18T01-00-00.bak"),
+            "older\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("authorized_keys.2026-10-18T02-00-00.bak"),
+            "newest\n",
+        )
+        .unwrap();
+
+        restore_file(path.to_str().unwrap(), None, &WriteOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "newest\n");
+        // The pre-restore contents were backed up, so the restore can be undone.
+        let latest = &list_backups(&path).unwrap()[0];
+        assert_eq!(fs::read_to_string(&latest.path).unwrap(), "current\n");
+    }
+
+    #[test]
+    fn test_restore_file_selects_backup_by_label() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        fs::write(&path, "current\n").unwrap();
+        fs::write(
+            dir.path().join("known_hosts.2026-10-18T01-00-00.bak"),
+            "older\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("known_hosts.2026-10-18T02-00-00.bak"),
+            "newest\n",
+        )
+        .unwrap();
+
+        restore_file(
+            path.to_str().unwrap(),
+            Some("2026-10-18T01-00-00"),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "older\n");
+    }
+
+    #[test]
+    fn test_restore_file_unknown_selector_errors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        fs::write(&path, "current\n").unwrap();
+        fs::write(
+            dir.path().join("known_hosts.2026-10-18T01-00-00.bak"),
+            "older\n",
+        )
+        .unwrap();
+
+        let result = restore_file(
+            path.to_str().unwrap(),
+            Some("2020-01-01T00-00-00"),
+            &WriteOptions::default(),
+        );
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("--list"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "current\n");
+    }
+
+    #[test]
+    fn test_restore_file_no_backups_errors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        fs::write(&path, "current\n").unwrap();
+
+        let result = restore_file(path.to_str().unwrap(), None, &WriteOptions::default());
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("No backups found"));
+    }
+
+    #[test]
+    fn test_restore_file_dry_run_writes_nothing() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        fs::write(&path, "current\n").unwrap();
+        fs::write(
+            dir.path().join("authorized_keys.2026-10-18T01-00-00.bak"),
+            "older\n",
+        )
+        .unwrap();
+
+        restore_file(
+            path.to_str().unwrap(),
+            None,
+            &WriteOptions {
+                dry_run: true,
+                ..WriteOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "current\n");
+        assert_eq!(list_backups(&path).unwrap().len(), 1);
+    }
+}
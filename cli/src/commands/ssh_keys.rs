@@ -1,28 +1,173 @@
-use std::io::IsTerminal;
+use std::collections::BTreeMap;
+use std::path::Path;
 
 use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
 use colored::Colorize;
-use reqwest::header::ACCEPT;
-use serde::Deserialize;
-
-use crate::utils::{ColumnConfig, backup_existing_file, pretty_print_table};
-
-#[derive(Debug, Deserialize)]
+use serde::{Deserialize, Serialize};
+
+use crate::commands::policy::KeyPolicy;
+use crate::commands::{
+    ChangeSummary, FingerprintHash, LastSync, OutputFormat, Server, ServerResponses, WriteMode,
+    WriteOptions, fetch_from_servers, managed_owner, report_conflicts, response_versions,
+};
+use crate::http::{Fetched, HttpClient};
+use crate::utils::{
+    ColumnConfig, ManagedFile, PublicKey, pretty_print_table, print_unified_diff,
+    replace_file_with_backup,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct KeysResponse {
     pub version: String,
     pub keys: Vec<SSHKey>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SSHKey {
     pub key: String,
     pub user: String,
     pub name: String,
     pub tags: Vec<String>,
+    /// The servers the key was fetched from, when several are merged
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+}
+
+/// The prefix of the tag giving a key's expiry date, e.g. `expires:2026-12-31`
+const EXPIRES_TAG: &str = "expires:";
+
+/// How many days before a key expires `keys ssh` highlights it by default
+pub const DEFAULT_EXPIRY_WARNING_DAYS: u64 = 14;
+
+impl SSHKey {
+    /// The date the key expires on, from its `expires:YYYY-MM-DD` tag (the
+    /// earliest, if it has several). Like OpenSSH's `expiry-time`, the key
+    /// stops being accepted at the start of that day, local time.
+    pub fn expires(&self) -> Result<Option<NaiveDate>, String> {
+        let mut expires: Option<NaiveDate> = None;
+        for tag in &self.tags {
+            let Some(date) = tag.strip_prefix(EXPIRES_TAG) else {
+                continue;
+            };
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                format!("invalid expiry tag {tag:?} (expected {EXPIRES_TAG}YYYY-MM-DD)")
+            })?;
+            expires = Some(expires.map_or(date, |earliest| earliest.min(date)));
+        }
+        Ok(expires)
+    }
+}
+
+/// Which keys to request from the server, mirroring the `user`, `allOf`,
+/// `oneOf` and `noneOf` query parameters accepted by the `/keys` route, and
+/// which of those keys to keep by fingerprint.
+///
+/// An empty filter requests every key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyFilter {
+    /// Only keys belonging to one of these users.
+    pub users: Vec<String>,
+    /// Only keys carrying every one of these tags.
+    pub all_of: Vec<String>,
+    /// Only keys carrying at least one of these tags.
+    pub one_of: Vec<String>,
+    /// Only keys carrying none of these tags.
+    pub none_of: Vec<String>,
+    /// Only keys with one of these fingerprints, as returned by
+    /// [`parse_fingerprint`](crate::utils::parse_fingerprint). The server can't filter by fingerprint, so this
+    /// is applied client-side.
+    pub fingerprints: Vec<String>,
+}
+
+impl KeyFilter {
+    /// The query parameters encoding this filter for the `/keys` route.
+    ///
+    /// The server only honours a single `user` parameter, so when more than one
+    /// user is requested the user filter is left off the query and applied
+    /// client-side by [`KeyFilter::retain_client_side`] instead.
+    fn query_pairs(&self) -> Vec<(&'static str, &str)> {
+        let mut pairs = Vec::new();
+        if let [user] = self.users.as_slice() {
+            pairs.push(("user", user.as_str()));
+        }
+        pairs.extend(self.all_of.iter().map(|tag| ("allOf", tag.as_str())));
+        pairs.extend(self.one_of.iter().map(|tag| ("oneOf", tag.as_str())));
+        pairs.extend(self.none_of.iter().map(|tag| ("noneOf", tag.as_str())));
+        pairs
+    }
+
+    /// Drop keys the server couldn't filter out: those whose user isn't one
+    /// of the requested users, when more than one was requested, and those
+    /// without one of the requested fingerprints.
+    fn retain_client_side(&self, keys_response: &mut KeysResponse) {
+        if self.users.len() > 1 {
+            keys_response
+                .keys
+                .retain(|key| self.users.contains(&key.user));
+        }
+        keys_response
+            .keys
+            .retain(|key| self.matches_fingerprint(key));
+    }
+
+    /// Whether `key` has one of the requested fingerprints, or none were
+    /// requested.
+    fn matches_fingerprint(&self, key: &SSHKey) -> bool {
+        self.fingerprints.is_empty()
+            || PublicKey::parse(&key.key).is_ok_and(|public_key| {
+                self.fingerprints
+                    .iter()
+                    .any(|fingerprint| public_key.has_fingerprint(fingerprint))
+            })
+    }
+
+    /// Whether `key` passes this filter, applying on the client the same rules
+    /// the server applies to the query parameters.
+    fn matches(&self, key: &SSHKey) -> bool {
+        let has_tag = |tag: &String| key.tags.contains(tag);
+        (self.users.is_empty() || self.users.contains(&key.user))
+            && self.all_of.iter().all(has_tag)
+            && (self.one_of.is_empty() || self.one_of.iter().any(has_tag))
+            && !self.none_of.iter().any(has_tag)
+            && self.matches_fingerprint(key)
+    }
+
+    /// The keys in `keys_response` that pass this filter, so one unfiltered
+    /// fetch can serve several differently filtered targets in `keys sync`.
+    pub(crate) fn apply(&self, keys_response: &KeysResponse) -> KeysResponse {
+        KeysResponse {
+            version: keys_response.version.clone(),
+            keys: keys_response
+                .keys
+                .iter()
+                .filter(|key| self.matches(key))
+                .cloned()
+                .collect(),
+        }
+    }
 }
 
-/// Function to pretty print the SSH keys with formatted columns and colors
-pub fn pretty_print_ssh_keys(keys_response: &KeysResponse) {
+/// Function to pretty print the SSH keys with formatted columns and colors,
+/// highlighting keys that expire within `expiry_warning_days` and showing
+/// each key's fingerprint with `fingerprint_hash`
+pub fn pretty_print_ssh_keys(
+    keys_response: &KeysResponse,
+    expiry_warning_days: u64,
+    fingerprint_hash: FingerprintHash,
+) {
+    let today = Local::now().date_naive();
+    let expires: Vec<String> = keys_response
+        .keys
+        .iter()
+        .map(|key| expiry_cell(key, today, expiry_warning_days))
+        .collect();
+    let fingerprints: Vec<String> = keys_response
+        .keys
+        .iter()
+        .map(|key| fingerprint_hash.fingerprint(PublicKey::parse(&key.key)))
+        .collect();
+
     // Find the maximum width for each column for better formatting
     let max_name_len = keys_response
         .keys
@@ -47,7 +192,7 @@ pub fn pretty_print_ssh_keys(keys_response: &KeysResponse) {
         .max(4);
 
     // Define the columns
-    let columns = vec![
+    let mut columns = vec![
         ColumnConfig {
             header: "NAME".to_string(),
             color: |s| s.green(),
@@ -64,23 +209,61 @@ pub fn pretty_print_ssh_keys(keys_response: &KeysResponse) {
             width: max_tags_len,
         },
         ColumnConfig {
-            header: "KEY".to_string(),
+            header: "EXPIRES".to_string(),
+            // Only keys expiring soon say how soon
+            color: |s| {
+                if s.contains('(') {
+                    s.red().bold()
+                } else {
+                    s.normal()
+                }
+            },
+            width: expires.iter().map(String::len).max().unwrap_or(7).max(7),
+        },
+        ColumnConfig {
+            header: "FINGERPRINT".to_string(),
             color: |s| s.red(),
-            width: 50, // Key is typically long, so use a reasonable default width
+            width: fingerprints.iter().map(String::len).max().unwrap_or(11),
         },
     ];
 
+    // Show where each key came from when several servers were merged
+    let merged = keys_response.keys.iter().any(|k| !k.sources.is_empty());
+    if merged {
+        columns.insert(
+            4,
+            ColumnConfig {
+                header: "SOURCE".to_string(),
+                color: |s| s.cyan(),
+                width: keys_response
+                    .keys
+                    .iter()
+                    .map(|k| k.sources.join(", ").len())
+                    .max()
+                    .unwrap_or(6)
+                    .max(6),
+            },
+        );
+    }
+
     // Prepare the rows
     let rows: Vec<Vec<String>> = keys_response
         .keys
         .iter()
-        .map(|key| {
-            vec![
+        .zip(expires)
+        .zip(fingerprints)
+        .map(|((key, expires), fingerprint)| {
+            let mut row = vec![
                 key.name.clone(),
                 key.user.clone(),
                 key.tags.join(", "),
-                key.key.clone(),
-            ]
+                expires,
+            ];
+            if merged {
+                row.push(key.sources.join(", "));
+            }
+            row.push(fingerprint);
+            row
         })
         .collect();
 
@@ -94,50 +277,52 @@ pub fn pretty_print_ssh_keys(keys_response: &KeysResponse) {
     );
 }
 
+/// The EXPIRES cell for `key`: its expiry date, followed by how many days are
+/// left if that is within `warning_days` of `today`.
+fn expiry_cell(key: &SSHKey, today: NaiveDate, warning_days: u64) -> String {
+    let Ok(Some(expires)) = key.expires() else {
+        return "never".to_string();
+    };
+    let days_left = (expires - today).num_days();
+    match days_left {
+        1 => format!("{expires} (in 1 day)"),
+        _ if days_left > 0 && days_left.unsigned_abs() <= warning_days => {
+            format!("{expires} (in {days_left} days)")
+        }
+        _ => expires.to_string(),
+    }
+}
+
 /// Private function to fetch SSH keys from the server
 ///
-/// This function handles the HTTP request to the keys server,
-/// validates the response, and parses the JSON into a KeysResponse.
+/// This function requests the keys through the shared HTTP client, then
+/// validates the parsed KeysResponse.
 ///
 /// # Arguments
+/// * `client` - The HTTP client to send the request with
 /// * `server_url` - The base URL of the keys server
+/// * `filter` - Which keys to request, encoded as query parameters
 ///
 /// # Returns
-/// * `Result<KeysResponse>` - The parsed keys response or an error
-fn fetch_keys_from_server(server_url: &str) -> Result<KeysResponse> {
+/// * `Result<Fetched<KeysResponse>>` - The parsed keys response, with the
+///   validators it was served with, or an error
+pub(crate) fn fetch_keys_from_server(
+    client: &HttpClient,
+    server_url: &str,
+    filter: &KeyFilter,
+) -> Result<Fetched<KeysResponse>> {
     let url = format!("{server_url}/keys");
 
-    let client = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .context("Failed to build HTTP client")?;
-    let response = client
-        .get(&url)
-        .header(ACCEPT, "application/json")
-        .send()
-        .context("Failed to send request to keys server")?;
-
-    let status = response.status();
-
-    if !status.is_success() {
-        return Err(anyhow::anyhow!(
-            "Server returned error code: {} - {}",
-            status.as_u16(),
-            status.canonical_reason().unwrap_or("Unknown")
-        ));
-    }
-
-    let keys_response = response
-        .json::<KeysResponse>()
-        .context("Failed to parse JSON response")?;
-    validate_keys_response(&keys_response)?;
-    Ok(keys_response)
+    let mut fetched =
+        client.fetch_json_versioned(&url, &filter.query_pairs(), validate_keys_response)?;
+    filter.retain_client_side(&mut fetched.response);
+    Ok(fetched)
 }
 
 /// Reject server keys that would produce malformed or forged authorized_keys
 /// lines. `key`, `user`, and `name` are interpolated directly into file lines,
 /// so a newline in any of them could inject additional entries, and an empty
-/// key would write a meaningless line.
+/// or corrupt key would write a line sshd can't use.
 fn validate_keys_response(response: &KeysResponse) -> Result<()> {
     for key in &response.keys {
         if key.key.trim().is_empty() {
@@ -153,36 +338,222 @@ fn validate_keys_response(response: &KeysResponse) -> Result<()> {
                 ));
             }
         }
+
+        PublicKey::parse(&key.key).with_context(|| {
+            format!(
+                "Server returned an invalid SSH key for {}@{}",
+                key.user, key.name
+            )
+        })?;
     }
 
     Ok(())
 }
 
-pub fn fetch_ssh_keys(server_url: &str) -> Result<()> {
-    let keys_response = fetch_keys_from_server(server_url)?;
+/// Fetch keys from every server in `servers` and merge them.
+///
+/// With a single server this is just [`fetch_keys_from_server`]. With several,
+/// every server must respond, so one being down can't silently drop its keys
+/// from a forced write; see [`merge_keys_responses`] for how they combine.
+pub(crate) fn fetch_keys_from_servers(
+    servers: &[Server],
+    filter: &KeyFilter,
+) -> Result<KeysResponse> {
+    Ok(merge_fetched_keys(
+        &fetch_keys_responses(servers, filter)?,
+        Local::now().date_naive(),
+    ))
+}
 
-    // Check if the output is being piped (not connected to a terminal)
-    // Use raw/minimal output when piped to another command
-    if !std::io::stdout().is_terminal() {
-        let output = format_keys_for_pipe(&keys_response);
-        if !output.is_empty() {
-            println!("{output}");
+/// Fetch keys from every server in `servers`, without merging them yet.
+pub(crate) fn fetch_keys_responses(
+    servers: &[Server],
+    filter: &KeyFilter,
+) -> Result<ServerResponses<KeysResponse>> {
+    fetch_from_servers(servers, "keys", |client, url| {
+        fetch_keys_from_server(client, url, filter)
+    })
+}
+
+/// Merge the keys fetched from each server, reporting any conflicts, and drop
+/// the keys that have expired by `today`. A single server's response is used
+/// as is.
+pub(crate) fn merge_fetched_keys(
+    responses: &ServerResponses<KeysResponse>,
+    today: NaiveDate,
+) -> KeysResponse {
+    let mut keys_response = if let [(_, fetched)] = responses.as_slice() {
+        fetched.response.clone()
+    } else {
+        let responses: Vec<(&str, KeysResponse)> = responses
+            .iter()
+            .map(|(url, fetched)| (url.as_str(), fetched.response.clone()))
+            .collect();
+        let (keys_response, conflicts) = merge_keys_responses(&responses);
+        report_conflicts(&conflicts);
+        keys_response
+    };
+    drop_expired_keys(&mut keys_response, today);
+    keys_response
+}
+
+/// Everything besides the servers' responses that decides what is written to
+/// an authorized_keys file, for [`LastSync`]. This includes the next date one
+/// of the fetched keys expires on after `today`, so the file is synced again
+/// once that key expires, even if the servers' responses haven't changed.
+pub(crate) fn ssh_sync_settings(
+    mode: WriteMode,
+    filter: &KeyFilter,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    responses: &ServerResponses<KeysResponse>,
+    today: NaiveDate,
+) -> String {
+    let next_expiry = responses
+        .iter()
+        .flat_map(|(_, fetched)| &fetched.response.keys)
+        .filter_map(|key| key.expires().ok().flatten())
+        .filter(|expires| *expires > today)
+        .min();
+    format!("ssh {mode:?} {filter:?} {tag_options:?} {policy:?} {next_expiry:?}")
+}
+
+/// Drop the keys that have expired by `today`, or whose expiry tag can't be
+/// read, so they are never shown, written or offered to sshd. Each one is
+/// reported on stderr.
+fn drop_expired_keys(keys_response: &mut KeysResponse, today: NaiveDate) {
+    keys_response.keys.retain(|key| {
+        let reason = match key.expires() {
+            Ok(Some(expires)) if expires <= today => format!("it expired on {expires}"),
+            Ok(_) => return true,
+            Err(err) => err,
+        };
+        eprintln!(
+            "{}  Skipping key {}@{}: {reason}",
+            "⚠️".yellow().bold(),
+            key.user,
+            key.name
+        );
+        false
+    });
+}
+
+/// Merge the keys served by several servers into one response, recording on
+/// each key the servers it came from.
+///
+/// Keys are matched by their `<type> <blob>`. A key served by more than one
+/// server keeps the user and name from the first to serve it, with the tags of
+/// all of them, and a conflict is reported if the servers disagree on its user
+/// or name.
+fn merge_keys_responses(responses: &[(&str, KeysResponse)]) -> (KeysResponse, Vec<String>) {
+    let mut keys: Vec<SSHKey> = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for (source, response) in responses {
+        for key in &response.keys {
+            let key_part = extract_key_part(&key.key);
+            let Some(merged) = keys
+                .iter_mut()
+                .find(|k| extract_key_part(&k.key) == key_part)
+            else {
+                keys.push(SSHKey {
+                    sources: vec![source.to_string()],
+                    ..key.clone()
+                });
+                continue;
+            };
+
+            if merged.user != key.user || merged.name != key.name {
+                conflicts.push(format!(
+                    "key {key_part} is {}@{} on {} but {}@{} on {source}; keeping {}@{}",
+                    merged.user,
+                    merged.name,
+                    merged.sources[0],
+                    key.user,
+                    key.name,
+                    merged.user,
+                    merged.name
+                ));
+            }
+            for tag in &key.tags {
+                if !merged.tags.contains(tag) {
+                    merged.tags.push(tag.clone());
+                }
+            }
+            if !merged.sources.iter().any(|s| s == source) {
+                merged.sources.push(source.to_string());
+            }
+        }
+    }
+
+    let version = responses
+        .iter()
+        .map(|(source, response)| format!("{} ({source})", response.version))
+        .collect::<Vec<_>>()
+        .join(", ");
+    (KeysResponse { version, keys }, conflicts)
+}
+
+pub fn fetch_ssh_keys(
+    servers: &[Server],
+    filter: &KeyFilter,
+    output: OutputFormat,
+    expiry_warning_days: u64,
+    fingerprint_hash: FingerprintHash,
+) -> Result<()> {
+    let keys_response = fetch_keys_from_servers(servers, filter)?;
+    let listed = ListedKeys {
+        version: &keys_response.version,
+        keys: keys_response
+            .keys
+            .iter()
+            .map(|key| ListedKey {
+                key,
+                fingerprint: fingerprint_hash.fingerprint(PublicKey::parse(&key.key)),
+            })
+            .collect(),
+    };
+    if output.print_structured(&listed)? {
+        return Ok(());
+    }
+
+    // Use raw/minimal output when piped to another command (or when asked
+    // to), and a table otherwise
+    if !output.is_table() {
+        let lines = format_keys_for_pipe(&keys_response);
+        if !lines.is_empty() {
+            println!("{lines}");
         }
         return Ok(());
     }
 
     // Use the pretty print function for interactive terminal output
-    pretty_print_ssh_keys(&keys_response);
+    pretty_print_ssh_keys(&keys_response, expiry_warning_days, fingerprint_hash);
 
     Ok(())
 }
 
+/// The keys as `--output json` and `yaml` print them, each with its
+/// fingerprint.
+#[derive(Serialize)]
+struct ListedKeys<'a> {
+    version: &'a str,
+    keys: Vec<ListedKey<'a>>,
+}
+
+#[derive(Serialize)]
+struct ListedKey<'a> {
+    #[serde(flatten)]
+    key: &'a SSHKey,
+    fingerprint: String,
+}
+
 /// Helper function to format keys for non-TTY output (used for testing)
 fn format_keys_for_pipe(keys_response: &KeysResponse) -> String {
     keys_response
         .keys
         .iter()
-        .map(|key| format!("{} {}@{}", key.key, key.user, key.name))
+        .map(format_server_key)
         .collect::<Vec<_>>()
         .join("\n")
 }
@@ -198,14 +569,20 @@ fn is_ssh_key_type(token: &str) -> bool {
 /// the normalized `<type> <blob>` identity.
 ///
 /// A line may begin with options such as `from="10.0.0.0/8"`, `command="..."`,
-/// or `no-pty` before the key type. We locate the key-type token rather than
-/// assuming it is first, so restricted entries still match the same server key
-/// as their unrestricted equivalent. The options are rejoined with single
-/// spaces (runs of internal whitespace are not preserved).
+/// or `no-pty` before the key type. We locate the key rather than assuming it
+/// is first, so restricted entries still match the same server key as their
+/// unrestricted equivalent: the first type and data that parse as a valid
+/// public key, or failing that the first token that looks like a key type.
+/// The options are rejoined with single spaces (runs of internal whitespace
+/// are not preserved).
 fn split_options_and_key(ssh_line: &str) -> (Option<String>, String) {
     let tokens: Vec<&str> = ssh_line.split_whitespace().collect();
 
-    match tokens.iter().position(|t| is_ssh_key_type(t)) {
+    let key_index = tokens
+        .windows(2)
+        .position(|pair| PublicKey::from_parts(pair[0], pair[1]).is_ok())
+        .or_else(|| tokens.iter().position(|t| is_ssh_key_type(t)));
+    match key_index {
         Some(idx) => {
             let key_part = match tokens.get(idx + 1) {
                 // "ssh-rsa AAAAB..." (type + blob, no options or comment)
@@ -239,31 +616,468 @@ fn extract_key_part(ssh_line: &str) -> String {
     split_options_and_key(ssh_line).1
 }
 
-/// Helper function to format a server key with user@host comment
-fn format_server_key(ssh_key: &SSHKey) -> String {
-    format!("{} {}@{}", ssh_key.key, ssh_key.user, ssh_key.name)
+/// The type of the key in an SSH key line, e.g. `ssh-ed25519`.
+pub(crate) fn key_type(ssh_line: &str) -> Option<String> {
+    extract_key_part(ssh_line)
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+/// The SHA256 fingerprint of the key in an SSH key line, as shown by
+/// `ssh-keygen -l` (e.g. `SHA256:KNrHOH...`), or `None` if it isn't a valid
+/// public key.
+pub(crate) fn key_fingerprint(ssh_line: &str) -> Option<String> {
+    PublicKey::parse(&extract_key_part(ssh_line))
+        .ok()
+        .map(|key| key.fingerprint())
+}
+
+/// Helper function to format a server key with user@host comment, noting
+/// which servers it came from when several were merged
+pub(crate) fn format_server_key(ssh_key: &SSHKey) -> String {
+    let line = format!("{} {}@{}", ssh_key.key, ssh_key.user, ssh_key.name);
+    if ssh_key.sources.is_empty() {
+        line
+    } else {
+        format!("{line} (from {})", ssh_key.sources.join(", "))
+    }
+}
+
+/// Options that only make sense once per authorized_keys line. Any other
+/// option, such as `permitopen`, may be given several times.
+const SINGLE_VALUED_OPTIONS: &[&str] = &[
+    "agent-forwarding",
+    "cert-authority",
+    "command",
+    "expiry-time",
+    "from",
+    "no-touch-required",
+    "principals",
+    "tunnel",
+    "verify-required",
+];
+
+/// authorized_keys options given to server keys by their tags, from the
+/// `[ssh.tag_options]` table, e.g. `ci = 'restrict,command="/usr/bin/deploy"'`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct TagOptions(pub BTreeMap<String, String>);
+
+impl TagOptions {
+    /// Check that each tag's options can be put in front of a key without
+    /// breaking the line.
+    pub fn validate(&self) -> Result<()> {
+        for (tag, options) in &self.0 {
+            split_option_list(options)
+                .map_err(|err| anyhow::anyhow!("[ssh.tag_options] {tag}: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// The options each of `keys` gets from its tags, in the same order, and
+    /// a description of each conflict between them.
+    ///
+    /// A key with several mapped tags gets the options of each, in tag name
+    /// order, with duplicates dropped and `restrict` moved to the front, since
+    /// sshd would otherwise let it undo the permissions listed before it.
+    /// When two tags give different values for an option that can only
+    /// appear once, such as `command`, the first tag's value is used and the
+    /// conflict is reported. A key with an `expires:` tag also gets the
+    /// matching `expiry-time`, which takes precedence over any from
+    /// `tag_options`. Like the other options, it is added to the local
+    /// options of an existing line (see [`merge_local_options`]).
+    pub(crate) fn resolve(&self, keys: &[SSHKey]) -> (Vec<Option<String>>, Vec<String>) {
+        let mut conflicts = Vec::new();
+        let options = keys
+            .iter()
+            .map(|key| {
+                // (option name, option, tag it came from)
+                let mut merged: Vec<(String, String, String)> = Vec::new();
+                if let Ok(Some(expires)) = key.expires() {
+                    merged.push((
+                        "expiry-time".to_string(),
+                        format!("expiry-time=\"{}\"", expires.format("%Y%m%d")),
+                        format!("{EXPIRES_TAG}{expires}"),
+                    ));
+                }
+                for (tag, options) in self.0.iter().filter(|(tag, _)| key.tags.contains(tag)) {
+                    for option in split_option_list(options).unwrap_or_default() {
+                        let name = option_name(&option);
+                        if merged.iter().any(|(_, existing, _)| *existing == option) {
+                            continue;
+                        }
+                        if SINGLE_VALUED_OPTIONS.contains(&name.as_str())
+                            && let Some((_, existing, first_tag)) =
+                                merged.iter().find(|(existing, _, _)| *existing == name)
+                        {
+                            conflicts.push(format!(
+                                "{}@{}: tags {first_tag} and {tag} both set {name}; using {existing} from {first_tag}",
+                                key.user, key.name
+                            ));
+                            continue;
+                        }
+                        merged.push((name, option, tag.clone()));
+                    }
+                }
+                merged.sort_by_key(|(name, _, _)| name != "restrict");
+                (!merged.is_empty()).then(|| {
+                    merged
+                        .into_iter()
+                        .map(|(_, option, _)| option)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+            })
+            .collect();
+        (options, conflicts)
+    }
+
+    /// The authorized_keys lines for `keys`, each with the options its tags
+    /// map to (see [`TagOptions::resolve`]), reporting any conflicts between
+    /// them.
+    pub(crate) fn server_lines(&self, keys: &[SSHKey]) -> Vec<String> {
+        let (options, conflicts) = self.resolve(keys);
+        report_conflicts(&conflicts);
+        keys.iter()
+            .zip(options)
+            .map(|(key, options)| format_server_line(key, options.as_deref()))
+            .collect()
+    }
+}
+
+/// The options for the existing authorized_keys line of `key`: its `local`
+/// options with the `mapped` ones its tags give it added, so a restricted
+/// entry never loses its restriction. When a tag sets an option that can only
+/// appear once, such as `from`, to a different value than the local line, the
+/// local value is kept and the conflict described in `conflicts`.
+fn merge_local_options(
+    key: &SSHKey,
+    local: Option<&str>,
+    mapped: Option<&str>,
+    conflicts: &mut Vec<String>,
+) -> Option<String> {
+    let (Some(local), Some(mapped)) = (local, mapped) else {
+        return local.or(mapped).map(str::to_string);
+    };
+    let local_options = match split_option_list(local) {
+        Ok(local_options) => local_options,
+        Err(err) => {
+            conflicts.push(format!(
+                "{}@{}: can't add the options from its tags to the local ones ({err}); leaving them as they are",
+                key.user, key.name
+            ));
+            return Some(local.to_string());
+        }
+    };
+    // (option name, option)
+    let mut merged: Vec<(String, String)> = local_options
+        .into_iter()
+        .map(|option| (option_name(&option), option))
+        .collect();
+    for option in split_option_list(mapped).unwrap_or_default() {
+        let name = option_name(&option);
+        if merged.iter().any(|(_, existing)| *existing == option) {
+            continue;
+        }
+        if SINGLE_VALUED_OPTIONS.contains(&name.as_str())
+            && let Some((_, existing)) = merged.iter().find(|(existing, _)| *existing == name)
+        {
+            conflicts.push(format!(
+                "{}@{}: the local line and its tags both set {name}; keeping the local {existing}",
+                key.user, key.name
+            ));
+            continue;
+        }
+        merged.push((name, option));
+    }
+    merged.sort_by_key(|(name, _)| name != "restrict");
+    Some(
+        merged
+            .into_iter()
+            .map(|(_, option)| option)
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+/// Split a comma-separated authorized_keys option list, such as
+/// `restrict,command="echo a,b"`, into its options. Commas and spaces inside
+/// double quotes don't split; a backslash escapes a quote.
+fn split_option_list(options: &str) -> Result<Vec<String>, String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut chars = options.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_control() => return Err("options can't contain control characters".into()),
+            '"' => quoted = !quoted,
+            '\\' if quoted => {
+                current.push(c);
+                match chars.next() {
+                    Some(escaped) => current.push(escaped),
+                    None => break,
+                }
+                continue;
+            }
+            ',' if !quoted => {
+                split.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ if c.is_whitespace() && !quoted => {
+                return Err(format!("unquoted whitespace in {options:?}"));
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if quoted {
+        return Err(format!("unterminated quote in {options:?}"));
+    }
+    split.push(current);
+    for option in &split {
+        let name = option_name(option);
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("invalid option {option:?} in {options:?}"));
+        }
+    }
+    Ok(split)
+}
+
+/// The lowercased name of an authorized_keys option, e.g. `command` for
+/// `command="/usr/bin/deploy"`.
+fn option_name(option: &str) -> String {
+    option
+        .split_once('=')
+        .map_or(option, |(name, _)| name)
+        .to_ascii_lowercase()
+}
+
+/// An authorized_keys line for a server key, with `options` in front of it.
+fn format_server_line(ssh_key: &SSHKey, options: Option<&str>) -> String {
+    match options {
+        Some(options) => format!("{options} {}", format_server_key(ssh_key)),
+        None => format_server_key(ssh_key),
+    }
+}
+
+/// Safeguards against writing an authorized_keys file that locks its owner out.
+///
+/// A write is refused when the resulting file would contain no keys at all, or
+/// would remove a key in `must_keep` or `session_keys`, unless `allow_lockout`
+/// is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockoutProtection {
+    /// Write the file even if it would lock someone out.
+    pub allow_lockout: bool,
+    /// Keys that must never be removed, as `<type> <blob>` or full
+    /// authorized_keys lines.
+    pub must_keep: Vec<String>,
+    /// Keys used to authenticate the current SSH session, if known.
+    pub session_keys: Vec<String>,
+}
+
+impl LockoutProtection {
+    /// Build the protection for this process, taking the current session's
+    /// keys from the file named by `SSH_USER_AUTH` (set by sshd when
+    /// `ExposeAuthInfo` is enabled). Outside such a session, or if the file
+    /// can't be read, only the `must_keep` and empty-file checks apply.
+    pub fn from_env(allow_lockout: bool, must_keep: Vec<String>) -> Self {
+        let session_keys = std::env::var_os("SSH_USER_AUTH")
+            .and_then(|auth_path| std::fs::read_to_string(auth_path).ok())
+            .map(|auth_info| parse_session_keys(&auth_info))
+            .unwrap_or_default();
+
+        Self {
+            allow_lockout,
+            must_keep,
+            session_keys,
+        }
+    }
+
+    /// Refuse a write of `new_content` over `existing_content` that would
+    /// leave no keys, or remove a must-keep or session key.
+    fn check(&self, path: &Path, existing_content: &str, new_content: &str) -> Result<()> {
+        if self.allow_lockout {
+            return Ok(());
+        }
+
+        let existing_key_parts = key_parts(existing_content);
+        let new_key_parts = key_parts(new_content);
+        let is_removed = |key: &String| {
+            let key_part = extract_key_part(key);
+            existing_key_parts.contains(&key_part) && !new_key_parts.contains(&key_part)
+        };
+
+        let mut reasons = Vec::new();
+        if new_key_parts.is_empty() {
+            reasons.push("it would contain no keys".to_string());
+        }
+        for key in self.session_keys.iter().filter(|key| is_removed(key)) {
+            reasons.push(format!(
+                "it would remove the key used by the current SSH session ({})",
+                extract_key_part(key)
+            ));
+        }
+        for key in self.must_keep.iter().filter(|key| is_removed(key)) {
+            reasons.push(format!(
+                "it would remove a must-keep key ({})",
+                extract_key_part(key)
+            ));
+        }
+
+        if reasons.is_empty() {
+            return Ok(());
+        }
+
+        Err(anyhow::anyhow!(
+            "Refusing to write {} because {}; pass --allow-lockout to write it anyway",
+            path.display(),
+            reasons.join(", and ")
+        ))
+    }
+}
+
+/// Extract the public keys from the auth info sshd exposes via
+/// `SSH_USER_AUTH`, which lists one method per line (e.g.
+/// `publickey ssh-ed25519 AAAA...`).
+fn parse_session_keys(auth_info: &str) -> Vec<String> {
+    auth_info
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("publickey "))
+        .map(extract_key_part)
+        .collect()
+}
+
+/// The `<type> <blob>` identities of every key line in an authorized_keys
+/// file, skipping blank lines and comments (including managed block markers).
+fn key_parts(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(extract_key_part)
+        .collect()
+}
+
+pub fn write_ssh_keys(
+    servers: &[Server],
+    file_path: &str,
+    filter: &KeyFilter,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    options: &WriteOptions,
+    lockout: &LockoutProtection,
+) -> Result<()> {
+    write_ssh_keys_on(
+        servers,
+        file_path,
+        filter,
+        tag_options,
+        policy,
+        options,
+        lockout,
+        Local::now().date_naive(),
+    )
+}
+
+/// [`write_ssh_keys`] with an explicit date, so tests can sync again once a
+/// key has expired.
+#[allow(clippy::too_many_arguments)]
+fn write_ssh_keys_on(
+    servers: &[Server],
+    file_path: &str,
+    filter: &KeyFilter,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    options: &WriteOptions,
+    lockout: &LockoutProtection,
+    today: NaiveDate,
+) -> Result<()> {
+    // Fetch keys from the servers, and stop there if nothing has changed
+    // since the file was last written
+    let responses = fetch_keys_responses(servers, filter)?;
+    let versions = response_versions(&responses);
+    let last_sync = LastSync::new(
+        servers,
+        file_path,
+        &ssh_sync_settings(options.mode, filter, tag_options, policy, &responses, today),
+    );
+    if last_sync.unchanged(&versions) {
+        return Ok(());
+    }
+
+    apply_ssh_keys(
+        &merge_fetched_keys(&responses, today),
+        &managed_owner(servers),
+        file_path,
+        tag_options,
+        policy,
+        options,
+        lockout,
+    )?;
+    if !options.dry_run {
+        last_sync.record(versions);
+    }
+    Ok(())
 }
 
-pub fn write_ssh_keys(server_url: &str, file_path: &str, force: bool) -> Result<()> {
-    // Fetch keys from the server
-    let keys_response = fetch_keys_from_server(server_url)?;
+/// Write already-fetched keys to an authorized_keys file according to
+/// `options`, with `owner` (see [`managed_owner`]) owning the managed block
+/// and each key given the options its tags map to in `tag_options`. Keys
+/// that break `policy` are skipped, or abort the write.
+/// Split out from [`write_ssh_keys`] so `keys sync` can apply a single fetch
+/// to several files.
+pub(crate) fn apply_ssh_keys(
+    keys_response: &KeysResponse,
+    owner: &str,
+    file_path: &str,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    options: &WriteOptions,
+    lockout: &LockoutProtection,
+) -> Result<()> {
+    let keys_response = &policy.enforce_ssh_keys(keys_response)?;
+    let force = options.mode == WriteMode::Force;
+    let (key_options, conflicts) = tag_options.resolve(&keys_response.keys);
+    report_conflicts(&conflicts);
+    let server_lines: Vec<String> = keys_response
+        .keys
+        .iter()
+        .zip(&key_options)
+        .map(|(key, key_options)| format_server_line(key, key_options.as_deref()))
+        .collect();
 
     // Expand ~ to home directory if present
     let expanded_path = shellexpand::tilde(file_path);
-    let path = std::path::Path::new(expanded_path.as_ref());
+    let path = Path::new(expanded_path.as_ref());
 
     // Read existing authorized_keys file if it exists
-    let existing_lines = if path.exists() {
+    let existing_content = if path.exists() {
         std::fs::read_to_string(path)
             .with_context(|| format!("Failed to read existing file: {}", path.display()))?
-            .lines()
-            .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>()
     } else {
-        Vec::new()
+        String::new()
     };
 
+    if options.mode == WriteMode::Managed {
+        return write_managed_ssh_keys(
+            path,
+            &existing_content,
+            keys_response,
+            &server_lines,
+            owner,
+            options,
+            lockout,
+        );
+    }
+
+    let existing_lines = existing_content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
     // Extract server keys (just the key part for comparison)
     let server_key_parts: Vec<String> = keys_response.keys.iter().map(|k| k.key.clone()).collect();
 
@@ -282,47 +1096,40 @@ pub fn write_ssh_keys(server_url: &str, file_path: &str, force: bool) -> Result<
         .collect();
     let num_local_only = local_only_keys.len();
 
-    // Create directory if it doesn't exist
-    if let Some(parent) = path.parent()
-        && !parent.exists()
-    {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create parent directory: {}", parent.display()))?;
-    }
-
     let mut updated_keys_count = 0;
 
     // Define the file content based on the force flag
     let file_content = if force {
         // Force mode: overwrite with server keys (with user@host comments)
-        keys_response
-            .keys
-            .iter()
-            .map(format_server_key)
-            .collect::<Vec<_>>()
-            .join("\n")
+        server_lines.join("\n")
     } else {
         // Safe mode: merge existing keys with server keys
         let mut result_lines = Vec::new();
+        let mut local_conflicts = Vec::new();
 
         // First, add existing keys, updating comments if the key matches a server key
         for existing_line in &existing_lines {
             let (options, existing_key_part) = split_options_and_key(existing_line);
 
             // Check if this key matches any server key
-            if let Some(server_key) = keys_response
+            if let Some(index) = keys_response
                 .keys
                 .iter()
-                .find(|k| k.key == existing_key_part)
+                .position(|k| k.key == existing_key_part)
             {
                 // Refresh the comment from the server, preserving any local
                 // authorized_keys options (e.g. `from=`, `command=`) so a
                 // restricted entry keeps its restriction instead of being
-                // replaced by an unrestricted line.
-                let new_line = match &options {
-                    Some(opts) => format!("{} {}", opts, format_server_key(server_key)),
-                    None => format_server_key(server_key),
-                };
+                // replaced by an unrestricted line. Options mapped from the
+                // key's tags are added to the local ones.
+                let key = &keys_response.keys[index];
+                let options = merge_local_options(
+                    key,
+                    options.as_deref(),
+                    key_options[index].as_deref(),
+                    &mut local_conflicts,
+                );
+                let new_line = format_server_line(key, options.as_deref());
                 if new_line != *existing_line {
                     updated_keys_count += 1;
                 }
@@ -334,25 +1141,16 @@ pub fn write_ssh_keys(server_url: &str, file_path: &str, force: bool) -> Result<
         }
 
         // Then, add new server keys that weren't already present
-        for server_key in &keys_response.keys {
+        for (server_key, line) in keys_response.keys.iter().zip(&server_lines) {
             if !existing_key_parts.contains(&server_key.key) {
-                result_lines.push(format_server_key(server_key));
+                result_lines.push(line.clone());
             }
         }
+        report_conflicts(&local_conflicts);
 
         result_lines.join("\n")
     };
 
-    // Back up the existing file before overwriting it, so a bad merge or a
-    // surprising server response can be recovered from.
-    if let Some(backup) = backup_existing_file(path)? {
-        println!("📦 Backed up existing file to {}", backup.display());
-    }
-
-    // Write to file
-    std::fs::write(path, file_content)
-        .with_context(|| format!("Failed to write to file: {}", path.display()))?;
-
     // Count stats
     let num_server_keys = keys_response.keys.len();
     let num_existing = existing_lines.len();
@@ -369,6 +1167,50 @@ pub fn write_ssh_keys(server_url: &str, file_path: &str, force: bool) -> Result<
         combined_key_parts.len()
     };
 
+    // In dry-run mode, show what would change and stop before touching disk.
+    // The lockout check still runs, so a dry run reports a write that would be
+    // refused.
+    if options.dry_run {
+        print_unified_diff(path, &existing_content, &file_content);
+    }
+    lockout.check(path, &existing_content, &file_content)?;
+    let change = ChangeSummary {
+        existing: num_existing,
+        remaining: num_final,
+        added: keys_response
+            .keys
+            .iter()
+            .filter(|k| !existing_key_parts.contains(&k.key))
+            .count(),
+        removed: if force {
+            local_only_keys.iter().map(|line| line.as_str()).collect()
+        } else {
+            Vec::new()
+        },
+    };
+    options.limits.check(path, "keys", &change)?;
+    if options.dry_run {
+        if force {
+            println!(
+                "🔍 Dry run: would write {} keys to {} (removing {} local keys)",
+                num_server_keys,
+                path.display(),
+                num_local_only
+            );
+        } else {
+            println!(
+                "🔍 Dry run: would add {} new keys to {} and update comments for {} (keeping {} local keys)",
+                num_final - num_existing,
+                path.display(),
+                updated_keys_count,
+                num_local_only
+            );
+        }
+        return Ok(());
+    }
+
+    replace_file_with_backup(path, file_content.as_bytes(), options.backup_retention)?;
+
     // Print a message about what happened
     if force {
         println!(
@@ -447,15 +1289,126 @@ pub fn write_ssh_keys(server_url: &str, file_path: &str, force: bool) -> Result<
     Ok(())
 }
 
+/// Write the server's keys, as `server_lines`, into the managed block `owner`
+/// owns in an authorized_keys file, replacing the block's previous contents
+/// entirely.
+///
+/// Lines outside the block, including comments, blank lines, and blocks owned
+/// by other servers, are preserved verbatim. That lets a key revoked on the
+/// server be removed from the block without touching hand-added local keys.
+fn write_managed_ssh_keys(
+    path: &Path,
+    existing_content: &str,
+    keys_response: &KeysResponse,
+    server_lines: &[String],
+    owner: &str,
+    options: &WriteOptions,
+    lockout: &LockoutProtection,
+) -> Result<()> {
+    let managed_file = ManagedFile::parse(existing_content, owner)
+        .with_context(|| format!("Failed to parse managed block in {}", path.display()))?;
+
+    let previous_lines: Vec<&String> = managed_file
+        .managed_lines()
+        .iter()
+        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .collect();
+    let previous_key_parts: Vec<String> = previous_lines
+        .iter()
+        .map(|line| extract_key_part(line))
+        .collect();
+
+    let file_content = managed_file.render(server_lines);
+
+    let num_added = keys_response
+        .keys
+        .iter()
+        .filter(|k| !previous_key_parts.contains(&k.key))
+        .count();
+    let removed_lines: Vec<&str> = previous_lines
+        .iter()
+        .zip(&previous_key_parts)
+        .filter(|(_, part)| !keys_response.keys.iter().any(|k| &k.key == *part))
+        .map(|(line, _)| line.as_str())
+        .collect();
+    let num_removed = removed_lines.len();
+    let num_unmanaged = managed_file
+        .unmanaged_lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .count();
+
+    // In dry-run mode, show what would change and stop before touching disk.
+    if options.dry_run {
+        print_unified_diff(path, existing_content, &file_content);
+    }
+    lockout.check(path, existing_content, &file_content)?;
+    let change = ChangeSummary {
+        existing: key_parts(existing_content).len(),
+        remaining: key_parts(&file_content).len(),
+        added: num_added,
+        removed: removed_lines,
+    };
+    options.limits.check(path, "keys", &change)?;
+    if options.dry_run {
+        println!(
+            "🔍 Dry run: would add {} and remove {} keys in the managed block of {} (leaving {} local keys untouched)",
+            num_added,
+            num_removed,
+            path.display(),
+            num_unmanaged
+        );
+        return Ok(());
+    }
+
+    replace_file_with_backup(path, file_content.as_bytes(), options.backup_retention)?;
+
+    println!(
+        "✅ Synced managed block in {} ({} keys: {} added, {} removed; {} local keys untouched)",
+        path.display(),
+        keys_response.keys.len(),
+        num_added,
+        num_removed,
+        num_unmanaged
+    );
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::{CacheFallback, CacheSettings};
+    use crate::commands::ChangeLimits;
+    use crate::utils::list_backups;
     use mockito;
     use std::fs::{self, File};
     use std::io::Write;
     use std::path::PathBuf;
     use tempfile::TempDir;
 
+    // Key data for the keys these tests serve and write: well-formed key blobs,
+    // as keys from the server are checked, but not real keys.
+    const KEY1: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEH7fMXuOUDkolNuTXwTbf/4=";
+    const KEY2: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHaDwaHO80Lyg+vjF+Lt3NI=";
+    const KEY3: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEFBORPoTR1DxT0n/5KbPzTw=";
+    const LOCAL_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEEYCJ984V862HgXfsuIRlVw=";
+    const LOCAL_KEY2: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEGH6onvZFKC7P0lJMP1skZA=";
+    const OLD_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEFfp1LIkQ3UALc+50ZrZdjQ=";
+    const OLD_KEY2: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEGVja/kXA9Sh+nwWlqVcEqU=";
+    const ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIJ3oojEZ22SS70BULrNDwNiExif0b72Zm/TZxfiE+QXN";
+    const ED25519_KEY2: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIJX7mzQBfKQABVP4YHcmybBZIe14fRuwSLcJ5EUU7OF/";
+    const ED25519_KEY3: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIEcCZnq3LK2Nhk7289Gptg4SMNi74meoJeeSO+7sGlgH";
+    const ECDSA_KEY: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBESmucKvE1n9IHir2by/RWoYiEiTHZqXS4xqgG61UcPfCwHt/UpPoliXYop09u6ZumEZeZDSBWZ5zYK2iVccjH0=";
+    const SK_KEY: &str = "AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIDZiizmICboKAO4AV4v3Yzn+hja7h6q9osCu8iQ48m7mAAAABHNzaDo=";
+    const SESSION_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIOz7egPAR1lWgiQXL5ahqFRKuQjIqh0Z1cCIjk6WUTBC";
+    const SESSION_KEY2: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEFVQbr5Z/aaEYSIotvvnkWg=";
+    const BREAKGLASS_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIG6inWSY6Aw4KtPIuRldP0J1CaF2IiLAQ00F2B9Tin/U";
+
     // Helper function to create a temp directory and file
     fn setup_temp_dir_and_file(content: Option<&str>) -> (TempDir, PathBuf) {
         let temp_dir = TempDir::new().unwrap();
@@ -503,37 +1456,51 @@ mod tests {
     #[test]
     fn test_write_ssh_keys_force_mode() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file with content that should be overwritten
-        let existing_content =
-            "ssh-rsa AAAABX old_key user@host\nssh-rsa AAAABY another_old_key user@host";
+        let existing_content = &format!(
+            "ssh-rsa {OLD_KEY} old_key user@host\nssh-rsa {OLD_KEY2} another_old_key user@host"
+        );
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
         // Call function with force=true
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents
         let content = fs::read_to_string(&file_path).unwrap();
         assert_eq!(
             content,
-            "ssh-rsa AAAAB1 user1@key1\nssh-rsa AAAAB2 user2@key2"
+            format!("ssh-rsa {KEY1} user1@key1\nssh-rsa {KEY2} user2@key2")
         );
 
         // Verify it doesn't contain old keys
-        assert!(!content.contains("AAAABX"));
-        assert!(!content.contains("AAAABY"));
+        assert!(!content.contains(OLD_KEY));
+        assert!(!content.contains(OLD_KEY2));
 
         // Cleanup
         drop(temp_dir);
@@ -542,35 +1509,45 @@ mod tests {
     #[test]
     fn test_write_ssh_keys_additive_mode() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file with one key that's also in the response and one that isn't
-        let existing_content = "ssh-rsa AAAAB1\nssh-rsa AAAABZ local_key user@host";
+        let existing_content = &format!("ssh-rsa {KEY1}\nssh-rsa {LOCAL_KEY} local_key user@host");
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
         // Call function with force=false (additive mode)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents - should contain both old and new keys
         let content = fs::read_to_string(&file_path).unwrap();
-        assert!(content.contains("ssh-rsa AAAAB1"));
-        assert!(content.contains("ssh-rsa AAAAB2")); // New key added
-        assert!(content.contains("ssh-rsa AAAABZ")); // Old local key retained
+        assert!(content.contains(&format!("ssh-rsa {KEY1}")));
+        assert!(content.contains(&format!("ssh-rsa {KEY2}"))); // New key added
+        assert!(content.contains(&format!("ssh-rsa {LOCAL_KEY}"))); // Old local key retained
 
-        // Count occurrences of AAAAB1 (should only appear once)
-        let count_key1 = content.matches("AAAAB1").count();
-        assert_eq!(count_key1, 1, "Duplicate key found: AAAAB1");
+        // Count occurrences of KEY1 (should only appear once)
+        let count_key1 = content.matches(KEY1).count();
+        assert_eq!(count_key1, 1, "Duplicate key found: {KEY1}");
 
         // Cleanup
         drop(temp_dir);
@@ -579,15 +1556,17 @@ mod tests {
     #[test]
     fn test_write_ssh_keys_new_file() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
@@ -595,14 +1574,25 @@ mod tests {
         let (temp_dir, file_path) = setup_temp_dir_and_file(None);
 
         // Call function (with either force mode)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file was created with correct contents
         let content = fs::read_to_string(&file_path).unwrap();
         assert_eq!(
             content,
-            "ssh-rsa AAAAB1 user1@key1\nssh-rsa AAAAB2 user2@key2"
+            format!("ssh-rsa {KEY1} user1@key1\nssh-rsa {KEY2} user2@key2")
         );
 
         // Cleanup
@@ -622,11 +1612,26 @@ mod tests {
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file
-        let existing_content = "ssh-rsa AAAABZ local_key user@host";
+        let existing_content = &format!("ssh-rsa {LOCAL_KEY} local_key user@host");
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
-        // Test force mode with empty response (should clear the file)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
+        // Test force mode with empty response (should clear the file once
+        // lockout protection is explicitly overridden)
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection {
+                allow_lockout: true,
+                ..LockoutProtection::default()
+            },
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents (should be empty)
@@ -647,7 +1652,18 @@ mod tests {
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some("existing-content"));
 
         // Call function
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server {
+                retries: 0,
+                ..Server::new(&server_url)
+            }],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
 
         // Should return an error
         assert!(result.is_err());
@@ -670,7 +1686,15 @@ mod tests {
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some("existing-content"));
 
         // Call function
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
 
         // Should return an error
         assert!(result.is_err());
@@ -683,44 +1707,94 @@ mod tests {
         drop(temp_dir);
     }
 
+    #[test]
+    fn test_write_ssh_keys_corrupt_key() {
+        // An ssh-rsa key whose blob says it's ed25519
+        let (server_url, _server) = setup_mock_server(
+            r#"{"version": "1.0.0", "keys": [{"user": "alice", "name": "laptop", "key": "ssh-rsa AAAAC3NzaC1lZDI1NTE5AAAAIKgbVtQOv3INVqwPcv6PgJffaSvhaM0paHE4J0LnMTU2", "tags": []}]}"#,
+        );
+        let (_temp_dir, file_path) = setup_temp_dir_and_file(Some("existing-content"));
+
+        let err = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        )
+        .unwrap_err();
+
+        let message = format!("{err:#}");
+        assert!(message.contains("alice@laptop"), "{message}");
+        assert!(message.contains("actually \"ssh-ed25519\""), "{message}");
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            "existing-content\n"
+        );
+    }
+
     #[test]
     fn test_write_ssh_keys_local_only_keys() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file with one key from the server and two local-only keys
-        let existing_content =
-            "ssh-rsa AAAAB1\nssh-rsa LOCALK1 local1@host\nssh-rsa LOCALK2 local2@host";
+        let existing_content = &format!(
+            "ssh-rsa {KEY1}\nssh-rsa {LOCAL_KEY} local1@host\nssh-rsa {LOCAL_KEY2} local2@host"
+        );
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
         // Call function with force=false (additive mode)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents - should contain both server keys and local keys
         let content = fs::read_to_string(&file_path).unwrap();
-        assert!(content.contains("ssh-rsa AAAAB1"));
-        assert!(content.contains("ssh-rsa LOCALK1"));
-        assert!(content.contains("ssh-rsa LOCALK2"));
+        assert!(content.contains(&format!("ssh-rsa {KEY1}")));
+        assert!(content.contains(&format!("ssh-rsa {LOCAL_KEY}")));
+        assert!(content.contains(&format!("ssh-rsa {LOCAL_KEY2}")));
 
         // Now try with force=true
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents - should only contain the server key
         let content = fs::read_to_string(&file_path).unwrap();
-        assert!(content.contains("ssh-rsa AAAAB1"));
-        assert!(!content.contains("ssh-rsa LOCALK1"));
-        assert!(!content.contains("ssh-rsa LOCALK2"));
+        assert!(content.contains(&format!("ssh-rsa {KEY1}")));
+        assert!(!content.contains(&format!("ssh-rsa {LOCAL_KEY}")));
+        assert!(!content.contains(&format!("ssh-rsa {LOCAL_KEY2}")));
 
         // Cleanup
         drop(temp_dir);
@@ -729,36 +1803,46 @@ mod tests {
     #[test]
     fn test_write_ssh_keys_already_in_sync() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file with the exact same keys that are on the server
-        let existing_content = "ssh-rsa AAAAB1\nssh-rsa AAAAB2";
+        let existing_content = &format!("ssh-rsa {KEY1}\nssh-rsa {KEY2}");
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
         // Call function with force=false (additive mode)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents - should be unchanged
         let content = fs::read_to_string(&file_path).unwrap();
-        assert!(content.contains("ssh-rsa AAAAB1"));
-        assert!(content.contains("ssh-rsa AAAAB2"));
+        assert!(content.contains(&format!("ssh-rsa {KEY1}")));
+        assert!(content.contains(&format!("ssh-rsa {KEY2}")));
 
         // Count occurrences of each key (should only appear once)
-        let count_key1 = content.matches("AAAAB1").count();
-        let count_key2 = content.matches("AAAAB2").count();
-        assert_eq!(count_key1, 1, "Duplicate key found: AAAAB1");
-        assert_eq!(count_key2, 1, "Duplicate key found: AAAAB2");
+        let count_key1 = content.matches(KEY1).count();
+        let count_key2 = content.matches(KEY2).count();
+        assert_eq!(count_key1, 1, "Duplicate key found: {KEY1}");
+        assert_eq!(count_key2, 1, "Duplicate key found: {KEY2}");
 
         // Cleanup
         drop(temp_dir);
@@ -771,22 +1855,25 @@ mod tests {
             version: "1.2.3".to_string(),
             keys: vec![
                 SSHKey {
-                    key: "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC7".to_string(),
+                    key: format!("ssh-rsa {KEY1}"),
                     user: "alice".to_string(),
                     name: "work-laptop".to_string(),
                     tags: vec!["dev".to_string(), "work".to_string()],
+                    sources: Vec::new(),
                 },
                 SSHKey {
-                    key: "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAI".to_string(),
+                    key: format!("ssh-ed25519 {ED25519_KEY}"),
                     user: "bob".to_string(),
                     name: "home-desktop".to_string(),
                     tags: vec!["personal".to_string()],
+                    sources: Vec::new(),
                 },
                 SSHKey {
-                    key: "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQD".to_string(),
+                    key: format!("ssh-rsa {KEY2}"),
                     user: "charlie".to_string(),
                     name: "server".to_string(),
                     tags: vec![],
+                    sources: Vec::new(),
                 },
             ],
         };
@@ -794,7 +1881,11 @@ mod tests {
         // This test primarily verifies the function doesn't panic and handles the data correctly
         // Since pretty_print_ssh_keys outputs to stdout, we can't easily capture and verify output
         // in this test environment, but we can verify it completes without errors
-        pretty_print_ssh_keys(&keys_response);
+        pretty_print_ssh_keys(
+            &keys_response,
+            DEFAULT_EXPIRY_WARNING_DAYS,
+            FingerprintHash::Sha256,
+        );
     }
 
     #[test]
@@ -806,21 +1897,27 @@ mod tests {
         };
 
         // Should handle empty keys gracefully
-        pretty_print_ssh_keys(&keys_response);
+        pretty_print_ssh_keys(
+            &keys_response,
+            DEFAULT_EXPIRY_WARNING_DAYS,
+            FingerprintHash::Sha256,
+        );
     }
 
     #[test]
     fn test_fetch_ssh_keys_piped_output() {
         // Setup mock server
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
@@ -828,7 +1925,13 @@ mod tests {
         // In a real test environment, we can't easily mock this behavior
         // This test verifies the function completes successfully, but the actual output format
         // depends on whether the test is run in a TTY or not
-        let result = fetch_ssh_keys(&server_url);
+        let result = fetch_ssh_keys(
+            &[Server::new(&server_url)],
+            &KeyFilter::default(),
+            OutputFormat::Auto,
+            DEFAULT_EXPIRY_WARNING_DAYS,
+            FingerprintHash::Sha256,
+        );
         assert!(result.is_ok(), "fetch_ssh_keys failed: {:?}", result.err());
     }
 
@@ -839,16 +1942,18 @@ mod tests {
             version: "1.0.0".to_string(),
             keys: vec![
                 SSHKey {
-                    key: "ssh-rsa AAAAB1".to_string(),
+                    key: format!("ssh-rsa {KEY1}"),
                     user: "user1".to_string(),
                     name: "key1".to_string(),
                     tags: vec!["dev".to_string()],
+                    sources: Vec::new(),
                 },
                 SSHKey {
-                    key: "ssh-ed25519 AAAAC1".to_string(),
+                    key: format!("ssh-ed25519 {ED25519_KEY}"),
                     user: "user2".to_string(),
                     name: "key2".to_string(),
                     tags: vec!["prod".to_string()],
+                    sources: Vec::new(),
                 },
             ],
         };
@@ -856,7 +1961,111 @@ mod tests {
         let output = format_keys_for_pipe(&keys_response);
         assert_eq!(
             output,
-            "ssh-rsa AAAAB1 user1@key1\nssh-ed25519 AAAAC1 user2@key2"
+            format!("ssh-rsa {KEY1} user1@key1\nssh-ed25519 {ED25519_KEY} user2@key2")
+        );
+    }
+
+    #[test]
+    fn test_merge_keys_responses_tracks_sources_and_conflicts() {
+        let response = |keys: &[(&str, &str, &str)]| KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: keys
+                .iter()
+                .map(|(key, user, tag)| SSHKey {
+                    key: key.to_string(),
+                    user: user.to_string(),
+                    name: "laptop".to_string(),
+                    tags: vec![tag.to_string()],
+                    sources: Vec::new(),
+                })
+                .collect(),
+        };
+        let team = response(&[
+            (&format!("ssh-ed25519 {ED25519_KEY}"), "alice", "team"),
+            (&format!("ssh-ed25519 {ED25519_KEY2}"), "bob", "team"),
+        ]);
+        let break_glass = response(&[
+            (
+                &format!("ssh-ed25519 {ED25519_KEY}"),
+                "admin",
+                "break-glass",
+            ),
+            (
+                &format!("ssh-ed25519 {ED25519_KEY3}"),
+                "admin",
+                "break-glass",
+            ),
+        ]);
+
+        let (merged, conflicts) = merge_keys_responses(&[
+            ("https://team.example.com", team),
+            ("https://glass.example.com", break_glass),
+        ]);
+
+        assert_eq!(
+            merged.version,
+            "1.0.0 (https://team.example.com), 1.0.0 (https://glass.example.com)"
+        );
+        assert_eq!(
+            format_keys_for_pipe(&merged),
+            format!(
+                "ssh-ed25519 {ED25519_KEY} alice@laptop (from https://team.example.com, https://glass.example.com)\n\
+             ssh-ed25519 {ED25519_KEY2} bob@laptop (from https://team.example.com)\n\
+             ssh-ed25519 {ED25519_KEY3} admin@laptop (from https://glass.example.com)"
+            )
+        );
+        assert_eq!(merged.keys[0].tags, vec!["team", "break-glass"]);
+        assert_eq!(
+            conflicts,
+            vec![format!(
+                "key ssh-ed25519 {ED25519_KEY} is alice@laptop on https://team.example.com but \
+                 admin@laptop on https://glass.example.com; keeping alice@laptop"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_write_ssh_keys_fails_if_any_server_fails() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [{{"key": "ssh-rsa {KEY1}", "user": "alice", "name": "laptop", "tags": []}}]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+        let (_temp_dir, file_path) =
+            setup_temp_dir_and_file(Some(&format!("ssh-rsa {OLD_KEY} old@key")));
+
+        let result = write_ssh_keys(
+            &[
+                Server::new(&server_url),
+                Server {
+                    retries: 0,
+                    ..Server::new("http://invalid-url-that-does-not-exist.local")
+                },
+            ],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+
+        let message = format!("{:#}", result.unwrap_err());
+        assert!(
+            message
+                .contains("Failed to fetch keys from http://invalid-url-that-does-not-exist.local"),
+            "{message}"
+        );
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            format!("ssh-rsa {OLD_KEY} old@key\n")
         );
     }
 
@@ -878,56 +2087,65 @@ mod tests {
         let keys_response = KeysResponse {
             version: "1.0.0".to_string(),
             keys: vec![SSHKey {
-                key: "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC7".to_string(),
+                key: format!("ssh-rsa {KEY1}"),
                 user: "alice".to_string(),
                 name: "laptop".to_string(),
                 tags: vec!["work".to_string(), "dev".to_string()],
+                sources: Vec::new(),
             }],
         };
 
         let output = format_keys_for_pipe(&keys_response);
-        assert_eq!(
-            output,
-            "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC7 alice@laptop"
-        );
+        assert_eq!(output, format!("ssh-rsa {KEY1} alice@laptop"));
     }
 
     #[test]
     fn test_write_ssh_keys_update_comments() {
         // Setup mock server with keys that have user@host info
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "newuser", "name": "newname", "tags": ["dev"]},
-                {"key": "ssh-rsa AAAAB2", "user": "user2", "name": "key2", "tags": ["prod"]}
+                {{"key": "ssh-rsa {KEY1}", "user": "newuser", "name": "newname", "tags": ["dev"]}},
+                {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["prod"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
         // Setup existing file with keys that have old comments
-        let existing_content = "ssh-rsa AAAAB1 olduser@oldname\nssh-rsa AAAAB3 localuser@localhost";
+        let existing_content =
+            &format!("ssh-rsa {KEY1} olduser@oldname\nssh-rsa {KEY3} localuser@localhost");
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
         // Call function with force=false (additive mode)
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         // Verify file contents
         let content = fs::read_to_string(&file_path).unwrap();
 
-        // Should contain the updated comment for AAAAB1
-        assert!(content.contains("ssh-rsa AAAAB1 newuser@newname"));
+        // Should contain the updated comment for KEY1
+        assert!(content.contains(&format!("ssh-rsa {KEY1} newuser@newname")));
 
-        // Should contain the new key AAAAB2
-        assert!(content.contains("ssh-rsa AAAAB2 user2@key2"));
+        // Should contain the new key KEY2
+        assert!(content.contains(&format!("ssh-rsa {KEY2} user2@key2")));
 
-        // Should still contain the local-only key AAAAB3
-        assert!(content.contains("ssh-rsa AAAAB3 localuser@localhost"));
+        // Should still contain the local-only key KEY3
+        assert!(content.contains(&format!("ssh-rsa {KEY3} localuser@localhost")));
 
-        // Should NOT contain the old comment for AAAAB1
+        // Should NOT contain the old comment for KEY1
         assert!(!content.contains("olduser@oldname"));
 
         // Count lines - should be 3 lines total
@@ -941,27 +2159,45 @@ mod tests {
         drop(temp_dir);
     }
 
+    #[test]
+    fn test_key_fingerprint() {
+        // As reported by `ssh-keygen -l`
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U";
+        assert_eq!(
+            key_fingerprint(&format!("no-pty {key} keys-server")).as_deref(),
+            Some("SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys")
+        );
+        assert_eq!(key_type(key).as_deref(), Some("ssh-ed25519"));
+
+        assert_eq!(key_fingerprint("ssh-ed25519 not!base64"), None);
+        assert_eq!(key_fingerprint("ssh-ed25519"), None);
+    }
+
     #[test]
     fn test_extract_key_part() {
         // Test with comment
         assert_eq!(
-            extract_key_part("ssh-rsa AAAAB123 user@host"),
-            "ssh-rsa AAAAB123"
+            extract_key_part(&format!("ssh-rsa {KEY1} user@host")),
+            format!("ssh-rsa {KEY1}")
         );
 
         // Test without comment
-        assert_eq!(extract_key_part("ssh-rsa AAAAB123"), "ssh-rsa AAAAB123");
+        assert_eq!(
+            extract_key_part(&format!("ssh-rsa {KEY1}")),
+            format!("ssh-rsa {KEY1}")
+        );
 
         // Test with extra whitespace
         assert_eq!(
-            extract_key_part("  ssh-rsa AAAAB123   user@host  "),
-            "ssh-rsa AAAAB123"
+            extract_key_part(&format!("  ssh-rsa {KEY1}   user@host  ")),
+            format!("ssh-rsa {KEY1}")
         );
 
         // Test with multiple comment parts
         assert_eq!(
-            extract_key_part("ssh-rsa AAAAB123 user@host some extra info"),
-            "ssh-rsa AAAAB123"
+            extract_key_part(&format!("ssh-rsa {KEY1} user@host some extra info")),
+            format!("ssh-rsa {KEY1}")
         );
 
         // Test edge case - only key type
@@ -970,22 +2206,26 @@ mod tests {
         // Leading authorized_keys options must be skipped so the identity
         // matches the same key without options.
         assert_eq!(
-            extract_key_part(r#"from="10.0.0.0/8" ssh-ed25519 AAAA user@host"#),
-            "ssh-ed25519 AAAA"
+            extract_key_part(&format!(
+                r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} user@host"#
+            )),
+            format!("ssh-ed25519 {ED25519_KEY}")
         );
         assert_eq!(
-            extract_key_part(r#"no-pty,command="/bin/false" ssh-rsa AAAAB123"#),
-            "ssh-rsa AAAAB123"
+            extract_key_part(&format!(r#"no-pty,command="/bin/false" ssh-rsa {KEY1}"#)),
+            format!("ssh-rsa {KEY1}")
         );
 
         // ECDSA and FIDO/U2F (sk-) key types are recognized too.
         assert_eq!(
-            extract_key_part("ecdsa-sha2-nistp256 AAAAE2 user@host"),
-            "ecdsa-sha2-nistp256 AAAAE2"
+            extract_key_part(&format!("ecdsa-sha2-nistp256 {ECDSA_KEY} user@host")),
+            format!("ecdsa-sha2-nistp256 {ECDSA_KEY}")
         );
         assert_eq!(
-            extract_key_part(r#"from="1.2.3.4" sk-ssh-ed25519@openssh.com AAAASK"#),
-            "sk-ssh-ed25519@openssh.com AAAASK"
+            extract_key_part(&format!(
+                r#"from="1.2.3.4" sk-ssh-ed25519@openssh.com {SK_KEY}"#
+            )),
+            format!("sk-ssh-ed25519@openssh.com {SK_KEY}")
         );
     }
 
@@ -993,16 +2233,18 @@ mod tests {
     fn test_split_options_and_key() {
         // No options: prefix is None.
         assert_eq!(
-            split_options_and_key("ssh-rsa AAAAB123 user@host"),
-            (None, "ssh-rsa AAAAB123".to_string())
+            split_options_and_key(&format!("ssh-rsa {KEY1} user@host")),
+            (None, format!("ssh-rsa {KEY1}"))
         );
 
         // Options prefix is captured verbatim (rejoined with single spaces).
         assert_eq!(
-            split_options_and_key(r#"from="10.0.0.0/8" ssh-ed25519 AAAA user@host"#),
+            split_options_and_key(&format!(
+                r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} user@host"#
+            )),
             (
                 Some(r#"from="10.0.0.0/8""#.to_string()),
-                "ssh-ed25519 AAAA".to_string()
+                format!("ssh-ed25519 {ED25519_KEY}")
             )
         );
 
@@ -1018,21 +2260,32 @@ mod tests {
         // A restricted local entry that matches a server key must keep its
         // options (the `from=` restriction) rather than gaining a second,
         // unrestricted line.
-        let mock_response = r#"
-        {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-ed25519 AAAA", "user": "user1", "name": "key1", "tags": ["dev"]}
+                {{"key": "ssh-ed25519 {ED25519_KEY}", "user": "user1", "name": "key1", "tags": ["dev"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
 
         let (server_url, _server) = setup_mock_server(mock_response);
 
-        let existing_content = r#"from="10.0.0.0/8" ssh-ed25519 AAAA olduser@oldhost"#;
+        let existing_content =
+            &format!(r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} olduser@oldhost"#);
         let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), false);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
         let content = fs::read_to_string(&file_path).unwrap();
@@ -1041,127 +2294,1364 @@ mod tests {
         // Exactly one line for this key, and it retains the restriction while
         // refreshing the comment from the server.
         assert_eq!(lines.len(), 1, "unexpected extra line: {content}");
-        assert_eq!(lines[0], r#"from="10.0.0.0/8" ssh-ed25519 AAAA user1@key1"#);
+        assert_eq!(
+            lines[0],
+            format!(r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} user1@key1"#)
+        );
 
         drop(temp_dir);
     }
 
+    fn tag_options(entries: &[(&str, &str)]) -> TagOptions {
+        TagOptions(
+            entries
+                .iter()
+                .map(|(tag, options)| (tag.to_string(), options.to_string()))
+                .collect(),
+        )
+    }
+
+    fn tagged_key(tags: &[&str]) -> SSHKey {
+        SSHKey {
+            key: format!("ssh-ed25519 {ED25519_KEY}"),
+            user: "alice".to_string(),
+            name: "ci".to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            sources: Vec::new(),
+        }
+    }
+
     #[test]
-    fn test_write_ssh_keys_backs_up_existing_file() {
-        let mock_response = r#"
-        {
-            "version": "1.0.0",
-            "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]}
+    fn test_split_option_list() {
+        assert_eq!(
+            split_option_list(r#"restrict,command="echo a, \"b\"",permitopen="*:22""#).unwrap(),
+            vec![
+                "restrict",
+                r#"command="echo a, \"b\"""#,
+                r#"permitopen="*:22""#
             ]
-        }
-        "#;
+        );
+        assert!(split_option_list("restrict, no-pty").is_err());
+        assert!(split_option_list(r#"command="echo"#).is_err());
+        assert!(split_option_list("restrict,,no-pty").is_err());
+        assert!(split_option_list("").is_err());
+        assert!(split_option_list(&format!("no-pty\nssh-ed25519 {ED25519_KEY}")).is_err());
+    }
 
-        let (server_url, _server) = setup_mock_server(mock_response);
+    #[test]
+    fn test_tag_options_merge_in_tag_order() {
+        let options = tag_options(&[
+            ("jump", r#"restrict,port-forwarding,permitopen="*:22""#),
+            ("ci", r#"restrict,command="/usr/local/bin/deploy""#),
+            ("bastion", r#"permitopen="*:2222""#),
+        ]);
+        let keys = vec![tagged_key(&["jump", "ci", "bastion"]), tagged_key(&["dev"])];
+
+        let (resolved, conflicts) = options.resolve(&keys);
+        assert_eq!(
+            resolved,
+            vec![
+                Some(
+                    r#"restrict,permitopen="*:2222",command="/usr/local/bin/deploy",port-forwarding,permitopen="*:22""#
+                        .to_string()
+                ),
+                None,
+            ]
+        );
+        assert!(conflicts.is_empty(), "{conflicts:?}");
+    }
 
-        let existing_content = "ssh-rsa AAAABOLD olduser@oldhost";
-        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+    #[test]
+    fn test_tag_options_report_conflicts() {
+        let options = tag_options(&[
+            ("backup", r#"command="/usr/bin/backup""#),
+            ("ci", r#"restrict,command="/usr/local/bin/deploy""#),
+        ]);
 
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
-        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+        let (resolved, conflicts) = options.resolve(&[tagged_key(&["ci", "backup"])]);
+        assert_eq!(
+            resolved,
+            vec![Some(r#"restrict,command="/usr/bin/backup""#.to_string())]
+        );
+        assert_eq!(
+            conflicts,
+            vec![
+                r#"alice@ci: tags backup and ci both set command; using command="/usr/bin/backup" from backup"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_local_options() {
+        let key = tagged_key(&["ci"]);
+        let merge = |local: Option<&str>, mapped: Option<&str>| {
+            let mut conflicts = Vec::new();
+            let merged = merge_local_options(&key, local, mapped, &mut conflicts);
+            (merged, conflicts)
+        };
 
-        // A `.bak` with the pre-write contents sits alongside the file.
-        let backup_path = file_path.with_file_name("authorized_keys.bak");
-        assert!(backup_path.exists(), "backup was not created");
+        assert_eq!(merge(None, None), (None, vec![]));
         assert_eq!(
-            fs::read_to_string(&backup_path).unwrap(),
-            format!("{existing_content}\n")
+            merge(Some("no-pty"), None),
+            (Some("no-pty".to_string()), vec![])
+        );
+        assert_eq!(
+            merge(None, Some("restrict")),
+            (Some("restrict".to_string()), vec![])
+        );
+        // Every local option is kept, with the mapped ones added and
+        // `restrict` moved to the front
+        assert_eq!(
+            merge(
+                Some(r#"from="10.0.0.0/8",no-pty"#),
+                Some(r#"restrict,no-pty,command="/usr/local/bin/deploy""#)
+            ),
+            (
+                Some(
+                    r#"restrict,from="10.0.0.0/8",no-pty,command="/usr/local/bin/deploy""#
+                        .to_string()
+                ),
+                vec![]
+            )
+        );
+        // A local value is never replaced
+        assert_eq!(
+            merge(
+                Some(r#"command="/usr/bin/backup""#),
+                Some(r#"restrict,command="/usr/local/bin/deploy""#)
+            ),
+            (
+                Some(r#"restrict,command="/usr/bin/backup""#.to_string()),
+                vec![
+                    r#"alice@ci: the local line and its tags both set command; keeping the local command="/usr/bin/backup""#
+                        .to_string()
+                ]
+            )
         );
+    }
 
-        drop(temp_dir);
+    #[test]
+    fn test_ssh_key_expires() {
+        let date = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        assert_eq!(tagged_key(&["dev"]).expires(), Ok(None));
+        assert_eq!(
+            tagged_key(&["expires:2026-12-31", "dev"]).expires(),
+            Ok(Some(date("2026-12-31")))
+        );
+        // Several servers may each add one; the earliest wins
+        assert_eq!(
+            tagged_key(&["expires:2026-12-31", "expires:2026-06-30"]).expires(),
+            Ok(Some(date("2026-06-30")))
+        );
+        assert_eq!(
+            tagged_key(&["expires:31/12/2026"]).expires(),
+            Err(
+                r#"invalid expiry tag "expires:31/12/2026" (expected expires:YYYY-MM-DD)"#
+                    .to_string()
+            )
+        );
     }
 
     #[test]
-    fn test_validate_keys_response_rejects_newline_in_field() {
-        // A newline in the name field could forge a second authorized_keys line.
-        let response = KeysResponse {
+    fn test_drop_expired_keys() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let mut keys_response = KeysResponse {
             version: "1.0.0".to_string(),
-            keys: vec![SSHKey {
-                key: "ssh-rsa AAAAB1".to_string(),
-                user: "user1".to_string(),
-                name: "key1\nssh-rsa INJECTED attacker@evil".to_string(),
-                tags: vec![],
-            }],
+            keys: vec![
+                tagged_key(&["expires:2026-10-19"]),
+                tagged_key(&["expires:2026-10-18"]),
+                tagged_key(&["expires:2025-01-01"]),
+                tagged_key(&["expires:soon"]),
+                tagged_key(&["dev"]),
+            ],
         };
 
-        let result = validate_keys_response(&response);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("line break"));
+        drop_expired_keys(&mut keys_response, today);
+        let tags: Vec<&[String]> = keys_response.keys.iter().map(|k| &k.tags[..]).collect();
+        assert_eq!(
+            tags,
+            vec![
+                &["expires:2026-10-19".to_string()][..],
+                &["dev".to_string()][..]
+            ]
+        );
     }
 
     #[test]
-    fn test_validate_keys_response_rejects_empty_key() {
-        let response = KeysResponse {
-            version: "1.0.0".to_string(),
-            keys: vec![SSHKey {
-                key: "   ".to_string(),
-                user: "user1".to_string(),
-                name: "key1".to_string(),
-                tags: vec![],
-            }],
+    fn test_write_ssh_keys_drops_key_once_expired() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", "\"v1\"")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": []}},
+                        {{"key": "ssh-rsa {KEY2}", "user": "user2", "name": "key2", "tags": ["expires:2026-10-20"]}}
+                    ]
+                }}"#),
+            )
+            .expect(1)
+            .create();
+        let cache_dir = TempDir::new().unwrap();
+        let servers = [Server {
+            cache: Some(CacheSettings {
+                dir: cache_dir.path().to_path_buf(),
+                fallback: CacheFallback::Never,
+                max_age: std::time::Duration::from_secs(3600),
+            }),
+            ..Server::new(&server.url())
+        }];
+        let (_temp_dir, file_path) = setup_temp_dir_and_file(None);
+        let write = |today: &str| {
+            write_ssh_keys_on(
+                &servers,
+                file_path.to_str().unwrap(),
+                &KeyFilter::default(),
+                &TagOptions::default(),
+                &KeyPolicy::default(),
+                &WriteOptions {
+                    mode: WriteMode::Managed,
+                    ..WriteOptions::default()
+                },
+                &LockoutProtection::default(),
+                NaiveDate::parse_from_str(today, "%Y-%m-%d").unwrap(),
+            )
+            .unwrap();
+            fs::read_to_string(&file_path).unwrap()
         };
 
-        let result = validate_keys_response(&response);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("empty key"));
+        let expiring = KEY2;
+        assert!(write("2026-10-18").contains(expiring));
+
+        let not_modified = server
+            .mock("GET", "/keys")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .with_header("etag", "\"v1\"")
+            .expect(2)
+            .create();
+
+        // The server's response hasn't changed, but the key has expired since
+        // the file was written, so it is synced again without the key
+        let content = write("2026-10-20");
+        assert!(!content.contains(expiring));
+        assert!(content.contains(&format!("{KEY1} user1@key1")));
+
+        // With no more keys left to expire, the next sync is skipped as usual
+        let mtime = fs::metadata(&file_path).unwrap().modified().unwrap();
+        write("2026-10-21");
+        assert_eq!(fs::metadata(&file_path).unwrap().modified().unwrap(), mtime);
+
+        not_modified.assert();
     }
 
     #[test]
-    fn test_write_ssh_keys_rejects_malformed_server_response() {
-        // A CR/LF in a server field must abort the write, leaving any existing
-        // file untouched.
-        let mock_response = r#"
-        {
+    fn test_expiry_cell() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let cell = |tags: &[&str]| expiry_cell(&tagged_key(tags), today, 14);
+        assert_eq!(cell(&[]), "never");
+        assert_eq!(cell(&["expires:2026-10-19"]), "2026-10-19 (in 1 day)");
+        assert_eq!(cell(&["expires:2026-11-01"]), "2026-11-01 (in 14 days)");
+        assert_eq!(cell(&["expires:2026-11-02"]), "2026-11-02");
+    }
+
+    #[test]
+    fn test_tag_options_add_expiry_time() {
+        let options = tag_options(&[("ci", r#"restrict,expiry-time="20300101""#)]);
+
+        let (resolved, conflicts) = options.resolve(&[
+            tagged_key(&["expires:2026-12-31"]),
+            tagged_key(&["ci", "expires:2026-12-31"]),
+        ]);
+        assert_eq!(
+            resolved,
+            vec![
+                Some(r#"expiry-time="20261231""#.to_string()),
+                Some(r#"restrict,expiry-time="20261231""#.to_string()),
+            ]
+        );
+        assert_eq!(
+            conflicts,
+            vec![
+                r#"alice@ci: tags expires:2026-12-31 and ci both set expiry-time; using expiry-time="20261231" from expires:2026-12-31"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_ssh_keys_applies_tag_options() {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1\ninjected", "tags": []}
+                {{"key": "ssh-ed25519 {ED25519_KEY}", "user": "deploy", "name": "ci", "tags": ["ci"]}},
+                {{"key": "ssh-ed25519 {ED25519_KEY2}", "user": "alice", "name": "laptop", "tags": ["dev"]}},
+                {{"key": "ssh-ed25519 {ED25519_KEY3}", "user": "bob", "name": "laptop", "tags": ["dev"]}}
             ]
-        }
-        "#;
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
-        let (temp_dir, file_path) = setup_temp_dir_and_file(Some("ssh-rsa EXISTING me@host"));
+        // The CI key was added by hand without its restriction, and bob's key
+        // has a local one
+        let existing_content = &format!(
+            "ssh-ed25519 {ED25519_KEY} deploy@ci\nno-pty ssh-ed25519 {ED25519_KEY3} bob@laptop"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
-        assert!(result.is_err());
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &tag_options(&[("ci", r#"restrict,command="/usr/local/bin/deploy""#)]),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
-        // Existing file is left as-is and no backup was written.
         let content = fs::read_to_string(&file_path).unwrap();
-        assert_eq!(content, "ssh-rsa EXISTING me@host\n");
-        assert!(!file_path.with_file_name("authorized_keys.bak").exists());
+        assert_eq!(
+            content,
+            format!(
+                "restrict,command=\"/usr/local/bin/deploy\" ssh-ed25519 {ED25519_KEY} deploy@ci\n\
+             no-pty ssh-ed25519 {ED25519_KEY3} bob@laptop\n\
+             ssh-ed25519 {ED25519_KEY2} alice@laptop"
+            )
+        );
 
         drop(temp_dir);
     }
 
     #[test]
-    fn test_write_ssh_keys_no_backup_for_new_file() {
-        let mock_response = r#"
-        {
+    fn test_write_ssh_keys_tag_options_keep_local_restriction() {
+        let mock_response = &format!(
+            r#"
+        {{
             "version": "1.0.0",
             "keys": [
-                {"key": "ssh-rsa AAAAB1", "user": "user1", "name": "key1", "tags": ["dev"]}
+                {{"key": "ssh-ed25519 {ED25519_KEY}", "user": "deploy", "name": "ci", "tags": ["ci"]}}
             ]
-        }
-        "#;
-
+        }}
+        "#
+        );
         let (server_url, _server) = setup_mock_server(mock_response);
 
-        // No existing file.
-        let (temp_dir, file_path) = setup_temp_dir_and_file(None);
+        // The host admin limited the CI key to the internal network
+        let existing_content = &format!(r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} deploy@ci"#);
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
 
-        let result = write_ssh_keys(&server_url, file_path.to_str().unwrap(), true);
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &tag_options(&[("ci", r#"restrict,command="/usr/local/bin/deploy""#)]),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
         assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
 
-        assert!(
-            !file_path.with_file_name("authorized_keys.bak").exists(),
-            "no backup should be created when the file did not previously exist"
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                r#"restrict,from="10.0.0.0/8",command="/usr/local/bin/deploy" ssh-ed25519 {ED25519_KEY} deploy@ci"#
+            )
         );
 
         drop(temp_dir);
     }
+
+    #[test]
+    fn test_write_ssh_keys_expiry_keeps_local_restriction() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-ed25519 {ED25519_KEY}", "user": "contractor", "name": "laptop", "tags": ["expires:2999-12-31"]}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let existing_content =
+            &format!(r#"from="10.0.0.0/8" ssh-ed25519 {ED25519_KEY} contractor@laptop"#);
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions::default(),
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        // The expiry is added to the local restriction rather than replacing it
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                r#"from="10.0.0.0/8",expiry-time="29991231" ssh-ed25519 {ED25519_KEY} contractor@laptop"#
+            )
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_backs_up_existing_file() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}}
+            ]
+        }}
+        "#
+        );
+
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let existing_content = &format!("ssh-rsa {OLD_KEY} olduser@oldhost");
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        // A timestamped `.bak` with the pre-write contents sits alongside the
+        // file.
+        let backups = list_backups(&file_path).unwrap();
+        assert_eq!(backups.len(), 1, "backup was not created");
+        let backup_path = &backups[0].path;
+        assert_eq!(
+            fs::read_to_string(backup_path).unwrap(),
+            format!("{existing_content}\n")
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_validate_keys_response_rejects_newline_in_field() {
+        // A newline in the name field could forge a second authorized_keys line.
+        let response = KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![SSHKey {
+                key: format!("ssh-rsa {KEY1}"),
+                user: "user1".to_string(),
+                name: "key1\nssh-rsa INJECTED attacker@evil".to_string(),
+                tags: vec![],
+                sources: Vec::new(),
+            }],
+        };
+
+        let result = validate_keys_response(&response);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("line break"));
+    }
+
+    #[test]
+    fn test_validate_keys_response_rejects_empty_key() {
+        let response = KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![SSHKey {
+                key: "   ".to_string(),
+                user: "user1".to_string(),
+                name: "key1".to_string(),
+                tags: vec![],
+                sources: Vec::new(),
+            }],
+        };
+
+        let result = validate_keys_response(&response);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("empty key"));
+    }
+
+    #[test]
+    fn test_write_ssh_keys_rejects_malformed_server_response() {
+        // A CR/LF in a server field must abort the write, leaving any existing
+        // file untouched.
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1\ninjected", "tags": []}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let (temp_dir, file_path) =
+            setup_temp_dir_and_file(Some(&format!("ssh-rsa {LOCAL_KEY} me@host")));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_err());
+
+        // Existing file is left as-is and no backup was written.
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("ssh-rsa {LOCAL_KEY} me@host\n"));
+        assert!(list_backups(&file_path).unwrap().is_empty());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_no_backup_for_new_file() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}}
+            ]
+        }}
+        "#
+        );
+
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        // No existing file.
+        let (temp_dir, file_path) = setup_temp_dir_and_file(None);
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        assert!(
+            list_backups(&file_path).unwrap().is_empty(),
+            "no backup should be created when the file did not previously exist"
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_key_filter_query_pairs() {
+        // An empty filter adds no query parameters.
+        assert!(KeyFilter::default().query_pairs().is_empty());
+
+        let filter = KeyFilter {
+            users: vec!["alice".to_string()],
+            all_of: vec!["prod".to_string(), "web".to_string()],
+            one_of: vec!["eu".to_string()],
+            none_of: vec!["retired".to_string()],
+            // Fingerprints are only filtered client-side
+            fingerprints: vec!["SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys".to_string()],
+        };
+        assert_eq!(
+            filter.query_pairs(),
+            vec![
+                ("user", "alice"),
+                ("allOf", "prod"),
+                ("allOf", "web"),
+                ("oneOf", "eu"),
+                ("noneOf", "retired"),
+            ]
+        );
+
+        // Several users can't be expressed to the server, so no `user` param
+        // is sent and the filter is applied client-side instead.
+        let filter = KeyFilter {
+            users: vec!["alice".to_string(), "bob".to_string()],
+            ..KeyFilter::default()
+        };
+        assert!(filter.query_pairs().is_empty());
+    }
+
+    #[test]
+    fn test_key_filter_apply() {
+        let key = |user: &str, tags: &[&str]| SSHKey {
+            key: format!("ssh-rsa {user}"),
+            user: user.to_string(),
+            name: "key".to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            sources: Vec::new(),
+        };
+        let keys_response = KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![
+                key("alice", &["prod", "web"]),
+                key("bob", &["prod", "db", "retired"]),
+                key("carol", &["dev"]),
+            ],
+        };
+        let users = |filter: KeyFilter| -> Vec<String> {
+            filter
+                .apply(&keys_response)
+                .keys
+                .into_iter()
+                .map(|k| k.user)
+                .collect()
+        };
+
+        assert_eq!(users(KeyFilter::default()), vec!["alice", "bob", "carol"]);
+        assert_eq!(
+            users(KeyFilter {
+                users: vec!["alice".to_string(), "carol".to_string()],
+                ..KeyFilter::default()
+            }),
+            vec!["alice", "carol"]
+        );
+        assert_eq!(
+            users(KeyFilter {
+                all_of: vec!["prod".to_string()],
+                none_of: vec!["retired".to_string()],
+                ..KeyFilter::default()
+            }),
+            vec!["alice"]
+        );
+        assert_eq!(
+            users(KeyFilter {
+                one_of: vec!["db".to_string(), "dev".to_string()],
+                ..KeyFilter::default()
+            }),
+            vec!["bob", "carol"]
+        );
+    }
+
+    #[test]
+    fn test_key_filter_fingerprints() {
+        let key = |name: &str, key: &str| SSHKey {
+            key: key.to_string(),
+            user: "alice".to_string(),
+            name: name.to_string(),
+            tags: Vec::new(),
+            sources: Vec::new(),
+        };
+        let mut keys_response = KeysResponse {
+            version: "1.0.0".to_string(),
+            keys: vec![
+                key(
+                    "laptop",
+                    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U",
+                ),
+                key("desktop", &format!("ssh-rsa {KEY1}")),
+            ],
+        };
+        let names = |filter: &KeyFilter, keys_response: &KeysResponse| -> Vec<String> {
+            filter
+                .apply(keys_response)
+                .keys
+                .into_iter()
+                .map(|k| k.name)
+                .collect()
+        };
+
+        // Either hash picks out the key, as sshd logs it
+        for fingerprint in [
+            "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys",
+            "MD5:66:22:78:86:67:92:36:42:d0:9b:7e:55:d2:91:76:92",
+        ] {
+            let filter = KeyFilter {
+                fingerprints: vec![fingerprint.to_string()],
+                ..KeyFilter::default()
+            };
+            assert_eq!(names(&filter, &keys_response), vec!["laptop"]);
+        }
+
+        let filter = KeyFilter {
+            fingerprints: vec!["SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU".to_string()],
+            ..KeyFilter::default()
+        };
+        assert!(names(&filter, &keys_response).is_empty());
+
+        // Fetched responses are filtered the same way
+        filter.retain_client_side(&mut keys_response);
+        assert!(keys_response.keys.is_empty());
+    }
+
+    #[test]
+    fn test_write_ssh_keys_sends_filter_query() {
+        use mockito::Matcher;
+
+        let mut mock_server = mockito::Server::new();
+        let mock = mock_server
+            .mock("GET", "/keys")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("user".into(), "alice".into()),
+                Matcher::UrlEncoded("allOf".into(), "prod".into()),
+                Matcher::UrlEncoded("noneOf".into(), "retired".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{"version": "1.0.0", "keys": [
+                    {{"key": "ssh-rsa {KEY1}", "user": "alice", "name": "key1", "tags": ["prod"]}}
+                ]}}"#
+            ))
+            .create();
+
+        let (temp_dir, file_path) = setup_temp_dir_and_file(None);
+        let filter = KeyFilter {
+            users: vec!["alice".to_string()],
+            all_of: vec!["prod".to_string()],
+            none_of: vec!["retired".to_string()],
+            ..KeyFilter::default()
+        };
+
+        let result = write_ssh_keys(
+            &[Server::new(&mock_server.url())],
+            file_path.to_str().unwrap(),
+            &filter,
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+        mock.assert();
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("ssh-rsa {KEY1} alice@key1"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_filters_multiple_users_client_side() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "alice", "name": "key1", "tags": []}},
+                {{"key": "ssh-rsa {KEY2}", "user": "bob", "name": "key2", "tags": []}},
+                {{"key": "ssh-rsa {KEY3}", "user": "carol", "name": "key3", "tags": []}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let (temp_dir, file_path) = setup_temp_dir_and_file(None);
+        let filter = KeyFilter {
+            users: vec!["alice".to_string(), "carol".to_string()],
+            ..KeyFilter::default()
+        };
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &filter,
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            content,
+            format!("ssh-rsa {KEY1} alice@key1\nssh-rsa {KEY3} carol@key3")
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_dry_run_writes_nothing() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": ["dev"]}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let existing_content = &format!("ssh-rsa {OLD_KEY} olduser@oldhost");
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                dry_run: true,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        // The file is untouched and no backup is made.
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+        assert!(list_backups(&file_path).unwrap().is_empty());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_dry_run_does_not_create_parent_dir() {
+        let (server_url, _server) = setup_mock_server(&format!(
+            r#"{{"version": "1.0.0", "keys": [{{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": []}}]}}"#
+        ));
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join(".ssh").join("authorized_keys");
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                dry_run: true,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+        assert!(!file_path.parent().unwrap().exists());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_replaces_only_block() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": []}},
+                {{"key": "ssh-rsa {KEY3}", "user": "user3", "name": "key3", "tags": []}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        // KEY2 was revoked on the server; the local key and comment must
+        // survive untouched.
+        let existing_content = format!(
+            "# hand-added keys\n\
+             ssh-rsa {LOCAL_KEY} me@laptop\n\
+             # BEGIN keys-managed {server_url}\n\
+             ssh-rsa {KEY1} user1@key1\n\
+             ssh-rsa {KEY2} user2@key2\n\
+             # END keys-managed"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(&existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "# hand-added keys\n\
+                 ssh-rsa {LOCAL_KEY} me@laptop\n\
+                 # BEGIN keys-managed {server_url}\n\
+                 ssh-rsa {KEY1} user1@key1\n\
+                 ssh-rsa {KEY3} user3@key3\n\
+                 # END keys-managed\n"
+            )
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_appends_block_to_existing_file() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": []}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let (temp_dir, file_path) =
+            setup_temp_dir_and_file(Some(&format!("ssh-rsa {LOCAL_KEY} me@laptop")));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "ssh-rsa {LOCAL_KEY} me@laptop\n\
+                 \n\
+                 # BEGIN keys-managed {server_url}\n\
+                 ssh-rsa {KEY1} user1@key1\n\
+                 # END keys-managed\n"
+            )
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_leaves_other_servers_blocks() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{"key": "ssh-rsa {KEY1}", "user": "user1", "name": "key1", "tags": []}}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+
+        let other_block = &format!(
+            "# BEGIN keys-managed https://break-glass.example.com\n\
+                           ssh-rsa {LOCAL_KEY} ops@vault\n\
+                           # END keys-managed"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(other_block));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert!(content.starts_with(&format!("{other_block}\n\n")));
+        assert!(content.contains(&format!(
+            "# BEGIN keys-managed {server_url}\nssh-rsa {KEY1} user1@key1\n# END keys-managed\n"
+        )));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_rejects_unclosed_block() {
+        let (server_url, _server) = setup_mock_server(r#"{"version": "1.0.0", "keys": []}"#);
+
+        let existing_content = format!("# BEGIN keys-managed {server_url}\nssh-rsa {KEY1} a@b");
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(&existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_err());
+        assert!(format!("{:#}", result.unwrap_err()).contains("no closing"));
+
+        // The file is untouched.
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_parse_session_keys() {
+        let auth_info = &format!(
+            "publickey ssh-ed25519 {SESSION_KEY}\n\
+                         keyboard-interactive\n\
+                         publickey ssh-rsa {SESSION_KEY2}\n"
+        );
+
+        assert_eq!(
+            parse_session_keys(auth_info),
+            vec![
+                format!("ssh-ed25519 {SESSION_KEY}"),
+                format!("ssh-rsa {SESSION_KEY2}")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_ssh_keys_refuses_empty_file() {
+        let (server_url, _server) = setup_mock_server(r#"{"version": "1.0.0", "keys": []}"#);
+        let existing_content = &format!("ssh-rsa {LOCAL_KEY} local_key user@host");
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("no keys"), "unexpected error: {err}");
+        assert!(err.contains("--allow-lockout"), "unexpected error: {err}");
+        // Nothing is written or backed up.
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+        assert!(list_backups(&file_path).unwrap().is_empty());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_refuses_to_remove_session_key() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{
+                    "key": "ssh-rsa {KEY1}",
+                    "user": "user1",
+                    "name": "key1",
+                    "tags": []
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+        let existing_content = &format!("ssh-ed25519 {SESSION_KEY} me@laptop");
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let lockout = LockoutProtection {
+            session_keys: vec![format!("ssh-ed25519 {SESSION_KEY}")],
+            ..LockoutProtection::default()
+        };
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &lockout,
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("current SSH session (ssh-ed25519 {SESSION_KEY})")),
+            "unexpected error: {err}"
+        );
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+
+        // The same write goes through once the override is given.
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection {
+                allow_lockout: true,
+                ..lockout
+            },
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("ssh-rsa {KEY1} user1@key1"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_refuses_to_remove_must_keep_key() {
+        // The break-glass key was put in the managed block by an earlier sync
+        // and has since been dropped from the server.
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{
+                    "key": "ssh-rsa {KEY1}",
+                    "user": "user1",
+                    "name": "key1",
+                    "tags": []
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+        let existing_content = format!(
+            "# BEGIN keys-managed {server_url}\n\
+             ssh-rsa {KEY1} user1@key1\n\
+             ssh-ed25519 {BREAKGLASS_KEY} admin@console\n\
+             # END keys-managed"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(&existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection {
+                must_keep: vec![format!("ssh-ed25519 {BREAKGLASS_KEY} admin@console")],
+                ..LockoutProtection::default()
+            },
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("must-keep key (ssh-ed25519 {BREAKGLASS_KEY})")),
+            "unexpected error: {err}"
+        );
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_must_keep_key_absent_from_file_is_ignored() {
+        // A must-keep key that was never in the file can't be removed by the
+        // write, so it doesn't block it.
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{
+                    "key": "ssh-rsa {KEY1}",
+                    "user": "user1",
+                    "name": "key1",
+                    "tags": []
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+        let (temp_dir, file_path) = setup_temp_dir_and_file(None);
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                ..WriteOptions::default()
+            },
+            &LockoutProtection {
+                must_keep: vec![format!("ssh-ed25519 {BREAKGLASS_KEY}")],
+                ..LockoutProtection::default()
+            },
+        );
+        assert!(result.is_ok(), "write_ssh_keys failed: {:?}", result.err());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_force_refuses_too_many_removals() {
+        let mock_response = &format!(
+            r#"
+        {{
+            "version": "1.0.0",
+            "keys": [
+                {{
+                    "key": "ssh-rsa {KEY1}",
+                    "user": "user1",
+                    "name": "key1",
+                    "tags": []
+                }}
+            ]
+        }}
+        "#
+        );
+        let (server_url, _server) = setup_mock_server(mock_response);
+        let existing_content = &format!(
+            "ssh-rsa {KEY1} user1@key1\n\
+                                ssh-rsa {KEY2} user2@key2\n\
+                                ssh-rsa {KEY3} user3@key3"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Force,
+                limits: ChangeLimits {
+                    max_removals: Some(1),
+                    ..ChangeLimits::default()
+                },
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("remove 2 of 3 keys, more than --max-removals 1"),
+            "unexpected error: {err}"
+        );
+        assert!(err.contains(&format!("ssh-rsa {KEY2} user2@key2")));
+        assert!(err.contains(&format!("ssh-rsa {KEY3} user3@key3")));
+        // Nothing is written or backed up.
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+        assert!(list_backups(&file_path).unwrap().is_empty());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_write_ssh_keys_managed_mode_checks_min_keys() {
+        let (server_url, _server) = setup_mock_server(r#"{"version": "1.0.0", "keys": []}"#);
+        let existing_content = format!(
+            "ssh-rsa {LOCAL_KEY} me@host\n\
+             # BEGIN keys-managed {server_url}\n\
+             ssh-rsa {KEY1} user1@key1\n\
+             # END keys-managed"
+        );
+        let (temp_dir, file_path) = setup_temp_dir_and_file(Some(&existing_content));
+
+        let result = write_ssh_keys(
+            &[Server::new(&server_url)],
+            file_path.to_str().unwrap(),
+            &KeyFilter::default(),
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &WriteOptions {
+                mode: WriteMode::Managed,
+                limits: ChangeLimits {
+                    min_entries: Some(2),
+                    ..ChangeLimits::default()
+                },
+                ..WriteOptions::default()
+            },
+            &LockoutProtection::default(),
+        );
+
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("leave 1 keys, fewer than --min-keys 2"),
+            "unexpected error: {err}"
+        );
+        let content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(content, format!("{existing_content}\n"));
+
+        drop(temp_dir);
+    }
 }
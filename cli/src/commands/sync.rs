@@ -0,0 +1,445 @@
+use std::cell::OnceCell;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use colored::Colorize;
+
+use crate::commands::known_hosts::{
+    apply_known_hosts, fetch_known_hosts_responses, merge_fetched_known_hosts,
+};
+use crate::commands::pgp_keys::{apply_pgp_keys, fetch_pgp_keys_from_server};
+use crate::commands::policy::KeyPolicy;
+use crate::commands::ssh_keys::{
+    KeyFilter, LockoutProtection, TagOptions, apply_ssh_keys, fetch_keys_responses,
+    merge_fetched_keys, ssh_sync_settings,
+};
+use crate::commands::{
+    ChangeLimits, LastSync, Server, WriteMode, WriteOptions, managed_owner, response_versions,
+};
+use crate::config::{Target, TargetKind};
+
+/// A short description of a target for progress and error messages, e.g.
+/// `ssh ~/.ssh/authorized_keys`.
+fn target_label(target: &Target) -> String {
+    let kind = match target.kind {
+        TargetKind::Ssh => "ssh",
+        TargetKind::KnownHosts => "known-hosts",
+        TargetKind::Pgp => "pgp",
+    };
+    match (&target.path, target.kind) {
+        (_, TargetKind::Pgp) => format!("{kind} (GnuPG keyring)"),
+        (Some(path), _) => format!("{kind} {path}"),
+        (None, _) => kind.to_string(),
+    }
+}
+
+/// Reject targets that are missing a required setting or carry one that
+/// doesn't apply to their kind, before anything is fetched or written.
+fn validate_targets(targets: &[Target]) -> Result<()> {
+    if targets.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No sync targets configured; add a [[targets]] table to the config file"
+        ));
+    }
+
+    for (i, target) in targets.iter().enumerate() {
+        let has_filters = !target.users.is_empty()
+            || !target.all_of.is_empty()
+            || !target.one_of.is_empty()
+            || !target.none_of.is_empty();
+        let has_write_settings = target.path.is_some()
+            || target.mode != WriteMode::default()
+            || target.min_keys.is_some()
+            || target.max_removals.is_some()
+            || target.max_change_percent.is_some();
+
+        let problem = match target.kind {
+            TargetKind::Ssh | TargetKind::KnownHosts if target.path.is_none() => {
+                Some("is missing `path`")
+            }
+            TargetKind::KnownHosts if has_filters => {
+                Some("sets user or tag filters, which only apply to `ssh` targets")
+            }
+            TargetKind::Pgp if has_filters || has_write_settings => {
+                Some("sets options that don't apply to `pgp` targets")
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            return Err(anyhow::anyhow!(
+                "Sync target {} ({}) {problem}",
+                i + 1,
+                target_label(target)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The write options for a file target.
+fn write_options(target: &Target, dry_run: bool, backup_retention: usize) -> WriteOptions {
+    WriteOptions {
+        mode: target.mode,
+        dry_run,
+        backup_retention,
+        limits: ChangeLimits {
+            min_entries: target.min_keys,
+            max_removals: target.max_removals,
+            max_change_percent: target.max_change_percent,
+        },
+    }
+}
+
+/// The response cached in `cell`, fetching it on first use, or the error that
+/// the fetch failed with.
+fn fetched<T>(cell: &OnceCell<Result<T>>, fetch: impl FnOnce() -> Result<T>) -> Result<&T> {
+    cell.get_or_init(fetch)
+        .as_ref()
+        .map_err(|err| anyhow::anyhow!("{err:#}"))
+}
+
+/// Sync every configured target in one pass.
+///
+/// Each server endpoint is fetched at most once, and the response is shared
+/// by every target that needs it (SSH key filters are applied on the client).
+/// A file target that was last synced from the same responses, and hasn't
+/// been changed since, is skipped.
+/// SSH keys and known hosts are merged across all of `servers`, and SSH keys
+/// get the options their tags map to in `tag_options`. SSH keys and host keys
+/// that break `policy` are skipped, or fail their target; PGP keys come from the
+/// first server. A failing target doesn't stop the others; the run fails at
+/// the end if any target failed, so a single cron or systemd entry can keep a
+/// host in sync and still report problems.
+pub fn sync_targets(
+    servers: &[Server],
+    targets: &[Target],
+    dry_run: bool,
+    backup_retention: usize,
+    tag_options: &TagOptions,
+    policy: &KeyPolicy,
+    lockout: &LockoutProtection,
+) -> Result<()> {
+    validate_targets(targets)?;
+
+    let owner = managed_owner(servers);
+    let today = Local::now().date_naive();
+    let ssh_keys = OnceCell::new();
+    let merged_ssh_keys = OnceCell::new();
+    let known_hosts = OnceCell::new();
+    let merged_known_hosts = OnceCell::new();
+    let pgp_keys = OnceCell::new();
+
+    let mut num_failed = 0;
+    for target in targets {
+        let label = target_label(target);
+        println!("{} {}", "🔄 Syncing".purple().bold(), label);
+
+        let options = write_options(target, dry_run, backup_retention);
+        let path = target.path.as_deref().unwrap_or_default();
+        let result = match target.kind {
+            TargetKind::Ssh => fetched(&ssh_keys, || {
+                fetch_keys_responses(servers, &KeyFilter::default())
+            })
+            .and_then(|responses| {
+                let filter = KeyFilter {
+                    users: target.users.clone(),
+                    all_of: target.all_of.clone(),
+                    one_of: target.one_of.clone(),
+                    none_of: target.none_of.clone(),
+                    fingerprints: Vec::new(),
+                };
+                let versions = response_versions(responses);
+                let last_sync = LastSync::new(
+                    servers,
+                    path,
+                    &ssh_sync_settings(target.mode, &filter, tag_options, policy, responses, today),
+                );
+                if last_sync.unchanged(&versions) {
+                    return Ok(());
+                }
+
+                let keys_response =
+                    merged_ssh_keys.get_or_init(|| merge_fetched_keys(responses, today));
+                apply_ssh_keys(
+                    &filter.apply(keys_response),
+                    &owner,
+                    path,
+                    tag_options,
+                    policy,
+                    &options,
+                    lockout,
+                )?;
+                if !dry_run {
+                    last_sync.record(versions);
+                }
+                Ok(())
+            }),
+            TargetKind::KnownHosts => {
+                fetched(&known_hosts, || fetch_known_hosts_responses(servers)).and_then(
+                    |responses| {
+                        let versions = response_versions(responses);
+                        let last_sync = LastSync::new(
+                            servers,
+                            path,
+                            &format!("known-hosts {:?} {policy:?}", target.mode),
+                        );
+                        if last_sync.unchanged(&versions) {
+                            return Ok(());
+                        }
+
+                        let response =
+                            merged_known_hosts.get_or_init(|| merge_fetched_known_hosts(responses));
+                        apply_known_hosts(response, &owner, path, policy, &options)?;
+                        if !dry_run {
+                            last_sync.record(versions);
+                        }
+                        Ok(())
+                    },
+                )
+            }
+            TargetKind::Pgp => fetched(&pgp_keys, || {
+                let server = servers.first().context("No keys server configured")?;
+                fetch_pgp_keys_from_server(&server.client()?, &server.url)
+            })
+            .and_then(|keys_response| {
+                if dry_run {
+                    println!(
+                        "🔍 Dry run: would import {} PGP key(s) into your local GnuPG keyring",
+                        keys_response.keys.len()
+                    );
+                    Ok(())
+                } else {
+                    apply_pgp_keys(keys_response)
+                }
+            }),
+        };
+
+        if let Err(err) = result {
+            num_failed += 1;
+            eprintln!("{} {label}: {err:#}", "❌ Failed to sync".red().bold());
+        }
+        println!();
+    }
+
+    if num_failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{num_failed} of {} sync targets failed",
+            targets.len()
+        ));
+    }
+
+    if dry_run {
+        println!("🔍 Dry run: checked all {} sync targets", targets.len());
+    } else {
+        println!("✅ Synced all {} targets", targets.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    // Key data for the keys these tests serve and write: well-formed key
+    // blobs, as keys from the server are checked, but not real keys.
+    const KEY1: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEH7fMXuOUDkolNuTXwTbf/4=";
+    const KEY2: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHaDwaHO80Lyg+vjF+Lt3NI=";
+    const GITHUB_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAILF8dQC2y/5bhasUEMJxSYJ/CXj/E7r/04420K2h6vrx";
+
+    fn keys_response() -> String {
+        format!(
+            r#"{{
+                "version": "1.0.0",
+                "keys": [
+                    {{"key": "ssh-rsa {KEY1}", "user": "alice", "name": "laptop", "tags": ["prod"]}},
+                    {{"key": "ssh-rsa {KEY2}", "user": "bob", "name": "desktop", "tags": ["dev"]}}
+                ]
+            }}"#
+        )
+    }
+
+    fn known_hosts_response() -> String {
+        format!(
+            r#"{{
+                "version": "1.0.0",
+                "knownHosts": [
+                    {{"hosts": ["github.com"], "keys": [{{"type": "ssh-ed25519", "key": "{GITHUB_KEY}"}}]}}
+                ]
+            }}"#
+        )
+    }
+
+    fn target(kind: TargetKind, path: Option<&str>) -> Target {
+        Target {
+            kind,
+            path: path.map(str::to_string),
+            mode: WriteMode::default(),
+            users: Vec::new(),
+            all_of: Vec::new(),
+            one_of: Vec::new(),
+            none_of: Vec::new(),
+            min_keys: None,
+            max_removals: None,
+            max_change_percent: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_targets() {
+        assert!(validate_targets(&[]).is_err());
+        assert!(validate_targets(&[target(TargetKind::Ssh, None)]).is_err());
+        assert!(validate_targets(&[target(TargetKind::Pgp, Some("~/.gnupg"))]).is_err());
+
+        let mut known_hosts = target(TargetKind::KnownHosts, Some("known_hosts"));
+        known_hosts.users = vec!["alice".to_string()];
+        let err = validate_targets(&[known_hosts]).unwrap_err().to_string();
+        assert!(
+            err.contains("Sync target 1 (known-hosts known_hosts)"),
+            "{err}"
+        );
+
+        assert!(
+            validate_targets(&[
+                target(TargetKind::Ssh, Some("authorized_keys")),
+                target(TargetKind::KnownHosts, Some("known_hosts")),
+                target(TargetKind::Pgp, None),
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_sync_targets_fetches_each_endpoint_once() {
+        let mut server = mockito::Server::new();
+        let keys_mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(keys_response())
+            .expect(1)
+            .create();
+        let known_hosts_mock = server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(known_hosts_response())
+            .expect(1)
+            .create();
+
+        let dir = tempdir().unwrap();
+        let prod_keys = dir.path().join("prod_authorized_keys");
+        let all_keys = dir.path().join("authorized_keys");
+        let known_hosts = dir.path().join("known_hosts");
+
+        let mut prod_target = target(TargetKind::Ssh, prod_keys.to_str());
+        prod_target.all_of = vec!["prod".to_string()];
+        let targets = [
+            prod_target,
+            target(TargetKind::Ssh, all_keys.to_str()),
+            target(TargetKind::KnownHosts, known_hosts.to_str()),
+        ];
+
+        let result = sync_targets(
+            &[Server::new(&server.url())],
+            &targets,
+            false,
+            5,
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "sync_targets failed: {:?}", result.err());
+
+        keys_mock.assert();
+        known_hosts_mock.assert();
+        // Each target gets its own filtering of the one fetch.
+        assert_eq!(
+            fs::read_to_string(&prod_keys).unwrap(),
+            format!("ssh-rsa {KEY1} alice@laptop")
+        );
+        assert_eq!(
+            fs::read_to_string(&all_keys).unwrap(),
+            format!("ssh-rsa {KEY1} alice@laptop\nssh-rsa {KEY2} bob@desktop")
+        );
+        assert_eq!(
+            fs::read_to_string(&known_hosts).unwrap(),
+            format!("github.com ssh-ed25519 {GITHUB_KEY}\n")
+        );
+    }
+
+    #[test]
+    fn test_sync_targets_failure_does_not_stop_other_targets() {
+        let mut server = mockito::Server::new();
+        let keys_mock = server
+            .mock("GET", "/keys")
+            .with_status(500)
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(known_hosts_response())
+            .create();
+
+        let dir = tempdir().unwrap();
+        let authorized_keys = dir.path().join("authorized_keys");
+        let known_hosts = dir.path().join("known_hosts");
+        let targets = [
+            target(TargetKind::Ssh, authorized_keys.to_str()),
+            target(TargetKind::Ssh, authorized_keys.to_str()),
+            target(TargetKind::KnownHosts, known_hosts.to_str()),
+        ];
+
+        let server = Server {
+            retries: 0,
+            ..Server::new(&server.url())
+        };
+        let result = sync_targets(
+            &[server],
+            &targets,
+            false,
+            5,
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &LockoutProtection::default(),
+        );
+
+        // The failed fetch isn't repeated for the second SSH target.
+        keys_mock.assert();
+        let err = result.unwrap_err().to_string();
+        assert_eq!(err, "2 of 3 sync targets failed");
+        assert!(!authorized_keys.exists());
+        assert!(known_hosts.exists());
+    }
+
+    #[test]
+    fn test_sync_targets_dry_run_writes_nothing() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(keys_response())
+            .create();
+
+        let dir = tempdir().unwrap();
+        let authorized_keys = dir.path().join("authorized_keys");
+        let targets = [target(TargetKind::Ssh, authorized_keys.to_str())];
+
+        let result = sync_targets(
+            &[Server::new(&server.url())],
+            &targets,
+            true,
+            5,
+            &TagOptions::default(),
+            &KeyPolicy::default(),
+            &LockoutProtection::default(),
+        );
+        assert!(result.is_ok(), "sync_targets failed: {:?}", result.err());
+        assert!(!authorized_keys.exists());
+    }
+}
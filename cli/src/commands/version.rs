@@ -0,0 +1,57 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::commands::Server;
+use crate::http::check_version;
+use crate::version::VersionReq;
+
+/// This CLI's version, as stamped from the release tag.
+pub const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Print the client's version and the version each of `servers` reports,
+/// checking the latter against `expected_version` if there is one.
+///
+/// Fails if a server can't be reached or doesn't satisfy `expected_version`,
+/// so scripts can assert which release a host is consuming.
+pub fn show_versions(servers: &[Server], expected_version: Option<&VersionReq>) -> Result<()> {
+    println!("{} {CLIENT_VERSION}", "Client:".bold());
+
+    let mut num_failed = 0;
+    for server in servers {
+        let version = server
+            .client()
+            .and_then(|client| Ok(client.server_version(&server.url)?));
+        let version = match version {
+            Ok(version) => version,
+            Err(err) => {
+                num_failed += 1;
+                eprintln!("{} {err:#}", "❌".red().bold());
+                continue;
+            }
+        };
+
+        println!(
+            "{} {} ({})",
+            "Server:".bold(),
+            version.as_deref().unwrap_or("unknown").green(),
+            server.url.cyan()
+        );
+        if let Some(expected_version) = expected_version {
+            match check_version(&server.url, version.as_deref(), expected_version) {
+                Ok(()) => println!("✅ Satisfies the expected version {expected_version}"),
+                Err(err) => {
+                    num_failed += 1;
+                    eprintln!("{} {err:#}", "❌".red().bold());
+                }
+            }
+        }
+    }
+
+    if num_failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{num_failed} of {} servers failed the version check",
+            servers.len()
+        ));
+    }
+    Ok(())
+}
@@ -1,32 +1,865 @@
 use anyhow::{Context, Result};
 use directories::ProjectDirs;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::cache::DEFAULT_CACHE_MAX_AGE_HOURS;
+use crate::commands::authorized_keys_command::DEFAULT_AUTHORIZED_KEYS_TIMEOUT_SECS;
+use crate::commands::policy::KeyPolicy;
+use crate::commands::ssh_keys::{DEFAULT_EXPIRY_WARNING_DAYS, TagOptions};
+use crate::commands::{OutputFormat, WriteMode};
+use crate::http::{
+    Auth, Credentials, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_RETRIES, DEFAULT_SIGNATURE_NAMESPACE,
+    DEFAULT_TIMEOUT_SECS, Secret, TrustedKey, Verifier,
+};
+use crate::utils::DEFAULT_BACKUP_RETENTION;
+use crate::version::VersionReq;
+
 /// Configuration structure for the keys CLI
+///
+/// Unknown keys are rejected rather than ignored, so a misspelled setting is
+/// reported instead of silently having no effect.
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// URL of the keys server
     #[serde(default = "default_server_url")]
     pub server_url: String,
-    // Add more config options here as needed
+    /// URLs of several keys servers whose SSH keys and known hosts are merged,
+    /// used instead of `server_url` when set
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Seconds to wait for the server before giving up
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Seconds to wait for a connection to the server before giving up
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// How many times a failed request to the server is retried
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Use the last cached response when the server is unavailable
+    #[serde(default)]
+    pub fallback_to_cache: bool,
+    /// Hours a cached response may be used for before it is too old
+    #[serde(default = "default_cache_max_age_hours")]
+    pub cache_max_age_hours: u64,
+    /// The versions the server must report, e.g. `1.4` or `>=1.4, <2`
+    pub expected_version: Option<String>,
+    /// The oldest version the server may report
+    pub min_version: Option<String>,
+    /// How many timestamped backups of each written file to keep
+    #[serde(default = "default_backup_retention")]
+    pub backup_retention: usize,
+    /// How fetched keys and hosts are printed
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// How requests to the server authenticate, under `[auth]`
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// The keys responses must be signed with, under `[signatures]`
+    #[serde(default)]
+    pub signatures: SignaturesConfig,
+    /// Defaults for the `ssh` command, under `[ssh]`
+    #[serde(default)]
+    pub ssh: SshConfig,
+    /// Defaults for the `known-hosts` command, under `[known_hosts]`
+    #[serde(default)]
+    pub known_hosts: KnownHostsConfig,
+    /// Settings for the `authorized-keys-command` command, under
+    /// `[authorized_keys_command]`
+    #[serde(default)]
+    pub authorized_keys_command: AuthorizedKeysCommandConfig,
+    /// Rules the keys written to authorized_keys and known_hosts files must
+    /// meet, under `[policy]`
+    #[serde(default)]
+    pub policy: KeyPolicy,
+    /// Files and keyrings kept up to date by `keys sync`, from `[[targets]]`
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    /// The profile used when `--profile` isn't given
+    pub default_profile: Option<String>,
+    /// Named sets of settings, from `[profiles.<name>]`, that override the
+    /// top-level ones when selected
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// How requests to the server authenticate, under `[auth]`, for servers
+/// behind an authenticating reverse proxy
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Bearer token sent in the `Authorization` header
+    pub token: Option<String>,
+    /// Environment variable holding the bearer token
+    pub token_env: Option<String>,
+    /// Shell command printing the bearer token, e.g. `pass show keys/token`
+    pub token_command: Option<String>,
+    /// Username for HTTP basic auth
+    pub username: Option<String>,
+    /// Password for HTTP basic auth
+    pub password: Option<String>,
+    /// Environment variable holding the basic auth password
+    pub password_env: Option<String>,
+    /// Shell command printing the basic auth password
+    pub password_command: Option<String>,
+    /// PEM client certificate presented to the server, for mutual TLS
+    pub client_cert: Option<String>,
+    /// PKCS#8 PEM key of `client_cert`
+    pub client_key: Option<String>,
+    /// PEM CA certificates to trust the server's certificate with, in
+    /// addition to the system's
+    pub ca_bundle: Option<String>,
+}
+
+impl AuthConfig {
+    /// The credentials to send, or `None` when none are configured.
+    fn credentials(&self) -> Result<Option<Credentials>> {
+        let token = single_secret(
+            "token",
+            [
+                self.token.clone().map(Secret::Value),
+                self.token_env.clone().map(Secret::Env),
+                self.token_command.clone().map(Secret::Command),
+            ],
+        )?;
+        let password = single_secret(
+            "password",
+            [
+                self.password.clone().map(Secret::Value),
+                self.password_env.clone().map(Secret::Env),
+                self.password_command.clone().map(Secret::Command),
+            ],
+        )?;
+        match (token, &self.username, password) {
+            (Some(_), Some(_), _) => Err(anyhow::anyhow!(
+                "[auth] can use a token or a username, not both"
+            )),
+            (Some(token), None, _) => Ok(Some(Credentials::Bearer(token))),
+            (None, Some(username), password) => Ok(Some(Credentials::Basic {
+                username: username.clone(),
+                password,
+            })),
+            (None, None, Some(_)) => {
+                Err(anyhow::anyhow!("[auth] sets a password without a username"))
+            }
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    /// The authentication for [`HttpClient`](crate::http::HttpClient), or
+    /// `None` when nothing is configured.
+    pub fn to_auth(&self) -> Result<Option<Auth>> {
+        let credentials = self.credentials()?;
+        let expand = |path: &String| PathBuf::from(shellexpand::tilde(path).as_ref());
+        let client_identity = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some((expand(cert), expand(key))),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "[auth] client_cert and client_key must be set together"
+                ));
+            }
+        };
+        let ca_bundle = self.ca_bundle.as_ref().map(expand);
+        if credentials.is_none() && client_identity.is_none() && ca_bundle.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Auth::new(credentials, client_identity, ca_bundle)))
+    }
+}
+
+/// The one secret set among `sources`, or an error naming `setting` if more
+/// than one is.
+fn single_secret(setting: &str, sources: [Option<Secret>; 3]) -> Result<Option<Secret>> {
+    let mut set = sources.into_iter().flatten();
+    let secret = set.next();
+    if set.next().is_some() {
+        return Err(anyhow::anyhow!(
+            "[auth] sets more than one of {setting}, {setting}_env and {setting}_command"
+        ));
+    }
+    Ok(secret)
+}
+
+/// The keys responses must be signed with, under `[signatures]`
+///
+/// With any trusted key configured, every response must carry a signature by
+/// one of them, or the command fails before anything is written.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignaturesConfig {
+    /// OpenSSH `ssh-ed25519` public keys trusted to sign responses
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// An `allowed_signers` file, as used by `ssh-keygen -Y verify`, whose
+    /// keys are trusted too
+    pub allowed_signers: Option<String>,
+    /// The namespace SSH signatures must be made in
+    pub namespace: Option<String>,
+}
+
+impl SignaturesConfig {
+    /// The keys listed in `trusted_keys`.
+    fn parse_trusted_keys(&self) -> Result<Vec<TrustedKey>> {
+        self.trusted_keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| {
+                TrustedKey::parse(key).with_context(|| {
+                    format!("Invalid [signatures] trusted_keys entry {}", index + 1)
+                })
+            })
+            .collect()
+    }
+
+    /// The verifier responses must pass, or `None` when no keys are trusted.
+    pub fn to_verifier(&self) -> Result<Option<Verifier>> {
+        let mut keys = self.parse_trusted_keys()?;
+        if let Some(allowed_signers) = &self.allowed_signers {
+            let path = PathBuf::from(shellexpand::tilde(allowed_signers).as_ref());
+            let contents = fs::read_to_string(&path).with_context(|| {
+                format!("Failed to read allowed_signers file: {}", path.display())
+            })?;
+            let before = keys.len();
+            for (index, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                keys.push(TrustedKey::parse(line).with_context(|| {
+                    format!("Invalid key on line {} of {}", index + 1, path.display())
+                })?);
+            }
+            // An empty file must not quietly turn verification off
+            if keys.len() == before {
+                return Err(anyhow::anyhow!(
+                    "allowed_signers file {} contains no keys",
+                    path.display()
+                ));
+            }
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let namespace = self
+            .namespace
+            .as_deref()
+            .unwrap_or(DEFAULT_SIGNATURE_NAMESPACE);
+        Ok(Some(Verifier::new(keys, namespace)))
+    }
+}
+
+/// Defaults for the `ssh` command, under `[ssh]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SshConfig {
+    /// The authorized_keys file `ssh --write` writes when no path is given
+    pub path: Option<String>,
+    /// How `ssh --write` combines the server's keys with the file
+    #[serde(default)]
+    pub mode: WriteMode,
+    /// Only fetch keys belonging to one of these users
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Only fetch keys carrying all of these tags
+    #[serde(default)]
+    pub all_of: Vec<String>,
+    /// Only fetch keys carrying at least one of these tags
+    #[serde(default)]
+    pub one_of: Vec<String>,
+    /// Only fetch keys carrying none of these tags
+    #[serde(default)]
+    pub none_of: Vec<String>,
+    /// Keys that `ssh --write` must never remove from an authorized_keys file
+    /// (each either `<type> <blob>` or a full authorized_keys line)
+    #[serde(default)]
+    pub must_keep: Vec<String>,
+    /// Highlight keys expiring within this many days in `keys ssh` tables
+    #[serde(default = "default_expiry_warning_days")]
+    pub expiry_warning_days: u64,
+    /// authorized_keys options to write in front of keys carrying each tag,
+    /// from `[ssh.tag_options]`
+    #[serde(default)]
+    pub tag_options: TagOptions,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            mode: WriteMode::default(),
+            users: Vec::new(),
+            all_of: Vec::new(),
+            one_of: Vec::new(),
+            none_of: Vec::new(),
+            must_keep: Vec::new(),
+            expiry_warning_days: default_expiry_warning_days(),
+            tag_options: TagOptions::default(),
+        }
+    }
+}
+
+/// Defaults for the `known-hosts` command, under `[known_hosts]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnownHostsConfig {
+    /// The known_hosts file `known-hosts --write` writes when no path is given
+    pub path: Option<String>,
+    /// How `known-hosts --write` combines the server's entries with the file
+    #[serde(default)]
+    pub mode: WriteMode,
+}
+
+/// Settings for the `authorized-keys-command` command, under
+/// `[authorized_keys_command]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthorizedKeysCommandConfig {
+    /// Seconds to wait for the server before falling back to the cache
+    #[serde(default = "default_authorized_keys_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Only accept keys carrying all of these tags
+    #[serde(default)]
+    pub all_of: Vec<String>,
+    /// Only accept keys carrying at least one of these tags
+    #[serde(default)]
+    pub one_of: Vec<String>,
+    /// Only accept keys carrying none of these tags
+    #[serde(default)]
+    pub none_of: Vec<String>,
+}
+
+impl Default for AuthorizedKeysCommandConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_authorized_keys_timeout_secs(),
+            all_of: Vec::new(),
+            one_of: Vec::new(),
+            none_of: Vec::new(),
+        }
+    }
+}
+
+/// A named set of settings, from `[profiles.<name>]`, e.g. for a staging
+/// server alongside production
+///
+/// Each setting given overrides the top-level one; a section (`auth`,
+/// `signatures`, `ssh`, `known_hosts`, `authorized_keys_command`, `policy` or
+/// `targets`) replaces the top-level section as a whole.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// URL of the keys server
+    pub server_url: Option<String>,
+    /// URLs of several keys servers to merge
+    pub servers: Option<Vec<String>>,
+    /// Seconds to wait for the server before giving up
+    pub timeout_secs: Option<u64>,
+    /// Seconds to wait for a connection to the server before giving up
+    pub connect_timeout_secs: Option<u64>,
+    /// How many times a failed request to the server is retried
+    pub retries: Option<u32>,
+    /// Use the last cached response when the server is unavailable
+    pub fallback_to_cache: Option<bool>,
+    /// Hours a cached response may be used for before it is too old
+    pub cache_max_age_hours: Option<u64>,
+    /// The versions the server must report
+    pub expected_version: Option<String>,
+    /// The oldest version the server may report
+    pub min_version: Option<String>,
+    /// How many timestamped backups of each written file to keep
+    pub backup_retention: Option<usize>,
+    /// How fetched keys and hosts are printed
+    pub output: Option<OutputFormat>,
+    /// How requests to the server authenticate
+    pub auth: Option<AuthConfig>,
+    /// The keys responses must be signed with
+    pub signatures: Option<SignaturesConfig>,
+    /// Defaults for the `ssh` command
+    pub ssh: Option<SshConfig>,
+    /// Defaults for the `known-hosts` command
+    pub known_hosts: Option<KnownHostsConfig>,
+    /// Settings for the `authorized-keys-command` command
+    pub authorized_keys_command: Option<AuthorizedKeysCommandConfig>,
+    /// Rules the keys written must meet
+    pub policy: Option<KeyPolicy>,
+    /// Files and keyrings kept up to date by `keys sync`
+    pub targets: Option<Vec<Target>>,
+}
+
+/// A file or keyring that `keys sync` keeps up to date, from a `[[targets]]`
+/// table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    /// What to sync
+    pub kind: TargetKind,
+    /// The file to write (required for `ssh` and `known-hosts`, unused for `pgp`)
+    pub path: Option<String>,
+    /// How the server's entries are combined with the file: `additive`
+    /// (default), `managed` or `force`
+    #[serde(default)]
+    pub mode: WriteMode,
+    /// Only sync keys belonging to one of these users (`ssh` only)
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Only sync keys carrying all of these tags (`ssh` only)
+    #[serde(default)]
+    pub all_of: Vec<String>,
+    /// Only sync keys carrying at least one of these tags (`ssh` only)
+    #[serde(default)]
+    pub one_of: Vec<String>,
+    /// Only sync keys carrying none of these tags (`ssh` only)
+    #[serde(default)]
+    pub none_of: Vec<String>,
+    /// Refuse to write a file that would be left with fewer entries
+    pub min_keys: Option<usize>,
+    /// Refuse to write if more existing entries would be removed
+    pub max_removals: Option<usize>,
+    /// Refuse to write if more than this percentage of the existing entries
+    /// would be added or removed
+    pub max_change_percent: Option<u32>,
+}
+
+/// What a sync target keeps up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetKind {
+    /// An authorized_keys file, from `/keys`
+    Ssh,
+    /// A known_hosts file, from `/known_hosts`
+    KnownHosts,
+    /// The local GnuPG keyring, from `/pgp`
+    Pgp,
 }
 
 fn default_server_url() -> String {
     "http://localhost:8000".to_string()
 }
 
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT_SECS
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+fn default_cache_max_age_hours() -> u64 {
+    DEFAULT_CACHE_MAX_AGE_HOURS
+}
+
+fn default_expiry_warning_days() -> u64 {
+    DEFAULT_EXPIRY_WARNING_DAYS
+}
+
+fn default_authorized_keys_timeout_secs() -> u64 {
+    DEFAULT_AUTHORIZED_KEYS_TIMEOUT_SECS
+}
+
+fn default_backup_retention() -> usize {
+    DEFAULT_BACKUP_RETENTION
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             server_url: default_server_url(),
+            servers: Vec::new(),
+            timeout_secs: default_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            retries: default_retries(),
+            fallback_to_cache: false,
+            cache_max_age_hours: default_cache_max_age_hours(),
+            expected_version: None,
+            min_version: None,
+            backup_retention: default_backup_retention(),
+            output: OutputFormat::default(),
+            auth: AuthConfig::default(),
+            signatures: SignaturesConfig::default(),
+            ssh: SshConfig::default(),
+            known_hosts: KnownHostsConfig::default(),
+            authorized_keys_command: AuthorizedKeysCommandConfig::default(),
+            policy: KeyPolicy::default(),
+            targets: Vec::new(),
+            default_profile: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Check the settings serde can't, so a bad value is reported when the
+    /// config is loaded rather than when it is first used.
+    fn validate(&self) -> Result<()> {
+        for (setting, url) in std::iter::once(("server_url", &self.server_url))
+            .chain(self.servers.iter().map(|url| ("servers", url)))
+        {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(anyhow::anyhow!(
+                    "{setting} must start with http:// or https://, got {url:?}"
+                ));
+            }
+        }
+        if self.timeout_secs == 0 {
+            return Err(anyhow::anyhow!("timeout_secs must be at least 1"));
+        }
+        if self.connect_timeout_secs == 0 {
+            return Err(anyhow::anyhow!("connect_timeout_secs must be at least 1"));
+        }
+        if self.cache_max_age_hours == 0 {
+            return Err(anyhow::anyhow!("cache_max_age_hours must be at least 1"));
+        }
+        if self.authorized_keys_command.timeout_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "[authorized_keys_command] timeout_secs must be at least 1"
+            ));
+        }
+        self.version_requirement(None)?;
+        self.ssh.tag_options.validate()?;
+        self.policy.validate()?;
+        self.auth.to_auth()?;
+        self.signatures.parse_trusted_keys()?;
+        if let Some(name) = &self.default_profile
+            && !self.profiles.contains_key(name)
+        {
+            return Err(anyhow::anyhow!(
+                "default_profile {name:?} doesn't match any [profiles.<name>] section"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The versions the server must report: `expected` (e.g. from
+    /// `--expect-version`, overriding `expected_version`) combined with
+    /// `min_version`, or `None` when neither is set.
+    pub fn version_requirement(&self, expected: Option<&VersionReq>) -> Result<Option<VersionReq>> {
+        let expected = match expected {
+            Some(expected) => Some(expected.clone()),
+            None => self
+                .expected_version
+                .as_deref()
+                .map(VersionReq::parse)
+                .transpose()
+                .context("Invalid expected_version")?,
+        };
+        let min_version = self
+            .min_version
+            .as_deref()
+            .map(VersionReq::at_least)
+            .transpose()
+            .context("Invalid min_version")?;
+        Ok(match (expected, min_version) {
+            (Some(expected), Some(min_version)) => Some(expected.and(min_version)),
+            (expected, min_version) => expected.or(min_version),
+        })
+    }
+
+    /// The URLs of the servers to fetch from: `servers` if set, otherwise
+    /// `server_url`
+    pub fn server_urls(&self) -> Vec<String> {
+        if self.servers.is_empty() {
+            vec![self.server_url.clone()]
+        } else {
+            self.servers.clone()
+        }
+    }
+
+    /// The profile named `name`, or an error listing the available ones
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let available = if self.profiles.is_empty() {
+                "no profiles are defined".to_string()
+            } else {
+                let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                format!("available profiles: {}", names.join(", "))
+            };
+            anyhow::anyhow!("Unknown profile {name:?} ({available})")
+        })
+    }
+
+    /// Apply the settings of the profile named `name`, or of `default_profile`
+    /// when no name is given. Without either, the config is returned as is.
+    pub fn with_profile(mut self, name: Option<&str>) -> Result<Config> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(self);
+        };
+        let profile = self.profile(name)?.clone();
+        // A profile naming its own server(s) replaces both settings, so it
+        // never ends up merged with the top-level servers.
+        if profile.server_url.is_some() || profile.servers.is_some() {
+            self.server_url = profile.server_url.unwrap_or_else(default_server_url);
+            self.servers = profile.servers.unwrap_or_default();
         }
+        if let Some(timeout_secs) = profile.timeout_secs {
+            self.timeout_secs = timeout_secs;
+        }
+        if let Some(connect_timeout_secs) = profile.connect_timeout_secs {
+            self.connect_timeout_secs = connect_timeout_secs;
+        }
+        if let Some(retries) = profile.retries {
+            self.retries = retries;
+        }
+        if let Some(fallback_to_cache) = profile.fallback_to_cache {
+            self.fallback_to_cache = fallback_to_cache;
+        }
+        if let Some(cache_max_age_hours) = profile.cache_max_age_hours {
+            self.cache_max_age_hours = cache_max_age_hours;
+        }
+        if let Some(expected_version) = profile.expected_version {
+            self.expected_version = Some(expected_version);
+        }
+        if let Some(min_version) = profile.min_version {
+            self.min_version = Some(min_version);
+        }
+        if let Some(backup_retention) = profile.backup_retention {
+            self.backup_retention = backup_retention;
+        }
+        if let Some(output) = profile.output {
+            self.output = output;
+        }
+        if let Some(auth) = profile.auth {
+            self.auth = auth;
+        }
+        if let Some(signatures) = profile.signatures {
+            self.signatures = signatures;
+        }
+        if let Some(ssh) = profile.ssh {
+            self.ssh = ssh;
+        }
+        if let Some(known_hosts) = profile.known_hosts {
+            self.known_hosts = known_hosts;
+        }
+        if let Some(authorized_keys_command) = profile.authorized_keys_command {
+            self.authorized_keys_command = authorized_keys_command;
+        }
+        if let Some(policy) = profile.policy {
+            self.policy = policy;
+        }
+        if let Some(targets) = profile.targets {
+            self.targets = targets;
+        }
+
+        self.validate()
+            .with_context(|| format!("Invalid settings in profile {name:?}"))?;
+        Ok(self)
     }
 }
 
-/// Load configuration from file or return default if not found
-pub fn load_config(config_path: Option<&str>) -> Result<Config> {
+/// The file `keys init` writes: every setting, documented, with the optional
+/// ones commented out at their defaults.
+const CONFIG_TEMPLATE: &str = r#"# Keys CLI Configuration
+#
+# Every setting is optional; the commented-out values show the defaults or an
+# example. Command-line flags take precedence over anything set here.
+
+# URL of the keys server
+server_url = "{server_url}"
+
+# Several keys servers (e.g. a team server and a break-glass server) whose SSH
+# keys and known hosts are merged, used instead of server_url when set. PGP
+# keys are only fetched from the first.
+# servers = ["https://keys.example.com", "https://break-glass.example.com"]
+
+# Seconds to wait for the server before giving up
+# timeout_secs = {timeout_secs}
+
+# Seconds to wait for a connection to the server before giving up
+# connect_timeout_secs = {connect_timeout_secs}
+
+# Times to retry a request that fails to connect, times out, or gets a 5xx or
+# 429 response, waiting longer before each retry (0 disables retries)
+# retries = {retries}
+
+# Every successful response is cached (e.g. in ~/.cache/keys on Linux). When
+# the server can't be reached or is failing, use the cached response instead,
+# as `--fallback-to-cache` does
+# fallback_to_cache = false
+
+# Hours a cached response may be used for; an older one fails the command
+# cache_max_age_hours = {cache_max_age_hours}
+
+# Fail before writing anything unless the server reports one of these versions
+# (as with `--expect-version`). A bare version pins it ("1.4.2" or any "1.4"
+# release); ranges work as in Cargo, e.g. ">=1.4, <2", "~1.4" or "^1.4".
+# expected_version = "1.4"
+
+# Fail before writing anything if the server reports an older version
+# min_version = "1.4.0"
+
+# Timestamped backups to keep of each file written (0 disables backups)
+# backup_retention = {backup_retention}
+
+# How to print fetched keys and hosts: "auto" (a table on a terminal, raw
+# lines when piped), "table", "raw", or "json" / "yaml" for every field
+# including tags
+# output = "auto"
+
+# The [profiles.<name>] section to use when --profile isn't given
+# default_profile = "staging"
+
+[auth]
+# Credentials for a server behind an authenticating reverse proxy. Set one of
+# token, token_env or token_command to send a bearer token...
+# token = "..."
+# token_env = "KEYS_TOKEN"
+# token_command = "pass show keys/token"
+
+# ...or a username, and optionally one of password, password_env or
+# password_command, for HTTP basic auth
+# username = "deploy"
+# password_env = "KEYS_PASSWORD"
+
+# A client certificate and its PKCS#8 key (both PEM) for mutual TLS
+# client_cert = "~/.config/keys/client.crt"
+# client_key = "~/.config/keys/client.key"
+
+# PEM CA certificates to trust the server with, in addition to the system's
+# ca_bundle = "/etc/ssl/internal-ca.pem"
+
+[signatures]
+# Require every response to be signed by one of these ssh-ed25519 keys, in an
+# X-Keys-Signature header, before anything is written. The signature is either
+# made with `ssh-keygen -Y sign -n keys` or a bare base64 Ed25519 signature.
+# trusted_keys = ["ssh-ed25519 AAAA... keys-server"]
+
+# Also trust the keys in an allowed_signers file, as used by ssh-keygen -Y
+# verify (principals and options are ignored)
+# allowed_signers = "~/.config/keys/allowed_signers"
+
+# The namespace SSH signatures must be made in
+# namespace = "keys"
+
+[ssh]
+# File written by `keys ssh --write` when no path is given
+# path = "~/.ssh/authorized_keys"
+
+# How `keys ssh --write` combines the server's keys with the file: "additive"
+# (only add new keys), "managed" (own a marked block) or "force" (replace the
+# whole file)
+# mode = "additive"
+
+# Only fetch keys matching these filters. Each filter flag given on the command
+# line replaces the matching setting here.
+# users = ["alice"]
+# all_of = ["prod"]
+# one_of = ["web", "db"]
+# none_of = ["retired"]
+
+# Keys `keys ssh --write` must never remove
+# must_keep = ["ssh-ed25519 AAAA... admin@console"]
+
+# Keys tagged `expires:YYYY-MM-DD` are written with a matching expiry-time and
+# skipped once expired; `keys ssh` highlights those expiring within this many
+# days
+# expiry_warning_days = {expiry_warning_days}
+
+# authorized_keys options written in front of server keys carrying each tag.
+# A key with several of these tags gets all their options; when two tags set
+# an option that can only appear once (such as command), the first tag by
+# name wins and the conflict is reported.
+# [ssh.tag_options]
+# ci = 'restrict,command="/usr/local/bin/deploy"'
+# jump = 'permitopen="*:22"'
+
+[known_hosts]
+# File written by `keys known-hosts --write` when no path is given
+# path = "~/.ssh/known_hosts"
+
+# How `keys known-hosts --write` combines the server's entries with the file
+# mode = "additive"
+
+[authorized_keys_command]
+# Seconds `keys authorized-keys-command` waits for the server before falling
+# back to the cache; sshd holds up the login until it answers
+# timeout_secs = {authorized_keys_timeout_secs}
+
+# Only hand sshd the user's keys matching these filters
+# all_of = ["prod"]
+# one_of = ["web", "db"]
+# none_of = ["retired"]
+
+[policy]
+# Rules every server key must meet to be written by `keys ssh --write`,
+# `keys known-hosts --write` and `keys sync`, or handed to sshd by
+# `keys authorized-keys-command`. Only these key algorithms are allowed
+# (certificates count as the key they certify)...
+# allowed_algorithms = ["ssh-ed25519", "sk-ssh-ed25519@openssh.com", "ssh-rsa"]
+
+# ...RSA keys must have at least this many bits...
+# min_rsa_bits = 3072
+
+# ...and keys carrying these tags must be hardware security keys (sk-*)
+# hardware_key_tags = ["admin"]
+
+# "skip" (leave out each key breaking the policy, with a warning) or "abort"
+# (fail, listing them all, without writing anything)
+# strictness = "skip"
+
+# Files and keyrings kept up to date by `keys sync`, one [[targets]] table each
+# [[targets]]
+# kind = "ssh"                     # ssh, known-hosts or pgp
+# path = "~/.ssh/authorized_keys"  # not used for pgp
+# mode = "managed"
+# all_of = ["web-01"]              # users, all_of, one_of, none_of (ssh only)
+# max_removals = 2                 # min_keys, max_removals, max_change_percent
+
+# Named sets of settings selected with --profile, e.g. for a staging server. A
+# profile can set any of the settings above, and have its own
+# [profiles.<name>.auth], [profiles.<name>.signatures], [profiles.<name>.ssh],
+# [profiles.<name>.known_hosts], [profiles.<name>.authorized_keys_command],
+# [profiles.<name>.policy] and [[profiles.<name>.targets]] sections, which
+# replace the top-level ones.
+# [profiles.staging]
+# server_url = "https://keys.staging.example.com"
+# timeout_secs = 10
+"#;
+
+/// The contents of a new config file, as written by `keys init`.
+fn default_config_contents() -> String {
+    CONFIG_TEMPLATE
+        .replace("{server_url}", &default_server_url())
+        .replace("{timeout_secs}", &default_timeout_secs().to_string())
+        .replace(
+            "{connect_timeout_secs}",
+            &default_connect_timeout_secs().to_string(),
+        )
+        .replace("{retries}", &default_retries().to_string())
+        .replace(
+            "{cache_max_age_hours}",
+            &default_cache_max_age_hours().to_string(),
+        )
+        .replace(
+            "{expiry_warning_days}",
+            &default_expiry_warning_days().to_string(),
+        )
+        .replace(
+            "{authorized_keys_timeout_secs}",
+            &default_authorized_keys_timeout_secs().to_string(),
+        )
+        .replace(
+            "{backup_retention}",
+            &default_backup_retention().to_string(),
+        )
+}
+
+/// Load configuration from file or return default if not found, with the
+/// settings of `profile` (or the config's `default_profile`) applied
+pub fn load_config(config_path: Option<&str>, profile: Option<&str>) -> Result<Config> {
+    load_config_file(config_path)?.with_profile(profile)
+}
+
+/// Load configuration from file or return default if not found, without
+/// applying any profile
+pub fn load_config_file(config_path: Option<&str>) -> Result<Config> {
     load_config_from(config_path, get_default_config_path())
 }
 
@@ -81,6 +914,9 @@ fn load_config_from_path(path: &Path) -> Result<Config> {
 
     let config: Config = toml::from_str(&contents)
         .with_context(|| format!("Failed to parse TOML config from: {}", path.display()))?;
+    config
+        .validate()
+        .with_context(|| format!("Invalid config in: {}", path.display()))?;
 
     Ok(config)
 }
@@ -116,13 +952,8 @@ fn ensure_config_exists_at(config_path: Option<PathBuf>) -> Result<PathBuf> {
                 })?;
             }
 
-            // Write default config to file
-            let default_config = format!(
-                "# Keys CLI Configuration\n\n# Server URL (default: http://localhost:8000)\nserver_url = \"{}\"\n",
-                default_server_url()
-            );
-
-            fs::write(&config_path, default_config).with_context(|| {
+            // Write the commented default config to file
+            fs::write(&config_path, default_config_contents()).with_context(|| {
                 format!(
                     "Failed to write default config to: {}",
                     config_path.display()
@@ -138,6 +969,7 @@ fn ensure_config_exists_at(config_path: Option<PathBuf>) -> Result<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::policy::Strictness;
     use std::fs;
     use tempfile::{NamedTempFile, TempDir};
 
@@ -162,18 +994,527 @@ server_url = "https://example.com:8080"
         fs::write(temp_file.path(), config_content).unwrap();
 
         // Load config from the temp file
-        let result = load_config(Some(temp_file.path().to_str().unwrap()));
+        let result = load_config(Some(temp_file.path().to_str().unwrap()), None);
         assert!(result.is_ok());
 
         let config = result.unwrap();
         assert_eq!(config.server_url, "https://example.com:8080");
     }
 
+    #[test]
+    fn test_load_config_with_ssh_must_keep() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+server_url = "https://example.com"
+
+[ssh]
+must_keep = ["ssh-ed25519 AAAAbreakglass admin@console"]
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+
+        let config = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap();
+        assert_eq!(
+            config.ssh.must_keep,
+            vec!["ssh-ed25519 AAAAbreakglass admin@console"]
+        );
+    }
+
+    #[test]
+    fn test_load_config_with_ssh_tag_options() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+[ssh.tag_options]
+ci = 'restrict,command="/usr/local/bin/deploy --from ci"'
+jump = 'permitopen="*:22"'
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+
+        let config = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap();
+        assert_eq!(
+            config.ssh.tag_options.0.get("ci").map(String::as_str),
+            Some(r#"restrict,command="/usr/local/bin/deploy --from ci""#)
+        );
+        assert_eq!(config.ssh.tag_options.0.len(), 2);
+
+        fs::write(
+            temp_file.path(),
+            "[ssh.tag_options]\nci = 'restrict, no-pty'\n",
+        )
+        .unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("[ssh.tag_options] ci: unquoted whitespace"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_load_config_with_policy() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+[policy]
+allowed_algorithms = ["ssh-ed25519", "ssh-rsa"]
+min_rsa_bits = 3072
+hardware_key_tags = ["admin"]
+strictness = "abort"
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+
+        let config = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap();
+        assert_eq!(
+            config.policy,
+            KeyPolicy {
+                allowed_algorithms: vec!["ssh-ed25519".to_string(), "ssh-rsa".to_string()],
+                min_rsa_bits: Some(3072),
+                hardware_key_tags: vec!["admin".to_string()],
+                strictness: Strictness::Abort,
+            }
+        );
+
+        fs::write(
+            temp_file.path(),
+            "[policy]\nallowed_algorithms = [\"rsa\"]\n",
+        )
+        .unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("unknown algorithm \"rsa\""),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_load_config_with_targets() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+[[targets]]
+kind = "ssh"
+path = "~/.ssh/authorized_keys"
+mode = "managed"
+all_of = ["prod"]
+max_removals = 2
+
+[[targets]]
+kind = "known-hosts"
+path = "~/.ssh/known_hosts"
+
+[[targets]]
+kind = "pgp"
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+
+        let config = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap();
+        let kinds: Vec<TargetKind> = config.targets.iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![TargetKind::Ssh, TargetKind::KnownHosts, TargetKind::Pgp]
+        );
+        assert_eq!(config.targets[0].mode, WriteMode::Managed);
+        assert_eq!(config.targets[0].all_of, vec!["prod"]);
+        assert_eq!(config.targets[0].max_removals, Some(2));
+        assert_eq!(config.targets[1].mode, WriteMode::Additive);
+        assert_eq!(config.targets[2].path, None);
+    }
+
+    #[test]
+    fn test_load_config_rejects_unknown_target_kind() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), "[[targets]]\nkind = \"gpg\"\n").unwrap();
+
+        assert!(load_config(Some(temp_file.path().to_str().unwrap()), None).is_err());
+    }
+
+    #[test]
+    fn test_load_config_with_command_defaults() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+timeout_secs = 5
+connect_timeout_secs = 2
+retries = 0
+fallback_to_cache = true
+cache_max_age_hours = 24
+backup_retention = 2
+output = "raw"
+
+[ssh]
+path = "~/.ssh/authorized_keys"
+mode = "managed"
+users = ["alice"]
+none_of = ["retired"]
+
+[known_hosts]
+path = "~/.ssh/known_hosts"
+mode = "force"
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+
+        let config = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap();
+        assert_eq!(config.timeout_secs, 5);
+        assert_eq!(config.connect_timeout_secs, 2);
+        assert_eq!(config.retries, 0);
+        assert!(config.fallback_to_cache);
+        assert_eq!(config.cache_max_age_hours, 24);
+        assert_eq!(config.backup_retention, 2);
+        assert_eq!(config.output, OutputFormat::Raw);
+        assert_eq!(config.ssh.path.as_deref(), Some("~/.ssh/authorized_keys"));
+        assert_eq!(config.ssh.mode, WriteMode::Managed);
+        assert_eq!(config.ssh.users, vec!["alice"]);
+        assert_eq!(config.ssh.none_of, vec!["retired"]);
+        assert_eq!(config.known_hosts.mode, WriteMode::Force);
+    }
+
+    #[test]
+    fn test_load_config_rejects_unknown_keys() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), "sever_url = \"https://example.com\"\n").unwrap();
+
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("unknown field `sever_url`"), "{message}");
+        assert!(message.contains("server_url"), "{message}");
+    }
+
+    #[test]
+    fn test_load_config_rejects_unknown_keys_in_sections() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), "[ssh]\nmust_keeps = []\n").unwrap();
+
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("unknown field `must_keeps`"));
+    }
+
+    #[test]
+    fn test_load_config_rejects_invalid_values() {
+        let temp_file = NamedTempFile::new().unwrap();
+
+        fs::write(temp_file.path(), "timeout_secs = 0\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("timeout_secs must be at least 1"));
+
+        fs::write(temp_file.path(), "connect_timeout_secs = 0\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("connect_timeout_secs must be at least 1"));
+
+        fs::write(temp_file.path(), "cache_max_age_hours = 0\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("cache_max_age_hours must be at least 1"));
+
+        fs::write(temp_file.path(), "server_url = \"keys.example.com\"\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("must start with http:// or https://"));
+
+        fs::write(
+            temp_file.path(),
+            "servers = [\"https://a.example.com\", \"b\"]\n",
+        )
+        .unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("servers must start with http:// or https://"));
+
+        fs::write(temp_file.path(), "[ssh]\nmode = \"merge\"\n").unwrap();
+        assert!(load_config(Some(temp_file.path().to_str().unwrap()), None).is_err());
+
+        fs::write(temp_file.path(), "expected_version = \"latest\"\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid expected_version"));
+
+        fs::write(temp_file.path(), "min_version = \">=1.4\"\n").unwrap();
+        let err = load_config(Some(temp_file.path().to_str().unwrap()), None).unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid min_version"));
+    }
+
+    #[test]
+    fn test_version_requirement() {
+        let mut config = Config::default();
+        assert_eq!(config.version_requirement(None).unwrap(), None);
+
+        config.expected_version = Some("1".to_string());
+        config.min_version = Some("1.4".to_string());
+        let req = config.version_requirement(None).unwrap().unwrap();
+        assert_eq!(req.to_string(), "1, >=1.4");
+
+        // A requirement from the command line replaces expected_version, but
+        // min_version still applies
+        let flag = VersionReq::parse("2").unwrap();
+        let req = config.version_requirement(Some(&flag)).unwrap().unwrap();
+        assert_eq!(req.to_string(), "2, >=1.4");
+    }
+
+    #[test]
+    fn test_load_config_applies_profile() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+server_url = "https://keys.example.com"
+timeout_secs = 5
+default_profile = "production"
+
+[ssh]
+path = "~/.ssh/authorized_keys"
+users = ["alice"]
+
+[profiles.production]
+
+[profiles.staging]
+server_url = "https://keys.staging.example.com"
+
+[profiles.staging.ssh]
+all_of = ["staging"]
+
+[[profiles.staging.targets]]
+kind = "pgp"
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        // The default profile sets nothing, so the top-level settings stand
+        let config = load_config(Some(path), None).unwrap();
+        assert_eq!(config.server_url, "https://keys.example.com");
+        assert_eq!(config.ssh.users, vec!["alice"]);
+
+        // Settings a profile leaves out are inherited; sections it gives
+        // replace the top-level ones as a whole
+        let config = load_config(Some(path), Some("staging")).unwrap();
+        assert_eq!(config.server_url, "https://keys.staging.example.com");
+        assert_eq!(config.timeout_secs, 5);
+        assert_eq!(config.ssh.all_of, vec!["staging"]);
+        assert!(config.ssh.users.is_empty());
+        assert!(config.ssh.path.is_none());
+        assert_eq!(config.targets.len(), 1);
+        assert_eq!(config.targets[0].kind, TargetKind::Pgp);
+    }
+
+    #[test]
+    fn test_server_urls() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let config_content = r#"
+server_url = "https://keys.example.com"
+servers = ["https://team.example.com", "https://glass.example.com"]
+
+[profiles.staging]
+server_url = "https://keys.staging.example.com"
+"#;
+        fs::write(temp_file.path(), config_content).unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        // `servers` takes over from `server_url` when set
+        let config = load_config(Some(path), None).unwrap();
+        assert_eq!(
+            config.server_urls(),
+            vec!["https://team.example.com", "https://glass.example.com"]
+        );
+
+        // A profile naming its own server replaces both settings
+        let config = load_config(Some(path), Some("staging")).unwrap();
+        assert_eq!(
+            config.server_urls(),
+            vec!["https://keys.staging.example.com"]
+        );
+    }
+
+    #[test]
+    fn test_load_config_rejects_unknown_profile() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(
+            temp_file.path(),
+            "[profiles.staging]
+[profiles.production]
+",
+        )
+        .unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let err = load_config(Some(path), Some("prod")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile \"prod\" (available profiles: production, staging)"
+        );
+
+        let err = Config::default().with_profile(Some("staging")).unwrap_err();
+        assert!(err.to_string().contains("no profiles are defined"));
+
+        fs::write(temp_file.path(), "default_profile = \"prod\"\n").unwrap();
+        let err = load_config(Some(path), None).unwrap_err();
+        assert!(format!("{err:#}").contains("default_profile \"prod\" doesn't match"));
+    }
+
+    #[test]
+    fn test_load_config_validates_profile_settings() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(
+            temp_file.path(),
+            "[profiles.staging]
+timeout_secs = 0
+",
+        )
+        .unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        // Only checked when the profile is used
+        assert!(load_config(Some(path), None).is_ok());
+        let err = load_config(Some(path), Some("staging")).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("Invalid settings in profile \"staging\""),
+            "{message}"
+        );
+        assert!(
+            message.contains("timeout_secs must be at least 1"),
+            "{message}"
+        );
+
+        fs::write(
+            temp_file.path(),
+            "[profiles.staging]
+sever_url = \"x\"
+",
+        )
+        .unwrap();
+        let err = load_config(Some(path), None).unwrap_err();
+        assert!(format!("{err:#}").contains("unknown field `sever_url`"));
+    }
+
+    #[test]
+    fn test_load_config_with_auth() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        fs::write(
+            temp_file.path(),
+            "[auth]
+token_command = \"pass show keys/token\"
+
+[profiles.staging.auth]
+username = \"deploy\"
+password_env = \"KEYS_PASSWORD\"
+client_cert = \"/etc/keys/client.crt\"
+client_key = \"/etc/keys/client.key\"
+",
+        )
+        .unwrap();
+
+        let auth = load_config(Some(path), None)
+            .unwrap()
+            .auth
+            .to_auth()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            auth.credentials,
+            Some(Credentials::Bearer(Secret::Command(
+                "pass show keys/token".to_string()
+            )))
+        );
+        assert_eq!(auth.client_identity, None);
+
+        let auth = load_config(Some(path), Some("staging"))
+            .unwrap()
+            .auth
+            .to_auth()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            auth.credentials,
+            Some(Credentials::Basic {
+                username: "deploy".to_string(),
+                password: Some(Secret::Env("KEYS_PASSWORD".to_string())),
+            })
+        );
+        assert_eq!(
+            auth.client_identity,
+            Some((
+                PathBuf::from("/etc/keys/client.crt"),
+                PathBuf::from("/etc/keys/client.key")
+            ))
+        );
+
+        assert!(Config::default().auth.to_auth().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_config_rejects_conflicting_auth() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        for (contents, expected) in [
+            (
+                "token = \"a\"\ntoken_env = \"B\"",
+                "more than one of token, token_env and token_command",
+            ),
+            (
+                "token = \"a\"\nusername = \"b\"",
+                "a token or a username, not both",
+            ),
+            ("password = \"a\"", "a password without a username"),
+            (
+                "client_cert = \"client.crt\"",
+                "client_cert and client_key must be set together",
+            ),
+        ] {
+            fs::write(temp_file.path(), format!("[auth]\n{contents}\n")).unwrap();
+            let err = load_config(Some(path), None).unwrap_err();
+            assert!(format!("{err:#}").contains(expected), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn test_load_config_with_signatures() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        let path = config_path.to_str().unwrap();
+        let allowed_signers = temp_dir.path().join("allowed_signers");
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U";
+
+        fs::write(
+            &config_path,
+            format!("[signatures]\ntrusted_keys = [\"{key} keys-server\"]\n"),
+        )
+        .unwrap();
+        let config = load_config(Some(path), None).unwrap();
+        assert!(config.signatures.to_verifier().unwrap().is_some());
+        assert!(
+            Config::default()
+                .signatures
+                .to_verifier()
+                .unwrap()
+                .is_none()
+        );
+
+        fs::write(
+            &config_path,
+            format!(
+                "[signatures]\nallowed_signers = \"{}\"\n",
+                allowed_signers.display()
+            ),
+        )
+        .unwrap();
+        let config = load_config(Some(path), None).unwrap();
+        let err = config.signatures.to_verifier().unwrap_err();
+        assert!(err.to_string().contains("Failed to read"), "{err:#}");
+
+        // An allowed_signers file without keys must not disable verification
+        fs::write(&allowed_signers, "# no keys yet\n").unwrap();
+        let err = config.signatures.to_verifier().unwrap_err();
+        assert!(err.to_string().contains("contains no keys"), "{err:#}");
+
+        fs::write(&allowed_signers, format!("ops@example.com {key}\n")).unwrap();
+        assert!(config.signatures.to_verifier().unwrap().is_some());
+
+        fs::write(
+            &config_path,
+            "[signatures]\ntrusted_keys = [\"ssh-rsa AAAAB3NzaC1yc2E=\"]\n",
+        )
+        .unwrap();
+        let err = load_config(Some(path), None).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid [signatures] trusted_keys entry 1"),
+            "{err:#}"
+        );
+    }
+
     #[test]
     fn test_load_config_with_nonexistent_file() {
         // An explicitly specified config path that doesn't exist should fail
         // fast rather than falling back to the default config.
-        let result = load_config(Some("/nonexistent/path/config.toml"));
+        let result = load_config(Some("/nonexistent/path/config.toml"), None);
         assert!(result.is_err());
 
         let message = result.unwrap_err().to_string();
@@ -190,7 +1531,7 @@ server_url = "unclosed string
         fs::write(temp_file.path(), invalid_content).unwrap();
 
         // Load config from the temp file
-        let result = load_config(Some(temp_file.path().to_str().unwrap()));
+        let result = load_config(Some(temp_file.path().to_str().unwrap()), None);
         assert!(result.is_err());
     }
 
@@ -201,7 +1542,7 @@ server_url = "unclosed string
         fs::write(temp_file.path(), "").unwrap();
 
         // Load config from the temp file
-        let result = load_config(Some(temp_file.path().to_str().unwrap()));
+        let result = load_config(Some(temp_file.path().to_str().unwrap()), None);
         assert!(result.is_ok());
 
         // Should use default values when file is empty
@@ -306,6 +1647,87 @@ server_url = "https://test.example.com"
         assert!(content.contains("http://localhost:8000"));
     }
 
+    #[test]
+    fn test_default_config_contents_parse_to_defaults() {
+        // The template `keys init` writes must load cleanly, and leave every
+        // setting at its default.
+        let config: Config = toml::from_str(&default_config_contents()).unwrap();
+        config.validate().unwrap();
+
+        let defaults = Config::default();
+        assert_eq!(config.server_url, defaults.server_url);
+        assert_eq!(config.timeout_secs, defaults.timeout_secs);
+        assert_eq!(config.connect_timeout_secs, defaults.connect_timeout_secs);
+        assert_eq!(config.retries, defaults.retries);
+        assert_eq!(config.fallback_to_cache, defaults.fallback_to_cache);
+        assert_eq!(config.cache_max_age_hours, defaults.cache_max_age_hours);
+        assert_eq!(config.backup_retention, defaults.backup_retention);
+        assert_eq!(config.output, defaults.output);
+        assert_eq!(config.ssh.mode, WriteMode::Additive);
+        assert!(config.ssh.path.is_none());
+        assert!(config.known_hosts.path.is_none());
+        assert_eq!(
+            config.authorized_keys_command.timeout_secs,
+            defaults.authorized_keys_command.timeout_secs
+        );
+        assert_eq!(config.policy, defaults.policy);
+        assert!(config.targets.is_empty());
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn test_default_config_contents_documents_every_setting() {
+        let contents = default_config_contents();
+        for setting in [
+            "timeout_secs = 30",
+            "connect_timeout_secs = 10",
+            "retries = 3",
+            "fallback_to_cache = false",
+            "expected_version =",
+            "min_version =",
+            "cache_max_age_hours = 168",
+            "backup_retention = 5",
+            "output =",
+            "[auth]",
+            "token =",
+            "token_env =",
+            "token_command =",
+            "username =",
+            "password_env =",
+            "client_cert =",
+            "client_key =",
+            "ca_bundle =",
+            "[signatures]",
+            "trusted_keys =",
+            "allowed_signers =",
+            "namespace =",
+            "[ssh]",
+            "path =",
+            "mode =",
+            "users =",
+            "all_of =",
+            "one_of =",
+            "none_of =",
+            "must_keep =",
+            "expiry_warning_days = 14",
+            "[ssh.tag_options]",
+            "[known_hosts]",
+            "[authorized_keys_command]",
+            "timeout_secs = 5",
+            "[policy]",
+            "allowed_algorithms =",
+            "min_rsa_bits =",
+            "hardware_key_tags =",
+            "strictness =",
+            "[[targets]]",
+            "default_profile =",
+            "[profiles.staging]",
+            "servers =",
+        ] {
+            assert!(contents.contains(setting), "template is missing {setting}");
+        }
+    }
+
     #[test]
     fn test_ensure_config_exists_at_preserves_existing_file() {
         // An existing file must not be overwritten.
@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::{ClientBuilder, RequestBuilder};
+use reqwest::{Certificate, Identity};
+
+/// Where a secret, such as a bearer token, comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Secret {
+    /// Given directly in the config file.
+    Value(String),
+    /// Read from the named environment variable.
+    Env(String),
+    /// The trimmed stdout of a shell command, e.g. `pass show keys/token`.
+    Command(String),
+}
+
+impl Secret {
+    fn resolve(&self) -> Result<String> {
+        let secret = match self {
+            Secret::Value(value) => value.clone(),
+            Secret::Env(name) => std::env::var(name)
+                .with_context(|| format!("Environment variable {name} isn't set"))?,
+            Secret::Command(command) => run_secret_command(command)?,
+        };
+        if secret.trim().is_empty() {
+            return Err(anyhow::anyhow!("The {} is empty", self.describe()));
+        }
+        Ok(secret.trim().to_string())
+    }
+
+    /// Where the secret comes from, for error messages.
+    fn describe(&self) -> String {
+        match self {
+            Secret::Value(_) => "secret in the config file".to_string(),
+            Secret::Env(name) => format!("environment variable {name}"),
+            Secret::Command(command) => format!("output of `{command}`"),
+        }
+    }
+}
+
+/// Run `command` with `sh -c` and return its stdout, failing with its stderr
+/// if it exits unsuccessfully.
+fn run_secret_command(command: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .with_context(|| format!("Failed to run `{command}`"))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "`{command}` failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("`{command}` printed invalid UTF-8"))
+}
+
+/// The credentials sent with every request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// `Authorization: Bearer <token>`
+    Bearer(Secret),
+    /// HTTP basic auth
+    Basic {
+        username: String,
+        password: Option<Secret>,
+    },
+}
+
+/// Credentials with their secrets resolved, ready to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Resolved {
+    Bearer(String),
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+}
+
+/// How requests to the keys server authenticate, for servers behind an
+/// authenticating reverse proxy.
+///
+/// Secrets are resolved on first use and then shared by every client built
+/// from the same `Auth`, so a `token_command` runs at most once per run, and
+/// not at all for commands that never contact the server.
+#[derive(Debug, Default)]
+pub struct Auth {
+    /// The credentials sent with every request.
+    pub credentials: Option<Credentials>,
+    /// A PEM client certificate and its PKCS#8 PEM key, for mutual TLS.
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+    /// PEM CA certificates to trust the server's certificate with, in
+    /// addition to the system's.
+    pub ca_bundle: Option<PathBuf>,
+    resolved: OnceLock<Result<Option<Resolved>, String>>,
+}
+
+impl Auth {
+    pub fn new(
+        credentials: Option<Credentials>,
+        client_identity: Option<(PathBuf, PathBuf)>,
+        ca_bundle: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            credentials,
+            client_identity,
+            ca_bundle,
+            resolved: OnceLock::new(),
+        }
+    }
+
+    /// Add the client certificate and CA bundle to `builder`.
+    pub(super) fn configure_tls(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        if let Some((cert_path, key_path)) = &self.client_identity {
+            let cert = fs::read(cert_path).with_context(|| {
+                format!("Failed to read client certificate: {}", cert_path.display())
+            })?;
+            let key = fs::read(key_path)
+                .with_context(|| format!("Failed to read client key: {}", key_path.display()))?;
+            let identity = Identity::from_pkcs8_pem(&cert, &key).with_context(|| {
+                format!(
+                    "Failed to load client certificate {} with key {} (the key must be PKCS#8 PEM)",
+                    cert_path.display(),
+                    key_path.display()
+                )
+            })?;
+            builder = builder.identity(identity);
+        }
+        if let Some(ca_path) = &self.ca_bundle {
+            let pem = fs::read(ca_path)
+                .with_context(|| format!("Failed to read CA bundle: {}", ca_path.display()))?;
+            let certificates = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Failed to parse CA bundle: {}", ca_path.display()))?;
+            if certificates.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "CA bundle {} contains no certificates",
+                    ca_path.display()
+                ));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Resolve the credentials' secrets, once.
+    pub(super) fn check_credentials(&self) -> Result<()> {
+        self.resolved()
+            .map(|_| ())
+            .map_err(|err| anyhow::anyhow!("{err}"))
+    }
+
+    fn resolved(&self) -> Result<&Option<Resolved>, &String> {
+        self.resolved
+            .get_or_init(|| self.resolve().map_err(|err| format!("{err:#}")))
+            .as_ref()
+    }
+
+    fn resolve(&self) -> Result<Option<Resolved>> {
+        Ok(match &self.credentials {
+            None => None,
+            Some(Credentials::Bearer(token)) => Some(Resolved::Bearer(
+                token.resolve().context("Failed to get the bearer token")?,
+            )),
+            Some(Credentials::Basic { username, password }) => Some(Resolved::Basic {
+                username: username.clone(),
+                password: password
+                    .as_ref()
+                    .map(Secret::resolve)
+                    .transpose()
+                    .context("Failed to get the basic auth password")?,
+            }),
+        })
+    }
+
+    /// Add the credentials to `request`. [`check_credentials`] must have
+    /// succeeded first.
+    ///
+    /// [`check_credentials`]: Self::check_credentials
+    pub(super) fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.resolved() {
+            Ok(Some(Resolved::Bearer(token))) => request.bearer_auth(token),
+            Ok(Some(Resolved::Basic { username, password })) => {
+                request.basic_auth(username, password.as_ref())
+            }
+            Ok(None) | Err(_) => request,
+        }
+    }
+
+    /// Whether requests carry credentials of any kind, including a client
+    /// certificate.
+    pub(super) fn has_credentials(&self) -> bool {
+        self.credentials.is_some() || self.client_identity.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_sources() {
+        assert_eq!(
+            Secret::Value(" abc\n".to_string()).resolve().unwrap(),
+            "abc"
+        );
+        assert_eq!(
+            Secret::Command("printf 'from-command\\n'".to_string())
+                .resolve()
+                .unwrap(),
+            "from-command"
+        );
+
+        let err = Secret::Env("KEYS_TEST_UNSET_TOKEN_VARIABLE".to_string())
+            .resolve()
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't set"), "{err:#}");
+
+        let err = Secret::Command("echo denied >&2; exit 3".to_string())
+            .resolve()
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("denied"), "{err:#}");
+
+        let err = Secret::Command("true".to_string()).resolve().unwrap_err();
+        assert_eq!(err.to_string(), "The output of `true` is empty");
+    }
+
+    #[test]
+    fn test_credentials_are_resolved_once() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let counter = temp_dir.path().join("runs");
+        let auth = Auth::new(
+            Some(Credentials::Bearer(Secret::Command(format!(
+                "echo run >> {}; echo token",
+                counter.display()
+            )))),
+            None,
+            None,
+        );
+
+        auth.check_credentials().unwrap();
+        auth.check_credentials().unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn test_configure_tls_reports_unreadable_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let missing = temp_dir.path().join("missing.pem");
+
+        let auth = Auth::new(None, Some((missing.clone(), missing.clone())), None);
+        let err = auth
+            .configure_tls(reqwest::blocking::Client::builder())
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Failed to read client certificate")
+        );
+
+        let not_pem = temp_dir.path().join("ca.pem");
+        fs::write(&not_pem, "not a certificate").unwrap();
+        let auth = Auth::new(None, None, Some(not_pem));
+        let err = auth
+            .configure_tls(reqwest::blocking::Client::builder())
+            .unwrap_err();
+        assert!(err.to_string().contains("no certificates"), "{err:#}");
+    }
+}
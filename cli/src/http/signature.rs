@@ -0,0 +1,345 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sha::{sha256, sha512};
+
+use crate::utils::{PublicKey, Reader, put_string};
+
+/// The header a signed response carries its signature in.
+pub const SIGNATURE_HEADER: &str = "x-keys-signature";
+
+/// The namespace responses are signed in by default, as given to
+/// `ssh-keygen -Y sign -n`.
+pub const DEFAULT_SIGNATURE_NAMESPACE: &str = "keys";
+
+/// What every SSH signature starts with.
+const SSHSIG_MAGIC: &[u8] = b"SSHSIG";
+
+/// The only key type signatures are verified with.
+const ED25519: &str = "ssh-ed25519";
+
+/// An Ed25519 public key trusted to sign responses.
+#[derive(Clone)]
+pub struct TrustedKey {
+    /// The raw 32-byte public key.
+    raw: Vec<u8>,
+    /// The key's comment, for messages.
+    comment: String,
+    key: PKey<Public>,
+}
+
+impl fmt::Debug for TrustedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TrustedKey({ED25519} {})", self.comment)
+    }
+}
+
+impl TrustedKey {
+    /// Parse an OpenSSH public key (`ssh-ed25519 AAAA... comment`), as found
+    /// in a `.pub` file or, after its principals and options, on a line of an
+    /// `allowed_signers` file.
+    pub fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let position = fields
+            .iter()
+            .position(|field| is_key_type(field))
+            .with_context(|| {
+                format!("Expected an OpenSSH public key such as `{ED25519} AAAA...`, got {line:?}")
+            })?;
+        let key_type = fields[position];
+        if key_type != ED25519 {
+            return Err(anyhow::anyhow!(
+                "Only {ED25519} keys can verify signatures, got a {key_type} key"
+            ));
+        }
+        let data = fields
+            .get(position + 1)
+            .with_context(|| format!("The {ED25519} key is missing its key data"))?;
+        let public_key = PublicKey::from_parts(key_type, data)
+            .with_context(|| format!("The {ED25519} key data is malformed"))?;
+        let raw = ed25519_key(&public_key)?;
+        let key = PKey::public_key_from_raw_bytes(&raw, Id::ED25519)
+            .context("Failed to load the Ed25519 key")?;
+        Ok(Self {
+            raw,
+            comment: fields[position + 2..].join(" "),
+            key,
+        })
+    }
+
+    /// Whether `signature` is this key's signature of `message`.
+    fn signed(&self, message: &[u8], signature: &[u8]) -> bool {
+        openssl::sign::Verifier::new_without_digest(&self.key)
+            .and_then(|mut verifier| verifier.verify_oneshot(signature, message))
+            .unwrap_or(false)
+    }
+}
+
+/// Whether `field` of a public key line is its key type, rather than a
+/// principal, an option, or the key data.
+fn is_key_type(field: &str) -> bool {
+    ["ssh-", "ecdsa-", "sk-"]
+        .iter()
+        .any(|prefix| field.starts_with(prefix))
+}
+
+/// Checks that responses were signed by a trusted key before they are used.
+///
+/// The signature, in the [`SIGNATURE_HEADER`] header, is either an SSH
+/// signature of the body, as made by `ssh-keygen -Y sign` (with or without its
+/// armour, on one line), or a bare base64 Ed25519 signature of the body.
+#[derive(Debug)]
+pub struct Verifier {
+    keys: Vec<TrustedKey>,
+    namespace: String,
+}
+
+impl Verifier {
+    /// Trust signatures by any of `keys`, with SSH signatures made in
+    /// `namespace`.
+    pub fn new(keys: Vec<TrustedKey>, namespace: &str) -> Self {
+        Self {
+            keys,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// Check that `signature` is a trusted key's signature of `body`.
+    pub fn verify(&self, body: &[u8], signature: Option<&str>) -> Result<()> {
+        let signature = signature
+            .with_context(|| format!("it isn't signed (there's no {SIGNATURE_HEADER} header)"))?;
+        let encoded: String = signature
+            .replace("-----BEGIN SSH SIGNATURE-----", "")
+            .replace("-----END SSH SIGNATURE-----", "")
+            .split_whitespace()
+            .collect();
+        let decoded = STANDARD
+            .decode(encoded)
+            .context("its signature isn't valid base64")?;
+
+        if let Some(sshsig) = decoded.strip_prefix(SSHSIG_MAGIC) {
+            self.verify_ssh_signature(body, sshsig)
+        } else if decoded.len() == 64 {
+            if self.keys.iter().any(|key| key.signed(body, &decoded)) {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "its signature doesn't match any trusted key"
+                ))
+            }
+        } else {
+            Err(anyhow::anyhow!(
+                "its signature is neither an SSH signature nor an Ed25519 signature"
+            ))
+        }
+    }
+
+    /// Check an SSH signature (without its magic preamble) of `body`, in the
+    /// format described in OpenSSH's PROTOCOL.sshsig.
+    fn verify_ssh_signature(&self, body: &[u8], sshsig: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(sshsig, "its signature");
+        let version = reader.u32()?;
+        if version != 1 {
+            return Err(anyhow::anyhow!(
+                "its SSH signature has unsupported version {version}"
+            ));
+        }
+        let public_key = reader.string()?;
+        let namespace = reader.string()?;
+        let reserved = reader.string()?;
+        let hash_algorithm = reader.string()?;
+        let signature = reader.string()?;
+        reader.finish()?;
+
+        if namespace != self.namespace.as_bytes() {
+            return Err(anyhow::anyhow!(
+                "it is signed for namespace {:?}, not {:?}",
+                String::from_utf8_lossy(namespace),
+                self.namespace
+            ));
+        }
+        let public_key =
+            PublicKey::from_blob(public_key).context("its signing key is malformed")?;
+        let raw = ed25519_key(&public_key)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.raw == raw)
+            .with_context(|| {
+                format!(
+                    "it is signed by {ED25519} {}, which isn't a trusted key",
+                    STANDARD.encode(&public_key.blob)
+                )
+            })?;
+
+        let digest = match hash_algorithm {
+            b"sha256" => sha256(body).to_vec(),
+            b"sha512" => sha512(body).to_vec(),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "its SSH signature uses unsupported hash {:?}",
+                    String::from_utf8_lossy(other)
+                ));
+            }
+        };
+        let mut signed = SSHSIG_MAGIC.to_vec();
+        for field in [namespace, reserved, hash_algorithm, &digest] {
+            put_string(&mut signed, field);
+        }
+
+        let mut reader = Reader::new(signature, "its signature");
+        let signature_type = reader.string()?;
+        if signature_type != ED25519.as_bytes() {
+            return Err(anyhow::anyhow!(
+                "its SSH signature is a {}, not an {ED25519} signature",
+                String::from_utf8_lossy(signature_type)
+            ));
+        }
+        let signature = reader.string()?;
+        reader.finish()?;
+
+        if !key.signed(&signed, signature) {
+            return Err(anyhow::anyhow!(
+                "its signature doesn't match (the response may have been altered)"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The raw 32-byte key of an `ssh-ed25519` public key.
+fn ed25519_key(key: &PublicKey) -> Result<Vec<u8>> {
+    if key.key_type != ED25519 {
+        return Err(anyhow::anyhow!(
+            "Only {ED25519} keys can verify signatures, got a {} key",
+            key.key_type
+        ));
+    }
+    let mut reader = Reader::new(&key.blob, "the key");
+    reader.string()?;
+    Ok(reader.string()?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Made with `ssh-keygen -t ed25519 -C keys-server`.
+    const SIGNER: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U keys-server";
+
+    const BODY: &str = r#"{"version":"1.0.0","keys":[]}"#;
+
+    /// `BODY` signed with `ssh-keygen -Y sign -n keys -f signer`.
+    const SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgf2x9t2Dm116TJ+DOedazs8Bgqf
+863CzqHXmX2xrNndQAAAAEa2V5cwAAAAAAAAAGc2hhNTEyAAAAUwAAAAtzc2gtZWQyNTUx
+OQAAAECNldFX4iFP+PvXyLti6DI32wDbKHsP68ZT5xLawf9DAz6wTLY3ZwjsgTKe14ZaVo
+qIH9txUM1atw13ZlTgUtQC
+-----END SSH SIGNATURE-----
+";
+
+    fn verifier(namespace: &str) -> Verifier {
+        Verifier::new(vec![TrustedKey::parse(SIGNER).unwrap()], namespace)
+    }
+
+    /// The `ssh-ed25519` public key blob of the raw key `raw`.
+    fn encode_public_key(raw: &[u8]) -> Vec<u8> {
+        let mut blob = Vec::new();
+        put_string(&mut blob, ED25519.as_bytes());
+        put_string(&mut blob, raw);
+        blob
+    }
+
+    #[test]
+    fn test_parse_trusted_key() {
+        let key = TrustedKey::parse(SIGNER).unwrap();
+        assert_eq!(key.comment, "keys-server");
+        assert_eq!(key.raw.len(), 32);
+
+        // An allowed_signers line, with principals and options first
+        let line = format!("ops@example.com namespaces=\"keys\" {SIGNER}");
+        assert_eq!(TrustedKey::parse(&line).unwrap().raw, key.raw);
+
+        let err = TrustedKey::parse("ssh-rsa AAAAB3NzaC1yc2E= rsa").unwrap_err();
+        assert!(err.to_string().contains("Only ssh-ed25519 keys"), "{err:#}");
+        assert!(TrustedKey::parse("not a key").is_err());
+        assert!(TrustedKey::parse("ssh-ed25519 !!!").is_err());
+    }
+
+    #[test]
+    fn test_verify_ssh_signature() {
+        verifier("keys")
+            .verify(BODY.as_bytes(), Some(SIGNATURE))
+            .unwrap();
+
+        // Headers can't hold line breaks, so the armour may be left off
+        let one_line: String = SIGNATURE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        verifier("keys")
+            .verify(BODY.as_bytes(), Some(&one_line))
+            .unwrap();
+
+        let err = verifier("keys")
+            .verify(br#"{"version":"1.0.0","keys":[{}]}"#, Some(SIGNATURE))
+            .unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err:#}");
+
+        let err = verifier("other")
+            .verify(BODY.as_bytes(), Some(SIGNATURE))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "it is signed for namespace \"keys\", not \"other\""
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_untrusted_and_unsigned() {
+        let other = PKey::generate_ed25519().unwrap();
+        let other_line = format!(
+            "{ED25519} {}",
+            STANDARD.encode(encode_public_key(&other.raw_public_key().unwrap()))
+        );
+        let untrusted = Verifier::new(vec![TrustedKey::parse(&other_line).unwrap()], "keys");
+
+        let err = untrusted
+            .verify(BODY.as_bytes(), Some(SIGNATURE))
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't a trusted key"), "{err:#}");
+
+        let err = untrusted.verify(BODY.as_bytes(), None).unwrap_err();
+        assert!(err.to_string().contains("isn't signed"), "{err:#}");
+
+        let err = untrusted
+            .verify(BODY.as_bytes(), Some("c2lnbmF0dXJl"))
+            .unwrap_err();
+        assert!(err.to_string().contains("neither"), "{err:#}");
+    }
+
+    #[test]
+    fn test_verify_raw_ed25519_signature() {
+        let private = PKey::generate_ed25519().unwrap();
+        let line = format!(
+            "{ED25519} {}",
+            STANDARD.encode(encode_public_key(&private.raw_public_key().unwrap()))
+        );
+        let verifier = Verifier::new(vec![TrustedKey::parse(&line).unwrap()], "keys");
+        let signature = openssl::sign::Signer::new_without_digest(&private)
+            .unwrap()
+            .sign_oneshot_to_vec(BODY.as_bytes())
+            .unwrap();
+
+        verifier
+            .verify(BODY.as_bytes(), Some(&STANDARD.encode(&signature)))
+            .unwrap();
+        let err = verifier
+            .verify(b"{}", Some(&STANDARD.encode(&signature)))
+            .unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err:#}");
+    }
+}
@@ -1,3 +1,6 @@
+pub mod cache;
 pub mod commands;
 pub mod config;
+pub mod http;
 pub mod utils;
+pub mod version;
@@ -1,34 +1,163 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
-use keys::{commands, config};
+use colored::Colorize;
+use keys::version::VersionReq;
+use keys::{cache, commands, config};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// The server URL (overrides config file)
-    #[arg(short, long, global = true)]
-    server: Option<String>,
+    /// The server URL (overrides config file); repeat to merge the keys and
+    /// known hosts of several servers
+    #[arg(short, long = "server", value_name = "SERVER", global = true)]
+    servers: Vec<String>,
 
     /// Path to config file (default: ~/.config/keys/config.toml)
     #[arg(short = 'c', long, global = true)]
     config: Option<String>,
 
+    /// Seconds to wait for the server before giving up (overrides config file)
+    #[arg(long, value_name = "SECS", global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Seconds to wait for a connection to the server before giving up
+    /// (overrides config file)
+    #[arg(long, value_name = "SECS", global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout: Option<u64>,
+
+    /// Times to retry a request that fails to connect, times out, or gets a 5xx
+    /// or 429 response (overrides config file; 0 disables retries)
+    #[arg(long, value_name = "N", global = true)]
+    retries: Option<u32>,
+
+    /// Use the last cached responses without contacting the server
+    #[arg(long, global = true, conflicts_with = "fallback_to_cache")]
+    offline: bool,
+
+    /// Use the last cached response when the server can't be reached or is
+    /// failing (overrides config file)
+    #[arg(long, global = true)]
+    fallback_to_cache: bool,
+
+    /// Hours a cached response may be used for before the command fails
+    /// instead (overrides config file)
+    #[arg(long, value_name = "HOURS", global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    cache_max_age: Option<u64>,
+
+    /// Fail before writing anything unless the server reports one of these
+    /// versions, e.g. `1.4` or `>=1.4, <2` (overrides config file)
+    #[arg(long, value_name = "VERSION", global = true, value_parser = parse_version_req)]
+    expect_version: Option<VersionReq>,
+
+    /// The `[profiles.<name>]` section of the config file to use (overrides
+    /// `default_profile`)
+    #[arg(short, long, global = true)]
+    profile: Option<String>,
+
+    /// How to print fetched keys and hosts (overrides config file)
+    #[arg(long, value_enum, global = true)]
+    output: Option<commands::OutputFormat>,
+
+    /// The hash to show key fingerprints with
+    #[arg(long, value_enum, global = true, default_value_t)]
+    fingerprint_hash: commands::FingerprintHash,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Thresholds that abort `--write` when the change looks suspiciously large
+#[derive(Args, Debug)]
+struct ChangeLimitArgs {
+    /// Refuse to write a file that would be left with fewer than N entries
+    #[arg(long, value_name = "N", requires = "write")]
+    min_keys: Option<usize>,
+
+    /// Refuse to write if more than N existing entries would be removed
+    #[arg(long, value_name = "N", requires = "write")]
+    max_removals: Option<usize>,
+
+    /// Refuse to write if more than P percent of the existing entries would be added or removed
+    #[arg(long, value_name = "P", requires = "write")]
+    max_change_percent: Option<u32>,
+}
+
+impl ChangeLimitArgs {
+    fn limits(&self) -> commands::ChangeLimits {
+        commands::ChangeLimits {
+            min_entries: self.min_keys,
+            max_removals: self.max_removals,
+            max_change_percent: self.max_change_percent,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Fetch or write SSH keys from the server
     Ssh {
-        /// Write keys to authorized_keys file
-        #[arg(short, long)]
-        write: Option<String>,
+        /// Write keys to an authorized_keys file (`[ssh] path` in the config if no path is given)
+        #[arg(short, long, value_name = "PATH", num_args = 0..=1)]
+        write: Option<Option<String>>,
+
+        /// Only add new keys, keeping every existing one (the default unless `[ssh] mode` is set)
+        #[arg(long, conflicts_with_all = ["force", "managed"])]
+        additive: bool,
 
         /// Force overwrite existing keys (default is to only add new keys)
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "managed")]
         force: bool,
+
+        /// Keep the server's keys in a marked block that is fully replaced on each sync,
+        /// leaving every line outside it untouched
+        #[arg(short, long)]
+        managed: bool,
+
+        /// Show a diff of the changes `--write` would make without writing anything
+        #[arg(long, requires = "write")]
+        dry_run: bool,
+
+        /// Number of timestamped backups of the file to keep (0 disables backups; default 5)
+        #[arg(long, value_name = "N")]
+        keep_backups: Option<usize>,
+
+        /// Write the file even if it would contain no keys, or would remove a must-keep key
+        /// or the key used by the current SSH session
+        #[arg(long, requires = "write")]
+        allow_lockout: bool,
+
+        /// A key `--write` must never remove, in addition to `[ssh] must_keep` in the config
+        /// (repeatable)
+        #[arg(long = "must-keep", value_name = "KEY", requires = "write")]
+        must_keep: Vec<String>,
+
+        #[command(flatten)]
+        limits: ChangeLimitArgs,
+
+        /// Only fetch keys belonging to this user (repeatable)
+        #[arg(long = "user", value_name = "USER")]
+        users: Vec<String>,
+
+        /// Only fetch keys carrying all of these tags (repeatable)
+        #[arg(long = "all-of", value_name = "TAG")]
+        all_of: Vec<String>,
+
+        /// Only fetch keys carrying at least one of these tags (repeatable)
+        #[arg(long = "one-of", value_name = "TAG")]
+        one_of: Vec<String>,
+
+        /// Only fetch keys carrying none of these tags (repeatable)
+        #[arg(long = "none-of", value_name = "TAG")]
+        none_of: Vec<String>,
+
+        /// Only fetch the key with this fingerprint, e.g. `SHA256:KNrHOH...` or
+        /// `MD5:66:22:...` as sshd logs it (repeatable)
+        #[arg(long = "fingerprint", value_name = "FINGERPRINT", value_parser = parse_fingerprint)]
+        fingerprints: Vec<String>,
     },
 
     /// Fetch PGP keys from the server, or import them into your local GnuPG keyring
@@ -40,19 +169,186 @@ enum Commands {
 
     /// Fetch known hosts from the server
     KnownHosts {
-        /// Write known hosts to a file (adds new entries, preserving existing ones)
-        #[arg(short, long)]
-        write: Option<String>,
+        /// Write known hosts to a file, adding new entries and preserving existing ones
+        /// (`[known_hosts] path` in the config if no path is given)
+        #[arg(short, long, value_name = "PATH", num_args = 0..=1)]
+        write: Option<Option<String>>,
+
+        /// Only add new entries, keeping every existing one (the default unless
+        /// `[known_hosts] mode` is set)
+        #[arg(long, conflicts_with_all = ["force", "managed"])]
+        additive: bool,
 
         /// Force overwrite the file with the server's entries (default is to only add new entries)
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "managed")]
         force: bool,
+
+        /// Keep the server's entries in a marked block that exactly mirrors the server,
+        /// leaving every line outside it untouched
+        #[arg(short, long)]
+        managed: bool,
+
+        /// Show a diff of the changes `--write` would make without writing anything
+        #[arg(long, requires = "write")]
+        dry_run: bool,
+
+        /// Number of timestamped backups of the file to keep (0 disables backups; default 5)
+        #[arg(long, value_name = "N")]
+        keep_backups: Option<usize>,
+
+        #[command(flatten)]
+        limits: ChangeLimitArgs,
+    },
+
+    /// List a file's backups, or restore it from one (the latest by default)
+    Restore {
+        /// The file to restore (e.g. ~/.ssh/authorized_keys)
+        file: String,
+
+        /// The backup to restore, by timestamp (e.g. 2026-10-18T02-31-00) or file name
+        #[arg(short, long, conflicts_with = "list")]
+        backup: Option<String>,
+
+        /// List the available backups instead of restoring one
+        #[arg(short, long)]
+        list: bool,
+
+        /// Show a diff of the changes the restore would make without writing anything
+        #[arg(long, conflicts_with = "list")]
+        dry_run: bool,
+
+        /// Number of timestamped backups of the file to keep (0 disables backups; default 5)
+        #[arg(long, value_name = "N")]
+        keep_backups: Option<usize>,
+    },
+
+    /// Sync every `[[targets]]` entry in the config file in one pass
+    Sync {
+        /// Show the changes each target would get without writing or importing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Number of timestamped backups of each file to keep (0 disables backups; default 5)
+        #[arg(long, value_name = "N")]
+        keep_backups: Option<usize>,
+    },
+
+    /// Print a user's keys for sshd's `AuthorizedKeysCommand`, falling back to the cache
+    ///
+    /// Set in sshd_config as, e.g.:
+    ///
+    ///     AuthorizedKeysCommand /usr/local/bin/keys authorized-keys-command %u %t %f
+    ///     AuthorizedKeysCommandUser nobody
+    #[command(verbatim_doc_comment)]
+    AuthorizedKeysCommand {
+        /// The user logging in
+        user: String,
+
+        /// The type of the key the client offered (`%t`); only that key is printed
+        #[arg(requires = "fingerprint")]
+        key_type: Option<String>,
+
+        /// The fingerprint of the key the client offered (`%f`)
+        fingerprint: Option<String>,
+    },
+
+    /// Show the client's version and the version each server reports
+    Version,
+
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
 
     /// Initialize a default config file
     Init {},
 }
 
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// List the profiles defined in the config file
+    Profiles,
+}
+
+/// The write mode selected by the mutually exclusive `--additive`, `--force`
+/// and `--managed` flags, or the configured default when none is given.
+fn write_mode(
+    additive: bool,
+    force: bool,
+    managed: bool,
+    default: commands::WriteMode,
+) -> commands::WriteMode {
+    if additive {
+        commands::WriteMode::Additive
+    } else if force {
+        commands::WriteMode::Force
+    } else if managed {
+        commands::WriteMode::Managed
+    } else {
+        default
+    }
+}
+
+/// The file given to `--write`, or the configured default (`setting`) when
+/// `--write` is given without a path.
+fn write_path(path: &Option<String>, default: &Option<String>, setting: &str) -> Result<String> {
+    path.as_ref().or(default.as_ref()).cloned().ok_or_else(|| {
+        anyhow::anyhow!(
+            "--write needs a path, or a default set with `{setting}` in the config file"
+        )
+    })
+}
+
+/// A repeatable flag's values, or the configured default when the flag wasn't
+/// given.
+fn or_config(flag: &[String], config: &[String]) -> Vec<String> {
+    if flag.is_empty() {
+        config.to_vec()
+    } else {
+        flag.to_vec()
+    }
+}
+
+/// Parse `--fingerprint`, reporting the full reason it is invalid.
+fn parse_fingerprint(text: &str) -> Result<String, String> {
+    keys::utils::parse_fingerprint(text).map_err(|err| format!("{err:#}"))
+}
+
+/// Parse `--expect-version`, reporting the full reason it is invalid.
+fn parse_version_req(text: &str) -> Result<VersionReq, String> {
+    VersionReq::parse(text).map_err(|err| format!("{err:#}"))
+}
+
+/// Where responses are cached, and when the cache stands in for the server.
+/// Caching is skipped, with a warning, if there is no cache directory,
+/// unless the cache is needed to stand in for the server.
+fn cache_settings(cli: &Cli, config: &config::Config) -> Result<Option<cache::CacheSettings>> {
+    let fallback = if cli.offline {
+        cache::CacheFallback::Always
+    } else if cli.fallback_to_cache || config.fallback_to_cache {
+        cache::CacheFallback::WhenUnavailable
+    } else {
+        cache::CacheFallback::Never
+    };
+    let Some(dir) = cache::default_cache_dir() else {
+        if fallback != cache::CacheFallback::Never {
+            return Err(anyhow::anyhow!("Could not determine the cache directory"));
+        }
+        eprintln!(
+            "{}  Could not determine the cache directory; responses won't be cached",
+            "⚠️".yellow().bold()
+        );
+        return Ok(None);
+    };
+    let max_age_hours = cli.cache_max_age.unwrap_or(config.cache_max_age_hours);
+    Ok(Some(cache::CacheSettings {
+        dir,
+        fallback,
+        max_age: Duration::from_secs(max_age_hours * 3600),
+    }))
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -64,36 +360,233 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    // Load configuration, with CLI-provided path if specified
-    let config = config::load_config(cli.config.as_deref())?;
+    // `config profiles` lists every profile, so it reads the config file
+    // before any profile's settings are applied to it.
+    if let Commands::Config {
+        command: ConfigCommands::Profiles,
+    } = cli.command
+    {
+        let config = config::load_config_file(cli.config.as_deref())?;
+        let output = cli.output.unwrap_or(config.output);
+        return commands::list_profiles(&config, cli.profile.as_deref(), output);
+    }
 
-    // CLI server arg takes precedence over config file
-    let server_url = cli.server.unwrap_or(config.server_url);
+    // Load configuration, with CLI-provided path if specified, and apply the
+    // selected profile
+    let config = config::load_config(cli.config.as_deref(), cli.profile.as_deref())?;
+
+    // CLI args take precedence over config file
+    let timeout = Duration::from_secs(cli.timeout.unwrap_or(config.timeout_secs));
+    let connect_timeout =
+        Duration::from_secs(cli.connect_timeout.unwrap_or(config.connect_timeout_secs));
+    let retries = cli.retries.unwrap_or(config.retries);
+    let cache = cache_settings(&cli, &config)?;
+    let expected_version = config.version_requirement(cli.expect_version.as_ref())?;
+    let auth = config.auth.to_auth()?.map(Arc::new);
+    let verifier = config.signatures.to_verifier()?.map(Arc::new);
+    let servers: Vec<commands::Server> = or_config(&cli.servers, &config.server_urls())
+        .into_iter()
+        .map(|url| commands::Server {
+            url,
+            timeout,
+            connect_timeout,
+            retries,
+            cache: cache.clone(),
+            expected_version: expected_version.clone(),
+            auth: auth.clone(),
+            verifier: verifier.clone(),
+        })
+        .collect();
+    // PGP keys aren't merged, so they come from the first server
+    let server = &servers[0];
+    let output = cli.output.unwrap_or(config.output);
 
     match &cli.command {
-        Commands::Ssh { write, force } => {
+        Commands::Ssh {
+            write,
+            additive,
+            force,
+            managed,
+            dry_run,
+            keep_backups,
+            allow_lockout,
+            must_keep,
+            limits,
+            users,
+            all_of,
+            one_of,
+            none_of,
+            fingerprints,
+        } => {
+            let filter = commands::ssh_keys::KeyFilter {
+                users: or_config(users, &config.ssh.users),
+                all_of: or_config(all_of, &config.ssh.all_of),
+                one_of: or_config(one_of, &config.ssh.one_of),
+                none_of: or_config(none_of, &config.ssh.none_of),
+                fingerprints: fingerprints.clone(),
+            };
             if let Some(path) = write {
-                commands::ssh_keys::write_ssh_keys(&server_url, path, *force)?;
+                let path = write_path(path, &config.ssh.path, "[ssh] path")?;
+                let options = commands::WriteOptions {
+                    mode: write_mode(*additive, *force, *managed, config.ssh.mode),
+                    dry_run: *dry_run,
+                    backup_retention: keep_backups.unwrap_or(config.backup_retention),
+                    limits: limits.limits(),
+                };
+                let must_keep = config
+                    .ssh
+                    .must_keep
+                    .iter()
+                    .chain(must_keep)
+                    .cloned()
+                    .collect();
+                let lockout =
+                    commands::ssh_keys::LockoutProtection::from_env(*allow_lockout, must_keep);
+                commands::ssh_keys::write_ssh_keys(
+                    &servers,
+                    &path,
+                    &filter,
+                    &config.ssh.tag_options,
+                    &config.policy,
+                    &options,
+                    &lockout,
+                )?;
             } else {
-                commands::ssh_keys::fetch_ssh_keys(&server_url)?;
+                commands::ssh_keys::fetch_ssh_keys(
+                    &servers,
+                    &filter,
+                    output,
+                    config.ssh.expiry_warning_days,
+                    cli.fingerprint_hash,
+                )?;
             }
         }
         Commands::Pgp { import } => {
             if *import {
-                commands::pgp_keys::import_pgp_keys(&server_url)?;
+                commands::pgp_keys::import_pgp_keys(server)?;
             } else {
-                commands::pgp_keys::fetch_pgp_keys(&server_url)?;
+                commands::pgp_keys::fetch_pgp_keys(server, output)?;
             }
         }
-        Commands::KnownHosts { write, force } => {
+        Commands::KnownHosts {
+            write,
+            additive,
+            force,
+            managed,
+            dry_run,
+            keep_backups,
+            limits,
+        } => {
             if let Some(path) = write {
-                commands::known_hosts::write_known_hosts(&server_url, path, *force)?;
+                let path = write_path(path, &config.known_hosts.path, "[known_hosts] path")?;
+                let options = commands::WriteOptions {
+                    mode: write_mode(*additive, *force, *managed, config.known_hosts.mode),
+                    dry_run: *dry_run,
+                    backup_retention: keep_backups.unwrap_or(config.backup_retention),
+                    limits: limits.limits(),
+                };
+                commands::known_hosts::write_known_hosts(
+                    &servers,
+                    &path,
+                    &config.policy,
+                    &options,
+                )?;
+            } else {
+                commands::known_hosts::fetch_known_hosts(&servers, output, cli.fingerprint_hash)?;
+            }
+        }
+        Commands::Restore {
+            file,
+            backup,
+            list,
+            dry_run,
+            keep_backups,
+        } => {
+            if *list {
+                commands::restore::list_file_backups(file)?;
             } else {
-                commands::known_hosts::fetch_known_hosts(&server_url)?;
+                let options = commands::WriteOptions {
+                    dry_run: *dry_run,
+                    backup_retention: keep_backups.unwrap_or(config.backup_retention),
+                    ..commands::WriteOptions::default()
+                };
+                commands::restore::restore_file(file, backup.as_deref(), &options)?;
             }
         }
-        // `Init` is handled above, before config loading.
+        Commands::Sync {
+            dry_run,
+            keep_backups,
+        } => {
+            let lockout =
+                commands::ssh_keys::LockoutProtection::from_env(false, config.ssh.must_keep);
+            commands::sync_targets(
+                &servers,
+                &config.targets,
+                *dry_run,
+                keep_backups.unwrap_or(config.backup_retention),
+                &config.ssh.tag_options,
+                &config.policy,
+                &lockout,
+            )?;
+        }
+        Commands::AuthorizedKeysCommand {
+            user,
+            key_type,
+            fingerprint,
+        } => {
+            let settings = &config.authorized_keys_command;
+            // sshd holds up the login until the command answers, so it gets a
+            // short timeout, no retries by default, and the cache to fall
+            // back on
+            let timeout = Duration::from_secs(cli.timeout.unwrap_or(settings.timeout_secs));
+            let servers: Vec<commands::Server> = servers
+                .iter()
+                .map(|server| commands::Server {
+                    timeout,
+                    connect_timeout: server.connect_timeout.min(timeout),
+                    retries: cli.retries.unwrap_or(0),
+                    cache: server.cache.clone().map(|cache| cache::CacheSettings {
+                        fallback: match cache.fallback {
+                            cache::CacheFallback::Always => cache::CacheFallback::Always,
+                            _ => cache::CacheFallback::WhenUnavailable,
+                        },
+                        ..cache
+                    }),
+                    ..server.clone()
+                })
+                .collect();
+            let filter = commands::ssh_keys::KeyFilter {
+                users: Vec::new(),
+                all_of: settings.all_of.clone(),
+                one_of: settings.one_of.clone(),
+                none_of: settings.none_of.clone(),
+                fingerprints: Vec::new(),
+            };
+            let offered =
+                key_type
+                    .as_ref()
+                    .zip(fingerprint.as_ref())
+                    .map(
+                        |(key_type, fingerprint)| commands::authorized_keys_command::OfferedKey {
+                            key_type: key_type.clone(),
+                            fingerprint: fingerprint.clone(),
+                        },
+                    );
+            commands::authorized_keys_command(
+                &servers,
+                user,
+                &filter,
+                &config.ssh.tag_options,
+                &config.policy,
+                offered.as_ref(),
+            )?;
+        }
+        Commands::Version => {
+            commands::show_versions(&servers, expected_version.as_ref())?;
+        }
+        // `Init` and `Config` are handled above, before config loading.
         Commands::Init {} => unreachable!("Init is handled before config loading"),
+        Commands::Config { .. } => unreachable!("Config is handled before config loading"),
     }
 
     Ok(())
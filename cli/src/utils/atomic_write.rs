@@ -0,0 +1,261 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Mode applied to a file that doesn't exist yet. sshd's `StrictModes` rejects
+/// an `authorized_keys` that is group- or world-writable, so default to
+/// owner-only access rather than whatever the process umask allows.
+#[cfg(unix)]
+const NEW_FILE_MODE: u32 = 0o600;
+
+/// Mode applied to a parent directory (e.g. `~/.ssh`) that we have to create.
+#[cfg(unix)]
+const NEW_DIR_MODE: u32 = 0o700;
+
+/// Atomically replace the contents of `path`.
+///
+/// The contents are written to a temporary file in the same directory, flushed
+/// to disk, given the original file's permissions and ownership (or `0600` for
+/// a new file), and then renamed over the target. A crash or full disk part way
+/// through therefore leaves either the old file or the new one in place, never
+/// a truncated mix. Missing parent directories are created with `0700`.
+///
+/// If `path` is a symlink, the file it points to is replaced and the link is
+/// left intact.
+pub fn write_file_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let target = resolve_symlink(path)?;
+    let parent = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    if !parent.exists() {
+        create_private_dir(&parent)?;
+    }
+
+    let existing_metadata = fs::metadata(&target).ok();
+
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(&format!(".{file_name}."))
+        .suffix(".tmp")
+        .tempfile_in(&parent)
+        .with_context(|| {
+            format!(
+                "Failed to create temporary file in directory: {}",
+                parent.display()
+            )
+        })?;
+
+    temp_file
+        .write_all(contents)
+        .with_context(|| format!("Failed to write to file: {}", target.display()))?;
+
+    apply_metadata(temp_file.path(), existing_metadata.as_ref())?;
+
+    temp_file
+        .as_file()
+        .sync_all()
+        .with_context(|| format!("Failed to flush file to disk: {}", target.display()))?;
+
+    temp_file
+        .persist(&target)
+        .map_err(|e| e.error)
+        .with_context(|| format!("Failed to replace file: {}", target.display()))?;
+
+    sync_dir(&parent);
+
+    Ok(())
+}
+
+/// Follow `path` if it is a symlink so the link itself isn't replaced by the
+/// rename. Non-symlinks (including paths that don't exist yet) are returned
+/// unchanged.
+fn resolve_symlink(path: &Path) -> Result<PathBuf> {
+    if path.is_symlink() {
+        fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve symlink: {}", path.display()))
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Create `dir` and any missing ancestors, restricting each directory created
+/// to its owner. Ancestors that already exist are left alone.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+
+        builder.mode(NEW_DIR_MODE);
+    }
+    builder
+        .create(dir)
+        .with_context(|| format!("Failed to create parent directory: {}", dir.display()))?;
+
+    // The mode given to the builder is still masked by the umask
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(dir, fs::Permissions::from_mode(NEW_DIR_MODE)).with_context(|| {
+            format!("Failed to set permissions on directory: {}", dir.display())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Give the temporary file the mode and owner of the file it will replace, or
+/// the default private mode when there is no existing file.
+#[cfg(unix)]
+fn apply_metadata(temp_path: &Path, existing: Option<&fs::Metadata>) -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let mode = existing
+        .map(|metadata| metadata.mode() & 0o7777)
+        .unwrap_or(NEW_FILE_MODE);
+    fs::set_permissions(temp_path, fs::Permissions::from_mode(mode))
+        .context("Failed to set permissions on temporary file")?;
+
+    if let Some(metadata) = existing {
+        let temp_metadata =
+            fs::metadata(temp_path).context("Failed to read temporary file metadata")?;
+        if temp_metadata.uid() != metadata.uid() || temp_metadata.gid() != metadata.gid() {
+            std::os::unix::fs::chown(temp_path, Some(metadata.uid()), Some(metadata.gid()))
+                .with_context(|| {
+                    format!(
+                        "Failed to preserve file ownership ({}:{})",
+                        metadata.uid(),
+                        metadata.gid()
+                    )
+                })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn apply_metadata(temp_path: &Path, existing: Option<&fs::Metadata>) -> Result<()> {
+    if let Some(metadata) = existing {
+        fs::set_permissions(temp_path, metadata.permissions())
+            .context("Failed to set permissions on temporary file")?;
+    }
+    Ok(())
+}
+
+/// Flush the directory entry for the rename to disk. Best effort: not every
+/// platform or filesystem allows opening a directory for syncing.
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_file_atomic_creates_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+
+        write_file_atomic(&path, b"ssh-rsa AAAAB1 user@host\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "ssh-rsa AAAAB1 user@host\n"
+        );
+        // Only the target remains; the temporary file was renamed into place.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_file_atomic_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        fs::write(&path, "a much longer original line that must not linger\n").unwrap();
+
+        write_file_atomic(&path, b"short\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "short\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_new_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+
+        write_file_atomic(&path, b"content\n").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_preserves_existing_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_file_atomic(&path, b"new\n").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o644);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_creates_private_parent_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let home = dir.path().join("home");
+        let ssh_dir = home.join(".ssh");
+        let path = ssh_dir.join("authorized_keys");
+
+        write_file_atomic(&path, b"content\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content\n");
+        for created in [&home, &ssh_dir] {
+            let mode = fs::metadata(created).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, 0o700, "{}", created.display());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_follows_symlink() {
+        let dir = tempdir().unwrap();
+        let real = dir.path().join("real_authorized_keys");
+        let link = dir.path().join("authorized_keys");
+        fs::write(&real, "old\n").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        write_file_atomic(&link, b"new\n").unwrap();
+
+        // The link still points at the real file, which holds the new contents.
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new\n");
+    }
+}
@@ -1,26 +1,168 @@
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Utc};
 
-/// The backup path for a file: the original path with `.bak` appended to its
-/// name (e.g. `~/.ssh/authorized_keys` -> `~/.ssh/authorized_keys.bak`).
-fn backup_path(path: &Path) -> PathBuf {
+use super::write_file_atomic;
+
+/// How many backups of a file are kept by default. Older ones are removed each
+/// time a new backup is made.
+pub const DEFAULT_BACKUP_RETENTION: usize = 5;
+
+/// Timestamp format embedded in backup names. Colons are replaced with dashes
+/// so the names are valid on every platform, and the fixed-width UTC format
+/// keeps backups in chronological order when sorted.
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+/// A backup of a file, as found alongside it on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Path to the backup file.
+    pub path: PathBuf,
+    /// When the backup was made (UTC), or `None` for a legacy `<name>.bak`
+    /// written before backups were timestamped.
+    pub timestamp: Option<NaiveDateTime>,
+    /// Disambiguates backups made within the same second (`0` for the first).
+    sequence: u32,
+}
+
+impl Backup {
+    /// The part of the backup's name that identifies it among the other
+    /// backups of the same file (e.g. `2026-10-18T02-31-00`), or `bak` for a
+    /// legacy backup.
+    pub fn label(&self) -> String {
+        match self.timestamp {
+            Some(timestamp) if self.sequence == 0 => {
+                timestamp.format(BACKUP_TIMESTAMP_FORMAT).to_string()
+            }
+            Some(timestamp) => format!(
+                "{}.{}",
+                timestamp.format(BACKUP_TIMESTAMP_FORMAT),
+                self.sequence
+            ),
+            None => "bak".to_string(),
+        }
+    }
+}
+
+/// The backup path for a file made at `timestamp`: the original path with
+/// `.<timestamp>.bak` appended to its name (e.g. `~/.ssh/authorized_keys` ->
+/// `~/.ssh/authorized_keys.2026-10-18T02-31-00.bak`). A non-zero `sequence`
+/// is added before `.bak` to keep backups made in the same second apart.
+fn backup_path(path: &Path, timestamp: NaiveDateTime, sequence: u32) -> PathBuf {
     let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", timestamp.format(BACKUP_TIMESTAMP_FORMAT)));
+    if sequence > 0 {
+        name.push(format!(".{sequence}"));
+    }
     name.push(".bak");
     PathBuf::from(name)
 }
 
+/// Parse the backup label (the part between `<name>.` and `.bak`) into its
+/// timestamp and sequence number. Returns `None` for anything that isn't a
+/// backup made by [`backup_existing_file`].
+fn parse_backup_label(label: &str) -> Option<(NaiveDateTime, u32)> {
+    let (timestamp, sequence) = match label.split_once('.') {
+        Some((timestamp, sequence)) => (timestamp, sequence.parse().ok()?),
+        None => (label, 0),
+    };
+    let timestamp = NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).ok()?;
+    Some((timestamp, sequence))
+}
+
+/// List the backups of `path`, newest first. A legacy `<name>.bak` is listed
+/// last, as the oldest.
+pub fn list_backups(path: &Path) -> Result<Vec<Backup>> {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{file_name}.");
+    let mut backups = Vec::new();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to list backups in directory: {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry
+            .with_context(|| format!("Failed to list backups in directory: {}", dir.display()))?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let Some(rest) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+
+        if rest == "bak" {
+            backups.push(Backup {
+                path: entry.path(),
+                timestamp: None,
+                sequence: 0,
+            });
+        } else if let Some(label) = rest.strip_suffix(".bak")
+            && let Some((timestamp, sequence)) = parse_backup_label(label)
+        {
+            backups.push(Backup {
+                path: entry.path(),
+                timestamp: Some(timestamp),
+                sequence,
+            });
+        }
+    }
+
+    // `None` sorts before any timestamp, so reversing puts legacy backups last.
+    backups.sort_by_key(|backup| (backup.timestamp, backup.sequence));
+    backups.reverse();
+    Ok(backups)
+}
+
 /// Back up an existing file before it is overwritten, copying it to a sibling
-/// `<name>.bak`.
+/// `<name>.<timestamp>.bak` and then removing all but the newest `retention`
+/// backups.
 ///
-/// This is a no-op when the file does not yet exist (nothing to preserve).
-/// Returns the backup path when a backup was made, or `None` otherwise.
-pub fn backup_existing_file(path: &Path) -> Result<Option<PathBuf>> {
-    if !path.exists() {
+/// This is a no-op when the file does not yet exist (nothing to preserve) or
+/// when `retention` is zero (backups disabled). Returns the backup path when a
+/// backup was made, or `None` otherwise.
+pub fn backup_existing_file(path: &Path, retention: usize) -> Result<Option<PathBuf>> {
+    backup_existing_file_at(path, retention, Utc::now().naive_utc())
+}
+
+/// Overwrite `path` with `contents`, backing up the existing file first so a
+/// bad merge or a surprising server response can be recovered from. The write
+/// is atomic, so an interrupted one can't leave a truncated file behind.
+pub fn replace_file_with_backup(path: &Path, contents: &[u8], retention: usize) -> Result<()> {
+    if let Some(backup) = backup_existing_file(path, retention)? {
+        println!("📦 Backed up existing file to {}", backup.display());
+    }
+
+    write_file_atomic(path, contents)
+}
+
+/// [`backup_existing_file`] with an explicit timestamp, so tests can make
+/// several backups without waiting for the clock to tick.
+fn backup_existing_file_at(
+    path: &Path,
+    retention: usize,
+    now: NaiveDateTime,
+) -> Result<Option<PathBuf>> {
+    if !path.exists() || retention == 0 {
         return Ok(None);
     }
 
-    let backup = backup_path(path);
+    let mut sequence = 0;
+    let mut backup = backup_path(path, now, sequence);
+    while backup.exists() {
+        sequence += 1;
+        backup = backup_path(path, now, sequence);
+    }
+
     std::fs::copy(path, &backup).with_context(|| {
         format!(
             "Failed to back up existing file from {} to {}",
@@ -29,38 +171,79 @@ pub fn backup_existing_file(path: &Path) -> Result<Option<PathBuf>> {
         )
     })?;
 
+    prune_backups(path, retention)?;
+
     Ok(Some(backup))
 }
 
+/// Remove all but the newest `retention` backups of `path`.
+fn prune_backups(path: &Path, retention: usize) -> Result<()> {
+    for old in list_backups(path)?.into_iter().skip(retention) {
+        std::fs::remove_file(&old.path)
+            .with_context(|| format!("Failed to remove old backup: {}", old.path.display()))?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
     use tempfile::tempdir;
 
+    fn at(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).unwrap()
+    }
+
     #[test]
-    fn test_backup_path_appends_bak() {
+    fn test_backup_path_appends_timestamp() {
         assert_eq!(
-            backup_path(Path::new("/home/user/.ssh/authorized_keys")),
-            PathBuf::from("/home/user/.ssh/authorized_keys.bak")
+            backup_path(
+                Path::new("/home/user/.ssh/authorized_keys"),
+                at("2026-10-18T02-31-00"),
+                0
+            ),
+            PathBuf::from("/home/user/.ssh/authorized_keys.2026-10-18T02-31-00.bak")
         );
-        // An existing extension is preserved, not replaced.
+        // An existing extension is preserved, not replaced, and a sequence
+        // number separates backups made in the same second.
         assert_eq!(
-            backup_path(Path::new("/tmp/known_hosts.d")),
-            PathBuf::from("/tmp/known_hosts.d.bak")
+            backup_path(
+                Path::new("/tmp/known_hosts.d"),
+                at("2026-10-18T02-31-00"),
+                2
+            ),
+            PathBuf::from("/tmp/known_hosts.d.2026-10-18T02-31-00.2.bak")
         );
     }
 
+    #[test]
+    fn test_parse_backup_label() {
+        assert_eq!(
+            parse_backup_label("2026-10-18T02-31-00"),
+            Some((at("2026-10-18T02-31-00"), 0))
+        );
+        assert_eq!(
+            parse_backup_label("2026-10-18T02-31-00.3"),
+            Some((at("2026-10-18T02-31-00"), 3))
+        );
+        assert_eq!(parse_backup_label("not-a-timestamp"), None);
+        assert_eq!(parse_backup_label("2026-10-18T02-31-00.x"), None);
+    }
+
     #[test]
     fn test_backup_existing_file_copies_contents() {
         let dir = tempdir().unwrap();
         let path = dir.path().join("authorized_keys");
         fs::write(&path, "original contents\n").unwrap();
 
-        let backup = backup_existing_file(&path).unwrap();
+        let backup = backup_existing_file_at(&path, 5, at("2026-10-18T02-31-00")).unwrap();
 
         let backup = backup.expect("a backup should be made for an existing file");
-        assert_eq!(backup, path.with_file_name("authorized_keys.bak"));
+        assert_eq!(
+            backup,
+            path.with_file_name("authorized_keys.2026-10-18T02-31-00.bak")
+        );
         assert_eq!(fs::read_to_string(&backup).unwrap(), "original contents\n");
         // The original file is left in place for the caller to overwrite.
         assert_eq!(fs::read_to_string(&path).unwrap(), "original contents\n");
@@ -71,9 +254,88 @@ mod tests {
         let dir = tempdir().unwrap();
         let path = dir.path().join("does_not_exist");
 
-        let backup = backup_existing_file(&path).unwrap();
+        let backup = backup_existing_file(&path, 5).unwrap();
+
+        assert!(backup.is_none());
+        assert!(list_backups(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backup_existing_file_noop_when_retention_is_zero() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        fs::write(&path, "original contents\n").unwrap();
+
+        let backup = backup_existing_file(&path, 0).unwrap();
 
         assert!(backup.is_none());
-        assert!(!path.with_file_name("does_not_exist.bak").exists());
+        assert!(list_backups(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backup_existing_file_same_second_does_not_overwrite() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        let now = at("2026-10-18T02-31-00");
+
+        fs::write(&path, "first\n").unwrap();
+        let first = backup_existing_file_at(&path, 5, now).unwrap().unwrap();
+        fs::write(&path, "second\n").unwrap();
+        let second = backup_existing_file_at(&path, 5, now).unwrap().unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "first\n");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "second\n");
+
+        // The later backup is listed first.
+        let backups = list_backups(&path).unwrap();
+        assert_eq!(backups[0].path, second);
+        assert_eq!(backups[1].path, first);
+    }
+
+    #[test]
+    fn test_backup_existing_file_prunes_to_retention() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+
+        for (i, timestamp) in [
+            "2026-10-18T01-00-00",
+            "2026-10-18T02-00-00",
+            "2026-10-18T03-00-00",
+            "2026-10-18T04-00-00",
+        ]
+        .iter()
+        .enumerate()
+        {
+            fs::write(&path, format!("version {i}\n")).unwrap();
+            backup_existing_file_at(&path, 2, at(timestamp)).unwrap();
+        }
+
+        let backups = list_backups(&path).unwrap();
+        let labels: Vec<String> = backups.iter().map(Backup::label).collect();
+        assert_eq!(labels, vec!["2026-10-18T04-00-00", "2026-10-18T03-00-00"]);
+        assert_eq!(fs::read_to_string(&backups[0].path).unwrap(), "version 3\n");
+    }
+
+    #[test]
+    fn test_list_backups_ignores_unrelated_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("authorized_keys");
+        fs::write(&path, "current\n").unwrap();
+        fs::write(
+            dir.path().join("authorized_keys.2026-10-18T02-31-00.bak"),
+            "b",
+        )
+        .unwrap();
+        fs::write(dir.path().join("authorized_keys.bak"), "legacy").unwrap();
+        fs::write(dir.path().join("authorized_keys.old"), "x").unwrap();
+        fs::write(dir.path().join("authorized_keys.garbage.bak"), "x").unwrap();
+        fs::write(dir.path().join("known_hosts.2026-10-18T02-31-00.bak"), "x").unwrap();
+
+        let backups = list_backups(&path).unwrap();
+        let labels: Vec<String> = backups.iter().map(Backup::label).collect();
+
+        // The legacy `.bak` is kept, but listed as the oldest.
+        assert_eq!(labels, vec!["2026-10-18T02-31-00", "bak"]);
     }
 }
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use colored::Colorize;
+use similar::TextDiff;
+
+/// Lines of unchanged context shown around each change in a unified diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Render a unified diff between the current contents of a file and the
+/// contents that would replace it, labelled with the file's path.
+///
+/// Returns an empty string when the contents are identical.
+pub fn unified_diff(path: &Path, current: &str, proposed: &str) -> String {
+    if current == proposed {
+        return String::new();
+    }
+
+    let label = path.display().to_string();
+    TextDiff::from_lines(current, proposed)
+        .unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .header(&label, &label)
+        .to_string()
+}
+
+/// Print a colored unified diff between the current and proposed contents of a
+/// file, used by `--dry-run` to preview a write without touching disk.
+pub fn print_unified_diff(path: &Path, current: &str, proposed: &str) {
+    let diff = unified_diff(path, current, proposed);
+    if diff.is_empty() {
+        println!("{}", format!("No changes to {}", path.display()).italic());
+        return;
+    }
+
+    for line in diff.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_identical_is_empty() {
+        let path = Path::new("/tmp/authorized_keys");
+        assert_eq!(unified_diff(path, "ssh-rsa A a@b\n", "ssh-rsa A a@b\n"), "");
+    }
+
+    #[test]
+    fn test_unified_diff_shows_added_and_removed_lines() {
+        let path = Path::new("/tmp/authorized_keys");
+        let diff = unified_diff(
+            path,
+            "ssh-rsa KEEP me@host\nssh-rsa OLD old@host\n",
+            "ssh-rsa KEEP me@host\nssh-rsa NEW new@host\n",
+        );
+
+        assert!(diff.starts_with("--- /tmp/authorized_keys\n+++ /tmp/authorized_keys\n"));
+        assert!(diff.contains("\n ssh-rsa KEEP me@host\n"));
+        assert!(diff.contains("\n-ssh-rsa OLD old@host\n"));
+        assert!(diff.contains("\n+ssh-rsa NEW new@host\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_new_file() {
+        // A file that doesn't exist yet diffs against empty contents.
+        let path = Path::new("/tmp/known_hosts");
+        let diff = unified_diff(path, "", "github.com ssh-rsa KEY\n");
+        assert!(diff.contains("\n+github.com ssh-rsa KEY\n"));
+        assert!(!diff.contains("\n-"));
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::Result;
+
+/// Prefix of the comment line opening a managed block. The owning server's URL
+/// follows it, so several servers can each own a block in the same file.
+const BEGIN_MARKER_PREFIX: &str = "# BEGIN keys-managed";
+
+/// The comment line closing a managed block.
+pub const END_MARKER: &str = "# END keys-managed";
+
+/// The comment line opening the block owned by `owner` (a server URL).
+pub fn begin_marker(owner: &str) -> String {
+    format!("{BEGIN_MARKER_PREFIX} {}", owner_id(owner))
+}
+
+/// Normalize a server URL for use in a marker, so `https://keys.example.com`
+/// and `https://keys.example.com/` own the same block.
+fn owner_id(owner: &str) -> &str {
+    owner.trim().trim_end_matches('/')
+}
+
+/// A file split around the managed block belonging to one owner.
+///
+/// Every line outside the block (including comments, blank lines, and other
+/// owners' blocks) is kept verbatim in `before` and `after`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedFile {
+    owner: String,
+    /// Lines preceding the block (or the whole file when there is no block).
+    pub before: Vec<String>,
+    /// Lines between the block's markers, or `None` if the file has no block
+    /// for this owner yet.
+    pub block: Option<Vec<String>>,
+    /// Lines following the block.
+    pub after: Vec<String>,
+}
+
+impl ManagedFile {
+    /// Split `content` around the block owned by `owner`.
+    ///
+    /// Fails if the block is opened but never closed, or if another block is
+    /// opened inside it, rather than guessing where the managed lines end and
+    /// risking rewriting local entries.
+    pub fn parse(content: &str, owner: &str) -> Result<Self> {
+        let begin = begin_marker(owner);
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+        let Some(start) = lines.iter().position(|line| line.trim() == begin) else {
+            return Ok(Self {
+                owner: owner.to_string(),
+                before: lines,
+                block: None,
+                after: Vec::new(),
+            });
+        };
+
+        let mut end = None;
+        for (offset, line) in lines[start + 1..].iter().enumerate() {
+            let line = line.trim();
+            if line == END_MARKER {
+                end = Some(start + 1 + offset);
+                break;
+            }
+            if line.starts_with(BEGIN_MARKER_PREFIX) {
+                return Err(anyhow::anyhow!(
+                    "Managed block `{begin}` contains another block's `{line}` before its `{END_MARKER}`; fix the markers by hand before syncing"
+                ));
+            }
+        }
+        let Some(end) = end else {
+            return Err(anyhow::anyhow!(
+                "Managed block `{begin}` has no closing `{END_MARKER}`; fix the markers by hand before syncing"
+            ));
+        };
+
+        if lines[end + 1..].iter().any(|line| line.trim() == begin) {
+            return Err(anyhow::anyhow!(
+                "Managed block `{begin}` appears more than once; fix the markers by hand before syncing"
+            ));
+        }
+
+        Ok(Self {
+            owner: owner.to_string(),
+            before: lines[..start].to_vec(),
+            block: Some(lines[start + 1..end].to_vec()),
+            after: lines[end + 1..].to_vec(),
+        })
+    }
+
+    /// The lines currently inside the block (empty when there is no block).
+    pub fn managed_lines(&self) -> &[String] {
+        self.block.as_deref().unwrap_or_default()
+    }
+
+    /// Every line outside the block, in file order.
+    pub fn unmanaged_lines(&self) -> impl Iterator<Item = &String> {
+        self.before.iter().chain(self.after.iter())
+    }
+
+    /// Render the file with the block's contents replaced by `block_lines`. A
+    /// file without a block gets one appended, separated from any existing
+    /// content by a blank line.
+    pub fn render(&self, block_lines: &[String]) -> String {
+        let mut lines: Vec<&str> = self.before.iter().map(String::as_str).collect();
+        if self.block.is_none() && lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push("");
+        }
+
+        let begin = begin_marker(&self.owner);
+        lines.push(&begin);
+        lines.extend(block_lines.iter().map(String::as_str));
+        lines.push(END_MARKER);
+        lines.extend(self.after.iter().map(String::as_str));
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "https://keys.example.com";
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_begin_marker_ignores_trailing_slash() {
+        assert_eq!(
+            begin_marker("https://keys.example.com/"),
+            "# BEGIN keys-managed https://keys.example.com"
+        );
+        assert_eq!(
+            begin_marker(OWNER),
+            begin_marker("https://keys.example.com/")
+        );
+    }
+
+    #[test]
+    fn test_parse_without_block() {
+        let file = ManagedFile::parse("# my keys\nssh-rsa LOCAL me@host\n", OWNER).unwrap();
+
+        assert_eq!(file.before, lines(&["# my keys", "ssh-rsa LOCAL me@host"]));
+        assert!(file.block.is_none());
+        assert!(file.managed_lines().is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_block() {
+        let content = "ssh-rsa LOCAL me@host\n\
+                       # BEGIN keys-managed https://keys.example.com\n\
+                       ssh-rsa SERVER a@b\n\
+                       # END keys-managed\n\
+                       # trailing comment\n";
+        let file = ManagedFile::parse(content, OWNER).unwrap();
+
+        assert_eq!(file.before, lines(&["ssh-rsa LOCAL me@host"]));
+        assert_eq!(file.managed_lines(), lines(&["ssh-rsa SERVER a@b"]));
+        assert_eq!(file.after, lines(&["# trailing comment"]));
+    }
+
+    #[test]
+    fn test_parse_leaves_other_owners_blocks_outside() {
+        let content = "# BEGIN keys-managed https://other.example.com\n\
+                       ssh-rsa OTHER a@b\n\
+                       # END keys-managed\n\
+                       # BEGIN keys-managed https://keys.example.com\n\
+                       ssh-rsa MINE a@b\n\
+                       # END keys-managed\n";
+        let file = ManagedFile::parse(content, OWNER).unwrap();
+
+        assert_eq!(
+            file.before,
+            lines(&[
+                "# BEGIN keys-managed https://other.example.com",
+                "ssh-rsa OTHER a@b",
+                "# END keys-managed",
+            ])
+        );
+        assert_eq!(file.managed_lines(), lines(&["ssh-rsa MINE a@b"]));
+        assert!(file.after.is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_unclosed_block() {
+        let content = "# BEGIN keys-managed https://keys.example.com\nssh-rsa A a@b\n";
+        let result = ManagedFile::parse(content, OWNER);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("no closing"));
+    }
+
+    #[test]
+    fn test_parse_rejects_nested_block() {
+        let content = "# BEGIN keys-managed https://keys.example.com\n\
+                       # BEGIN keys-managed https://other.example.com\n\
+                       # END keys-managed\n";
+        assert!(ManagedFile::parse(content, OWNER).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_duplicate_block() {
+        let content = "# BEGIN keys-managed https://keys.example.com\n\
+                       # END keys-managed\n\
+                       # BEGIN keys-managed https://keys.example.com\n\
+                       # END keys-managed\n";
+        assert!(ManagedFile::parse(content, OWNER).is_err());
+    }
+
+    #[test]
+    fn test_render_appends_block_after_blank_line() {
+        let file = ManagedFile::parse("ssh-rsa LOCAL me@host\n", OWNER).unwrap();
+
+        assert_eq!(
+            file.render(&lines(&["ssh-rsa SERVER a@b"])),
+            "ssh-rsa LOCAL me@host\n\
+             \n\
+             # BEGIN keys-managed https://keys.example.com\n\
+             ssh-rsa SERVER a@b\n\
+             # END keys-managed\n"
+        );
+    }
+
+    #[test]
+    fn test_render_replaces_block_in_place() {
+        let content = "# header\n\
+                       # BEGIN keys-managed https://keys.example.com\n\
+                       ssh-rsa OLD a@b\n\
+                       # END keys-managed\n\
+                       ssh-rsa LOCAL me@host\n";
+        let file = ManagedFile::parse(content, OWNER).unwrap();
+
+        assert_eq!(
+            file.render(&lines(&["ssh-rsa NEW a@b"])),
+            "# header\n\
+             # BEGIN keys-managed https://keys.example.com\n\
+             ssh-rsa NEW a@b\n\
+             # END keys-managed\n\
+             ssh-rsa LOCAL me@host\n"
+        );
+    }
+
+    #[test]
+    fn test_render_empty_file() {
+        let file = ManagedFile::parse("", OWNER).unwrap();
+
+        assert_eq!(
+            file.render(&[]),
+            "# BEGIN keys-managed https://keys.example.com\n# END keys-managed\n"
+        );
+    }
+}
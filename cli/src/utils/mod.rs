@@ -1,5 +1,17 @@
+pub mod atomic_write;
 pub mod backup;
+pub mod diff;
+pub mod managed_block;
 pub mod pretty_print;
+pub mod ssh_key;
+pub mod wire;
+pub mod yaml;
 
+pub use atomic_write::*;
 pub use backup::*;
+pub use diff::*;
+pub use managed_block::*;
 pub use pretty_print::*;
+pub use ssh_key::*;
+pub use wire::*;
+pub use yaml::*;
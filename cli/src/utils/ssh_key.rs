@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use openssl::hash::{MessageDigest, hash};
+use openssl::sha::sha256;
+
+use super::Reader;
+
+/// The suffix of OpenSSH certificate key types, e.g.
+/// `ssh-ed25519-cert-v01@openssh.com`.
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+
+/// An SSH public key, parsed and checked from its OpenSSH text form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    /// The key type, e.g. `ssh-ed25519` or `ssh-rsa-cert-v01@openssh.com`.
+    pub key_type: String,
+    /// The decoded key blob.
+    pub blob: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Parse the `<type> <base64>` at the start of an SSH key line, ignoring
+    /// any comment after it.
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut parts = line.split_whitespace();
+        let key_type = parts.next().context("the key is empty")?;
+        let data = parts
+            .next()
+            .with_context(|| format!("the {key_type} key has no key data"))?;
+        Self::from_parts(key_type, data)
+    }
+
+    /// Parse a key given as its type and base64 data, as known hosts are.
+    /// The type must match the one embedded in the key, which is then checked
+    /// as [`PublicKey::from_blob`] does.
+    pub fn from_parts(key_type: &str, data: &str) -> Result<Self> {
+        let blob = STANDARD
+            .decode(data)
+            .with_context(|| format!("the {key_type:?} key data isn't valid base64"))?;
+        let embedded = Reader::new(&blob, "the key").string()?;
+        if embedded != key_type.as_bytes() {
+            return Err(anyhow::anyhow!(
+                "the key is declared as {key_type:?} but is actually {:?}",
+                String::from_utf8_lossy(embedded)
+            ));
+        }
+        Self::from_blob(&blob)
+    }
+
+    /// Parse a decoded key blob, as embedded in certificates and signatures.
+    ///
+    /// The key's fields must be well formed for its type: an RSA key needs its
+    /// exponent and modulus, an Ed25519 key 32 bytes, an ECDSA key an
+    /// uncompressed point of the size its named curve uses, and so on.
+    /// Certificates are checked the same way, along with their signing key.
+    /// Keys of other types aren't checked further.
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(blob, "the key");
+        let key_type = String::from_utf8_lossy(reader.string()?).into_owned();
+        match key_type.strip_suffix(CERT_SUFFIX) {
+            Some(base_type) => read_certificate(&mut reader, base_type)?,
+            None => read_key_fields(&mut reader, &key_type)?,
+        }
+        reader.finish()?;
+        Ok(Self {
+            key_type,
+            blob: blob.to_vec(),
+        })
+    }
+
+    /// The key's algorithm: its type, or for a certificate, the type of the
+    /// key it certifies, e.g. `ssh-ed25519`.
+    pub fn algorithm(&self) -> &str {
+        self.key_type
+            .strip_suffix(CERT_SUFFIX)
+            .unwrap_or(&self.key_type)
+    }
+
+    /// Whether the key is held on a FIDO hardware security key (`sk-*`).
+    pub fn is_security_key(&self) -> bool {
+        self.algorithm().starts_with("sk-")
+    }
+
+    /// The size of the modulus of an RSA key or certificate, in bits.
+    pub fn rsa_bits(&self) -> Option<u64> {
+        if self.algorithm() != "ssh-rsa" {
+            return None;
+        }
+        let mut reader = Reader::new(&self.blob, "the key");
+        reader.string().ok()?;
+        if self.key_type != self.algorithm() {
+            // A certificate's nonce comes before the key
+            reader.string().ok()?;
+        }
+        reader.mpint("RSA exponent").ok()?;
+        let modulus = reader.mpint("RSA modulus").ok()?;
+        let first = modulus.first()?;
+        Some(modulus.len() as u64 * 8 - u64::from(first.leading_zeros()))
+    }
+
+    /// The key's SHA256 fingerprint, as shown by `ssh-keygen -l`, e.g.
+    /// `SHA256:KNrHOH...`.
+    pub fn fingerprint(&self) -> String {
+        format!("SHA256:{}", STANDARD_NO_PAD.encode(sha256(&self.blob)))
+    }
+
+    /// The key's legacy MD5 fingerprint, as shown by `ssh-keygen -l -E md5`,
+    /// e.g. `MD5:66:22:78:...`.
+    pub fn md5_fingerprint(&self) -> Result<String> {
+        let digest =
+            hash(MessageDigest::md5(), &self.blob).context("Failed to hash the key with MD5")?;
+        let hex: Vec<String> = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        Ok(format!("MD5:{}", hex.join(":")))
+    }
+
+    /// Whether `fingerprint`, as returned by [`parse_fingerprint`], is this
+    /// key's SHA256 or MD5 fingerprint.
+    pub fn has_fingerprint(&self, fingerprint: &str) -> bool {
+        if fingerprint.starts_with("MD5:") {
+            self.md5_fingerprint().is_ok_and(|md5| md5 == fingerprint)
+        } else {
+            self.fingerprint() == fingerprint
+        }
+    }
+}
+
+/// Check a fingerprint given to select keys by, in the `SHA256:...` or
+/// `MD5:aa:bb:...` form that `ssh-keygen -l` and sshd's logs show, and
+/// normalize it to the form [`PublicKey::has_fingerprint`] compares.
+pub fn parse_fingerprint(text: &str) -> Result<String> {
+    let text = text.trim();
+    if let Some(digest) = text.strip_prefix("SHA256:") {
+        // OpenSSH leaves off the base64 padding
+        let digest = digest.trim_end_matches('=');
+        let valid = STANDARD_NO_PAD
+            .decode(digest)
+            .is_ok_and(|digest| digest.len() == 32);
+        if valid {
+            return Ok(format!("SHA256:{digest}"));
+        }
+    } else if let Some(digest) = text.strip_prefix("MD5:") {
+        let digest = digest.to_ascii_lowercase();
+        let valid = digest.split(':').count() == 16
+            && digest
+                .split(':')
+                .all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit()));
+        if valid {
+            return Ok(format!("MD5:{digest}"));
+        }
+    }
+    Err(anyhow::anyhow!(
+        "invalid fingerprint {text:?} (expected SHA256:... or MD5:aa:bb:...)"
+    ))
+}
+
+/// Read and check the fields after the type of a plain `key_type` key.
+fn read_key_fields(reader: &mut Reader, key_type: &str) -> Result<()> {
+    match key_type {
+        "ssh-rsa" => {
+            reader.mpint("RSA exponent")?;
+            reader.mpint("RSA modulus")?;
+        }
+        "ssh-dss" => {
+            for field in ["DSA p", "DSA q", "DSA g", "DSA y"] {
+                reader.mpint(field)?;
+            }
+        }
+        "ssh-ed25519" => read_ed25519(reader)?,
+        "sk-ssh-ed25519@openssh.com" => {
+            read_ed25519(reader)?;
+            reader.string()?;
+        }
+        _ => {
+            if let Some(curve) = key_type.strip_prefix("ecdsa-sha2-") {
+                read_ecdsa(reader, curve)?;
+            } else if let Some(curve) = key_type
+                .strip_prefix("sk-ecdsa-sha2-")
+                .and_then(|rest| rest.strip_suffix("@openssh.com"))
+            {
+                read_ecdsa(reader, curve)?;
+                reader.string()?;
+            } else {
+                // A type this parser doesn't know; accept whatever follows
+                reader.skip_rest();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_ed25519(reader: &mut Reader) -> Result<()> {
+    let key = reader.string()?;
+    if key.len() != 32 {
+        return Err(anyhow::anyhow!(
+            "the Ed25519 key is {} bytes instead of 32",
+            key.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Read an ECDSA key's curve name and uncompressed point, checking they agree
+/// with the `curve` in its type.
+fn read_ecdsa(reader: &mut Reader, curve: &str) -> Result<()> {
+    let point_len = match curve {
+        "nistp256" => 65,
+        "nistp384" => 97,
+        "nistp521" => 133,
+        _ => return Err(anyhow::anyhow!("unknown ECDSA curve {curve:?}")),
+    };
+    let named = reader.string()?;
+    if named != curve.as_bytes() {
+        return Err(anyhow::anyhow!(
+            "the ECDSA key is for curve {:?} but its type says {curve:?}",
+            String::from_utf8_lossy(named)
+        ));
+    }
+    let point = reader.string()?;
+    if point.len() != point_len || point[0] != 0x04 {
+        return Err(anyhow::anyhow!(
+            "the ECDSA key's point isn't a {curve} point"
+        ));
+    }
+    Ok(())
+}
+
+/// Read a certificate's fields after its type, for a certificate over a
+/// `base_type` key.
+fn read_certificate(reader: &mut Reader, base_type: &str) -> Result<()> {
+    reader.string()?; // nonce
+    read_key_fields(reader, base_type)?;
+    reader.u64()?; // serial
+    let cert_type = reader.u32()?;
+    if cert_type != 1 && cert_type != 2 {
+        return Err(anyhow::anyhow!(
+            "the certificate has an unknown type {cert_type}"
+        ));
+    }
+    reader.string()?; // key id
+    reader.string()?; // valid principals
+    reader.u64()?; // valid after
+    reader.u64()?; // valid before
+    reader.string()?; // critical options
+    reader.string()?; // extensions
+    reader.string()?; // reserved
+
+    let signing_key = reader.string()?;
+    let mut signing_reader = Reader::new(signing_key, "the key");
+    let signing_type = String::from_utf8_lossy(signing_reader.string()?).into_owned();
+    if signing_type.ends_with(CERT_SUFFIX) {
+        return Err(anyhow::anyhow!(
+            "the certificate is signed by another certificate"
+        ));
+    }
+    read_key_fields(&mut signing_reader, &signing_type)
+        .context("the certificate's signing key is invalid")?;
+    signing_reader
+        .finish()
+        .context("the certificate's signing key is invalid")?;
+
+    if reader.string()?.is_empty() {
+        return Err(anyhow::anyhow!("the certificate has no signature"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::put_string;
+
+    /// Made with `ssh-keygen -t ed25519`.
+    const ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U keys-server";
+
+    fn encode(fields: &[&[u8]]) -> String {
+        let mut blob = Vec::new();
+        for field in fields {
+            put_string(&mut blob, field);
+        }
+        STANDARD.encode(blob)
+    }
+
+    #[test]
+    fn test_parse_ed25519() {
+        let key = PublicKey::parse(ED25519).unwrap();
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert_eq!(
+            key.fingerprint(),
+            "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys"
+        );
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let key = PublicKey::parse(ED25519).unwrap();
+        assert_eq!(
+            key.md5_fingerprint().unwrap(),
+            "MD5:66:22:78:86:67:92:36:42:d0:9b:7e:55:d2:91:76:92"
+        );
+
+        for text in [
+            "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys",
+            "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys=",
+            "MD5:66:22:78:86:67:92:36:42:D0:9B:7E:55:D2:91:76:92",
+        ] {
+            let fingerprint = parse_fingerprint(text).unwrap();
+            assert!(key.has_fingerprint(&fingerprint), "{text}");
+        }
+        let other =
+            parse_fingerprint("SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU").unwrap();
+        assert!(!key.has_fingerprint(&other));
+
+        for text in [
+            "KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys",
+            "SHA256:KNrHOH",
+            "MD5:66:22:78",
+            "MD5:zz:22:78:86:67:92:36:42:d0:9b:7e:55:d2:91:76:92",
+        ] {
+            let err = parse_fingerprint(text).unwrap_err();
+            assert!(err.to_string().contains("expected SHA256:"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_parse_other_key_types() {
+        // From examples/keys-config.yaml and `ssh-keygen -t ecdsa -b 384`
+        for line in [
+            "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC3o6dpyLFuyDfqhc84es4R2xNE+AhsKKqKJNxs6eyLcqIf9dezH8BD9Ye6E0BoupeZwJx9CL3wwZFmdpHEYmdLb1e7PRxx0hf/6nLRBI5+34gKukj3dZtAhZuiGOQ3sKl6iOqCTi499cRBi2TxdH2xS9n0sZCIWFLuvVzyYy+AX9F1hSTCkVhTvQKc3PJCUZHluk83ydvCyQh0wzUYDVSLkNkt03Ptu2tkj8VqTMsc8WPwBsnBwgNqK3FrD45HuFJYSObEO7ZqrHMZXOyys/jgjoAnIJ+CB5ef43PopTe+IQwqilf8JOjl7PWLPXDpnemiBkPKPy6MBGUr0F9mVEaD",
+            "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBEmKSENjQEezOmxkZMy7opKgwFB9nkt5YRrYMjNuG5N87uRgg6CLrbo5wAdT/y6v0mKV0U2w0WZ2YB/++Tpockg=",
+            "ecdsa-sha2-nistp384 AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzODQAAABhBFFz9yS/k7D3CoWIbwbDXlDg0vpGr/K0jNRBnqKV1cVLZzSjv4oU4KWJc82yaHo/gzDaRqn7pw7+IWgMCc69laL9vxcdJaes7AqGxYZ+MWoSLlaUj3qeKQbgBar+gI+KVA== e",
+        ] {
+            PublicKey::parse(line).unwrap();
+        }
+
+        let sk_ed25519 = encode(&[b"sk-ssh-ed25519@openssh.com", &[7; 32], b"ssh:"]);
+        PublicKey::from_parts("sk-ssh-ed25519@openssh.com", &sk_ed25519).unwrap();
+
+        let mut point = vec![0x04];
+        point.extend_from_slice(&[1; 64]);
+        let sk_ecdsa = encode(&[
+            b"sk-ecdsa-sha2-nistp256@openssh.com",
+            b"nistp256",
+            &point,
+            b"ssh:",
+        ]);
+        PublicKey::from_parts("sk-ecdsa-sha2-nistp256@openssh.com", &sk_ecdsa).unwrap();
+    }
+
+    #[test]
+    fn test_key_strength() {
+        // From examples/keys-config.yaml
+        let rsa_2048 = PublicKey::parse("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC3o6dpyLFuyDfqhc84es4R2xNE+AhsKKqKJNxs6eyLcqIf9dezH8BD9Ye6E0BoupeZwJx9CL3wwZFmdpHEYmdLb1e7PRxx0hf/6nLRBI5+34gKukj3dZtAhZuiGOQ3sKl6iOqCTi499cRBi2TxdH2xS9n0sZCIWFLuvVzyYy+AX9F1hSTCkVhTvQKc3PJCUZHluk83ydvCyQh0wzUYDVSLkNkt03Ptu2tkj8VqTMsc8WPwBsnBwgNqK3FrD45HuFJYSObEO7ZqrHMZXOyys/jgjoAnIJ+CB5ef43PopTe+IQwqilf8JOjl7PWLPXDpnemiBkPKPy6MBGUr0F9mVEaD").unwrap();
+        assert_eq!(rsa_2048.rsa_bits(), Some(2048));
+        let rsa_3072 = PublicKey::from_parts("ssh-rsa", "AAAAB3NzaC1yc2EAAAADAQABAAABgQCj7ndNxQowgcQnjshcLrqPEiiphnt+VTTvDP6mHBL9j1aNUkY4Ue1gvwnGLVlOhGeYrnZaMgRK6+PKCUXaDbC7qtbW8gIkhL7aGCsOr/C56SJMy/BCZfxd1nWzAOxSDPgVsmerOBYfNqltV9/hWCqBywINIR+5dIg6JTJ72pcEpEjcYgXkE2YEFXV1JHnsKgbLWNlhScqb2UmyRkQyytRLtL+38TGxkxCflmO+5Z8CSSNY7GidjMIZ7Q4zMjA2n1nGrlTDkzwDCsw+wqFPGQA179cnfGWOWRVruj16z6XyvxvjJwbz0wQZ75XK5tKSb7FNyeIEs4TT4jk+S4dhPeAUC5y+bDYirYgM4GC7uEnztnZyaVWQ7B381AK4Qdrwt51ZqExKbQpTUNn+EjqoTwvqNj4kqx5QUCI0ThS/YkOxJCXmPUWZbhjpCg56i+2aB6CmK2JGhn57K5mj0MNdBXA4/WnwH6XoPWJzK5Nyu2zB3nAZp+S5hpQs+p1vN1/wsjk=").unwrap();
+        assert_eq!(rsa_3072.rsa_bits(), Some(3072));
+        // `ssh-keygen -s ca -I r -n r rsa.pub`, certifying a 3072-bit key
+        let rsa_cert = PublicKey::parse("ssh-rsa-cert-v01@openssh.com AAAAHHNzaC1yc2EtY2VydC12MDFAb3BlbnNzaC5jb20AAAAghP+wYnCGo029ZsnKRBEyDW6/8n/UFAk37NpkKPuVy+sAAAADAQABAAABgQCq+JLIXiDCH3qH/nMZ79+7yHWaCJ/Zgmk70QU8/ilS7CKi6lMJWMpZRu1VZxqCwDEwAzj5MY18XfogqCHXAQxpUVN+f/Gifn2jSB2FpSaPjKEzz7m8oe1ZLikniwRFalhfY/V7YGvaVgB4nYB/owFXhfTdrSP8hNXrb0x0/IoUa3UDaSud0St5Cj+InKkGJgyqePnLQ1pzfcMUiK1F9LNmVymMlfjpR4kMbHuwAym25Ks2ffOkNWR94EvzUuIUDZQaTE5Im1ZxJguLZPD+w7vlS9yD7JK70GfHUeiK7AFun+Vmeohr0ieQuEuncq0EVzlbhcmRidrBmedddN4vO1+ql58MyGjc9Oo/64KbpCk/wfyE3Sjrmyg+gwGm4aJuEcAyUefu8AYWHTko/hcUNvJnPDttB+q7MDq5jGL/3ui/nSJehBWSMg39U/4LRxbCRMHLokalJXSV6RuaWrN9fFvw91eiOuS73LyFujWaS42njA+B/5+FxoRt9bHB3kG7/YMAAAAAAAAAAAAAAAEAAAABcgAAAAUAAAABcgAAAAAAAAAA//////////8AAAAAAAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2VudC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQtcHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBIt1KeC2fyK9LnD6uCK5pGzVquKWd3TQ29vCgryx6tDAAAAFMAAAALc3NoLWVkMjU1MTkAAABAYAN/1ZAH1OHea6SVgwDggWEdkKZJwvqjJbxxC6vjwg41u9m4+1Ag/c7OOCspJbmlnCaqzyGVsEh6VGG8CE0aCQ==").unwrap();
+        assert_eq!(rsa_cert.algorithm(), "ssh-rsa");
+        assert_eq!(rsa_cert.rsa_bits(), Some(3072));
+
+        let ed25519 = PublicKey::parse(ED25519).unwrap();
+        assert_eq!(ed25519.algorithm(), "ssh-ed25519");
+        assert_eq!(ed25519.rsa_bits(), None);
+        assert!(!ed25519.is_security_key());
+
+        let sk = encode(&[b"sk-ssh-ed25519@openssh.com", &[7; 32], b"ssh:"]);
+        let sk = PublicKey::from_parts("sk-ssh-ed25519@openssh.com", &sk).unwrap();
+        assert!(sk.is_security_key());
+    }
+
+    #[test]
+    fn test_parse_certificates() {
+        // `ssh-keygen -s ca -I alice -n alice -V +52w user.pub`
+        let user = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIE+3P+Oy03Ei55BnfK2JDLyY7tzBO+pb+NEdz0kXecBOAAAAIEcTH0j5Ih3vDTGIGyjcQeMFk4NaFf6uxSPmFSThyWkAAAAAAAAAAAAAAAABAAAABWFsaWNlAAAACQAAAAVhbGljZQAAAABq1EmkAAAAAGy0K+QAAAAAAAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2VudC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQtcHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBIt1KeC2fyK9LnD6uCK5pGzVquKWd3TQ29vCgryx6tDAAAAFMAAAALc3NoLWVkMjU1MTkAAABAo3EWQkg2XP6QNM7IVp1D2bGy/CRfjYlNScd4Jm+f2zy2nbNs5dPK3SpXTkxYGRnEJXJm3jhMALUqQ7Ph2cNHBw== alice@laptop";
+        let key = PublicKey::parse(user).unwrap();
+        assert_eq!(key.key_type, "ssh-ed25519-cert-v01@openssh.com");
+        assert_eq!(key.algorithm(), "ssh-ed25519");
+
+        // `ssh-keygen -s ca -I host -h -n host.example.com ecdsa.pub`
+        let host = "ecdsa-sha2-nistp256-cert-v01@openssh.com AAAAKGVjZHNhLXNoYTItbmlzdHAyNTYtY2VydC12MDFAb3BlbnNzaC5jb20AAAAgdE6VNjmu7e2dpe69BjtYkNgn25ejowWccMGHB0QWwwkAAAAIbmlzdHAyNTYAAABBBE3NHNXF51cRIrlKc1CAgOalbCA9tZ2JV/Lcp8lnKtO8BbqAYE6lkp7eBswNyF8nkiJ10Ih7jCb5Vo072t/3enAAAAAAAAAAAAAAAAIAAAAEaG9zdAAAABQAAAAQaG9zdC5leGFtcGxlLmNvbQAAAAAAAAAA//////////8AAAAAAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIEi3Up4LZ/Ir0ucPq4IrmkbNWq4pZ3dNDb28KCvLHq0MAAAAUwAAAAtzc2gtZWQyNTUxOQAAAECPa0vYP9e/438W6QRPfm6YZM3reXrdt3EGh5PHsORKFUyGK4i+RNhKol+vEaf/q9Cw0ii4IAzyS9DzroEHLTAJ e";
+        let key = PublicKey::parse(host).unwrap();
+        assert_eq!(key.key_type, "ecdsa-sha2-nistp256-cert-v01@openssh.com");
+
+        // A certificate cut off before its signature
+        let data = STANDARD
+            .decode(user.split_whitespace().nth(1).unwrap())
+            .unwrap();
+        let truncated = STANDARD.encode(&data[..data.len() - 90]);
+        assert!(PublicKey::from_parts("ssh-ed25519-cert-v01@openssh.com", &truncated).is_err());
+    }
+
+    #[test]
+    fn test_rejects_invalid_keys() {
+        let error = |key_type: &str, data: &str| {
+            PublicKey::from_parts(key_type, data)
+                .unwrap_err()
+                .to_string()
+        };
+        let ed25519 = ED25519.split_whitespace().nth(1).unwrap();
+
+        assert_eq!(
+            error("ssh-rsa ", ed25519),
+            r#"the key is declared as "ssh-rsa " but is actually "ssh-ed25519""#
+        );
+        assert_eq!(
+            error("ssh-ed25519", "AAAA not base64"),
+            r#"the "ssh-ed25519" key data isn't valid base64"#
+        );
+        assert_eq!(error("ssh-ed25519", &ed25519[..40]), "the key is truncated");
+        assert_eq!(
+            error("ssh-ed25519", &encode(&[b"ssh-ed25519", &[1; 31]])),
+            "the Ed25519 key is 31 bytes instead of 32"
+        );
+        assert_eq!(
+            error(
+                "ssh-ed25519",
+                &encode(&[b"ssh-ed25519", &[1; 32], b"extra"])
+            ),
+            "the key has trailing data"
+        );
+        assert_eq!(
+            error("ssh-rsa", &encode(&[b"ssh-rsa", &[1, 0, 1], &[]])),
+            "the key's RSA modulus is zero"
+        );
+        assert_eq!(
+            error(
+                "ecdsa-sha2-nistp256",
+                &encode(&[b"ecdsa-sha2-nistp256", b"nistp384", &[4; 97]])
+            ),
+            r#"the ECDSA key is for curve "nistp384" but its type says "nistp256""#
+        );
+        assert_eq!(
+            error(
+                "ecdsa-sha2-nistp256",
+                &encode(&[b"ecdsa-sha2-nistp256", b"nistp256", &[4; 33]])
+            ),
+            "the ECDSA key's point isn't a nistp256 point"
+        );
+        assert_eq!(
+            PublicKey::parse("ssh-ed25519").unwrap_err().to_string(),
+            "the ssh-ed25519 key has no key data"
+        );
+    }
+}
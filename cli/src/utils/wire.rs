@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+
+/// Append `value` to `buffer` as an SSH `string`: its length, then itself.
+pub fn put_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    buffer.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(value);
+}
+
+/// Reads the SSH wire format: big-endian integers and length-prefixed
+/// `string`s, as found in key blobs and signatures.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    /// What is being read, for errors, e.g. `the key` or `its signature`.
+    subject: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], subject: &'static str) -> Self {
+        Self { data, subject }
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let (value, rest) = self
+            .data
+            .split_first_chunk::<4>()
+            .with_context(|| format!("{} is truncated", self.subject))?;
+        self.data = rest;
+        Ok(u32::from_be_bytes(*value))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let (value, rest) = self
+            .data
+            .split_first_chunk::<8>()
+            .with_context(|| format!("{} is truncated", self.subject))?;
+        self.data = rest;
+        Ok(u64::from_be_bytes(*value))
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        if self.data.len() < length {
+            return Err(anyhow::anyhow!("{} is truncated", self.subject));
+        }
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(value)
+    }
+
+    /// A positive, non-zero `mpint`, such as an RSA modulus, without the
+    /// leading zero bytes it may be padded with.
+    pub fn mpint(&mut self, field: &str) -> Result<&'a [u8]> {
+        let value = self.string()?;
+        if value.first().is_some_and(|first| first & 0x80 != 0) {
+            return Err(anyhow::anyhow!("{}'s {field} is negative", self.subject));
+        }
+        match value.iter().position(|byte| *byte != 0) {
+            Some(start) => Ok(&value[start..]),
+            None => Err(anyhow::anyhow!("{}'s {field} is zero", self.subject)),
+        }
+    }
+
+    /// Skip whatever is left, for data whose layout isn't known.
+    pub fn skip_rest(&mut self) {
+        self.data = &[];
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        if !self.data.is_empty() {
+            return Err(anyhow::anyhow!("{} has trailing data", self.subject));
+        }
+        Ok(())
+    }
+}
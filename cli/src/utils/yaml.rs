@@ -0,0 +1,179 @@
+use serde_json::{Map, Value};
+
+/// Render `value` as a YAML document, for `--output yaml`.
+///
+/// Strings are double-quoted (YAML accepts JSON's escapes), except multi-line
+/// ones, such as PGP key blocks, which are written as literal blocks so they
+/// stay readable.
+pub fn to_yaml(value: &Value) -> String {
+    let mut out = String::new();
+    match value {
+        Value::Object(map) if !map.is_empty() => write_mapping(&mut out, map, 0),
+        Value::Array(items) if !items.is_empty() => write_sequence(&mut out, items, 0),
+        scalar => write_scalar(&mut out, scalar, 0),
+    }
+    out
+}
+
+/// Write the entries of `map`, each on its own line at `indent`.
+fn write_mapping(out: &mut String, map: &Map<String, Value>, indent: usize) {
+    for (key, value) in map {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&yaml_key(key));
+        out.push(':');
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                out.push('\n');
+                write_mapping(out, map, indent + 2);
+            }
+            Value::Array(items) if !items.is_empty() => {
+                out.push('\n');
+                write_sequence(out, items, indent);
+            }
+            scalar => {
+                out.push(' ');
+                write_scalar(out, scalar, indent);
+            }
+        }
+    }
+}
+
+/// Write the items of `items`, each starting with `- ` at `indent`.
+fn write_sequence(out: &mut String, items: &[Value], indent: usize) {
+    for item in items {
+        out.push_str(&" ".repeat(indent));
+        out.push('-');
+        match item {
+            Value::Object(map) if !map.is_empty() => {
+                // The first entry goes on the same line as the dash
+                let mut mapping = String::new();
+                write_mapping(&mut mapping, map, indent + 2);
+                out.push(' ');
+                out.push_str(&mapping[indent + 2..]);
+            }
+            Value::Array(items) if !items.is_empty() => {
+                out.push('\n');
+                write_sequence(out, items, indent + 2);
+            }
+            scalar => {
+                out.push(' ');
+                write_scalar(out, scalar, indent);
+            }
+        }
+    }
+}
+
+/// Write a scalar, or an empty collection, ending the line. `indent` is that
+/// of the entry holding it, which a literal block is indented past.
+fn write_scalar(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::String(text) if is_literal_block(text) => {
+            let chomping = if !text.ends_with('\n') {
+                "-"
+            } else if text.ends_with("\n\n") {
+                "+"
+            } else {
+                ""
+            };
+            out.push('|');
+            out.push_str(chomping);
+            out.push('\n');
+            for line in text.strip_suffix('\n').unwrap_or(text).split('\n') {
+                if !line.is_empty() {
+                    out.push_str(&" ".repeat(indent + 2));
+                    out.push_str(line);
+                }
+                out.push('\n');
+            }
+        }
+        Value::Object(_) => out.push_str("{}\n"),
+        Value::Array(_) => out.push_str("[]\n"),
+        // Strings, numbers, booleans and null read the same in JSON and YAML
+        scalar => {
+            out.push_str(&scalar.to_string());
+            out.push('\n');
+        }
+    }
+}
+
+/// Whether `text` can be written as a literal block: it spans several lines,
+/// and has nothing a literal block can't hold exactly.
+fn is_literal_block(text: &str) -> bool {
+    text.contains('\n')
+        && !text.starts_with([' ', '\n'])
+        && !text.chars().any(|c| c.is_control() && c != '\n')
+        && !text.lines().any(|line| line.ends_with(' '))
+}
+
+/// A mapping key, quoted unless it is a plain word such as `cert-authority`.
+fn yaml_key(key: &str) -> String {
+    let plain = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if plain {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_yaml_nested() {
+        let value = json!({
+            "version": "1.0.0",
+            "knownHosts": [
+                {
+                    "name": null,
+                    "hosts": ["github.com", "[gh]:22"],
+                    "keys": [{"type": "ssh-ed25519", "cert-authority": true}]
+                }
+            ],
+            "empty": [],
+            "odd key": 1
+        });
+        assert_eq!(
+            to_yaml(&value),
+            r#"version: "1.0.0"
+knownHosts:
+- name: null
+  hosts:
+  - "github.com"
+  - "[gh]:22"
+  keys:
+  - type: "ssh-ed25519"
+    cert-authority: true
+empty: []
+"odd key": 1
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_yaml_multiline_strings() {
+        let value = json!({
+            "keys": [{"key": "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmQINBG\n-----END PGP PUBLIC KEY BLOCK-----\n"}],
+            "no_newline": "a\nb",
+            "tab": "a\tb\nc"
+        });
+        assert_eq!(
+            to_yaml(&value),
+            r#"keys:
+- key: |
+    -----BEGIN PGP PUBLIC KEY BLOCK-----
+
+    mQINBG
+    -----END PGP PUBLIC KEY BLOCK-----
+no_newline: |-
+  a
+  b
+tab: "a\tb\nc"
+"#
+        );
+    }
+}
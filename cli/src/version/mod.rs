@@ -0,0 +1,398 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use anyhow::{Context, Result};
+
+/// A semantic version, as in the project's `v1.2.3` and `v1.2.3-rc.1` tags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// The pre-release identifiers after the `-`, e.g. `["rc", "1"]`.
+    pub pre: Vec<String>,
+}
+
+impl Version {
+    /// Parse a version such as `1.2.3`, `v1.2.3` or `1.2.3-rc.1`. Build
+    /// metadata after a `+` is ignored.
+    pub fn parse(text: &str) -> Result<Version> {
+        let partial = PartialVersion::parse(text)?;
+        match partial {
+            PartialVersion {
+                major,
+                minor: Some(minor),
+                patch: Some(patch),
+                pre,
+            } => Ok(Version {
+                major,
+                minor,
+                patch,
+                pre,
+            }),
+            _ => Err(anyhow::anyhow!(
+                "{text:?} isn't a full version (expected MAJOR.MINOR.PATCH)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", self.pre.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| compare_pre(&self.pre, &other.pre))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare pre-release identifiers by semver's rules: a release sorts after
+/// its pre-releases, numeric identifiers compare numerically and sort before
+/// alphanumeric ones, and a longer list wins a tie.
+fn compare_pre(pre: &[String], other: &[String]) -> Ordering {
+    match (pre.is_empty(), other.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        (false, false) => {}
+    }
+    for (identifier, other_identifier) in pre.iter().zip(other) {
+        let ordering = match (identifier.parse::<u64>(), other_identifier.parse::<u64>()) {
+            (Ok(number), Ok(other_number)) => number.cmp(&other_number),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => identifier.cmp(other_identifier),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    pre.len().cmp(&other.len())
+}
+
+/// A version that may leave out its minor or patch number, as written in a
+/// requirement (e.g. the `1.4` of `>=1.4`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PartialVersion {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<String>,
+}
+
+impl PartialVersion {
+    fn parse(text: &str) -> Result<PartialVersion> {
+        let trimmed = text.trim();
+        let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+        let without_build = trimmed.split('+').next().unwrap_or_default();
+        let (numbers, pre) = match without_build.split_once('-') {
+            Some((numbers, pre)) => (numbers, pre.split('.').map(str::to_string).collect()),
+            None => (without_build, Vec::new()),
+        };
+
+        let invalid = || anyhow::anyhow!("{text:?} isn't a semantic version");
+        let mut parts = numbers.split('.').map(|part| {
+            // `*` and `x` leave the rest of the version open, as in `1.x`
+            if part == "*" || part.eq_ignore_ascii_case("x") {
+                Ok(None)
+            } else {
+                part.parse::<u64>().map(Some).map_err(|_| invalid())
+            }
+        });
+        let major = parts.next().ok_or_else(invalid)??.ok_or_else(invalid)?;
+        let minor = parts.next().transpose()?.flatten();
+        let patch = parts.next().transpose()?.flatten();
+        if parts.next().is_some()
+            || (minor.is_none() && patch.is_some())
+            || pre.iter().any(String::is_empty)
+        {
+            return Err(invalid());
+        }
+
+        Ok(PartialVersion {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    /// The lowest version this matches, filling missing numbers with zero.
+    fn lowest(&self) -> Version {
+        Version {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+        }
+    }
+
+    /// The first version past those this matches, e.g. `1.5.0` for `1.4`.
+    fn next(&self) -> Version {
+        match (self.minor, self.patch) {
+            (None, _) => release(self.major + 1, 0, 0),
+            (Some(minor), None) => release(self.major, minor + 1, 0),
+            (Some(minor), Some(patch)) => release(self.major, minor, patch + 1),
+        }
+    }
+}
+
+fn release(major: u64, minor: u64, patch: u64) -> Version {
+    Version {
+        major,
+        minor,
+        patch,
+        pre: Vec::new(),
+    }
+}
+
+/// How a comparator relates a version to its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+/// A single constraint of a requirement, such as `>=1.4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    version: PartialVersion,
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Result<Comparator> {
+        let text = text.trim();
+        let (op, rest) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+            ("~", Op::Tilde),
+            ("^", Op::Caret),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (op, rest)))
+        .unwrap_or((Op::Exact, text));
+        Ok(Comparator {
+            op,
+            version: PartialVersion::parse(rest)?,
+        })
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let lowest = self.version.lowest();
+        let exact = self.version.patch.is_some();
+        match self.op {
+            // A partial version matches every version it is a prefix of
+            Op::Exact if exact => *version == lowest,
+            Op::Exact => *version >= lowest && *version < self.version.next(),
+            Op::GreaterEq => *version >= lowest,
+            Op::Greater if exact => *version > lowest,
+            Op::Greater => *version >= self.version.next(),
+            Op::Less => *version < lowest,
+            Op::LessEq if exact => *version <= lowest,
+            Op::LessEq => *version < self.version.next(),
+            // `~1.4.2` allows patch updates, `~1` minor ones
+            Op::Tilde => {
+                let upper = match self.version.minor {
+                    Some(minor) => release(self.version.major, minor + 1, 0),
+                    None => release(self.version.major + 1, 0, 0),
+                };
+                *version >= lowest && *version < upper
+            }
+            // `^1.4.2` allows anything up to the next breaking release: the
+            // next major, or for `0.x` versions the next minor or patch
+            Op::Caret => {
+                let upper = match (self.version.major, self.version.minor, self.version.patch) {
+                    (0, Some(0), Some(patch)) => release(0, 0, patch + 1),
+                    (0, Some(minor), _) => release(0, minor + 1, 0),
+                    (major, _, _) => release(major + 1, 0, 0),
+                };
+                *version >= lowest && *version < upper
+            }
+        }
+    }
+}
+
+/// A constraint on the server's version: one or more comma-separated
+/// comparators that must all hold, e.g. `>=1.4, <2`.
+///
+/// A bare version pins it: `1.4.2` matches only 1.4.2, and `1.4` any 1.4.x.
+/// `>`, `>=`, `<`, `<=`, `~` and `^` work as in Cargo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    text: String,
+    comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    pub fn parse(text: &str) -> Result<VersionReq> {
+        let comparators = text
+            .split(',')
+            .map(Comparator::parse)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid version requirement {text:?}"))?;
+        Ok(VersionReq {
+            text: text.trim().to_string(),
+            comparators,
+        })
+    }
+
+    /// The requirement that the version is at least `version`.
+    pub fn at_least(version: &str) -> Result<VersionReq> {
+        VersionReq::parse(&format!(">={}", version.trim()))
+    }
+
+    /// Both this requirement and `other`.
+    pub fn and(mut self, other: VersionReq) -> VersionReq {
+        self.text = format!("{}, {}", self.text, other.text);
+        self.comparators.extend(other.comparators);
+        self
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(req: &str, version: &str) -> bool {
+        VersionReq::parse(req)
+            .unwrap()
+            .matches(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(Version::parse("v1.2.3").unwrap(), release(1, 2, 3));
+        assert_eq!(Version::parse("1.2.3+build.5").unwrap(), release(1, 2, 3));
+        assert_eq!(
+            Version::parse("1.2.3-rc.1").unwrap().to_string(),
+            "1.2.3-rc.1"
+        );
+
+        for invalid in ["", "1.2", "1.2.3.4", "one.two.three", "1.2.3-", "dev"] {
+            assert!(Version::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            let (lower, higher) = (
+                Version::parse(pair[0]).unwrap(),
+                Version::parse(pair[1]).unwrap(),
+            );
+            assert!(lower < higher, "{lower} < {higher}");
+        }
+    }
+
+    #[test]
+    fn test_exact_requirements() {
+        assert!(matches("1.4.2", "1.4.2"));
+        assert!(matches("v1.4.2", "1.4.2"));
+        assert!(matches("=1.4.2", "1.4.2"));
+        assert!(!matches("1.4.2", "1.4.3"));
+        assert!(!matches("1.4.2", "1.4.2-rc.1"));
+
+        assert!(matches("1.4", "1.4.0"));
+        assert!(matches("1.4", "1.4.9"));
+        assert!(!matches("1.4", "1.5.0"));
+        assert!(matches("1.x", "1.9.0"));
+        assert!(!matches("1", "2.0.0"));
+    }
+
+    #[test]
+    fn test_range_requirements() {
+        assert!(matches(">=1.4", "1.4.0"));
+        assert!(matches(">=1.4", "2.0.0"));
+        assert!(!matches(">=1.4", "1.3.9"));
+        assert!(matches(">1.4.2", "1.4.3"));
+        assert!(!matches(">1.4.2", "1.4.2"));
+        assert!(!matches(">1.4", "1.4.9"));
+        assert!(matches("<2", "1.99.0"));
+        assert!(!matches("<2", "2.0.0"));
+        assert!(matches("<=1.4", "1.4.9"));
+        assert!(!matches("<=1.4", "1.5.0"));
+        assert!(matches(">=1.4, <2", "1.9.0"));
+        assert!(!matches(">=1.4, <2", "2.1.0"));
+    }
+
+    #[test]
+    fn test_tilde_and_caret_requirements() {
+        assert!(matches("~1.4.2", "1.4.9"));
+        assert!(!matches("~1.4.2", "1.5.0"));
+        assert!(matches("~1", "1.9.0"));
+        assert!(!matches("~1", "2.0.0"));
+
+        assert!(matches("^1.4.2", "1.9.0"));
+        assert!(!matches("^1.4.2", "1.4.1"));
+        assert!(!matches("^1.4.2", "2.0.0"));
+        assert!(matches("^0.3.1", "0.3.9"));
+        assert!(!matches("^0.3.1", "0.4.0"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+    }
+
+    #[test]
+    fn test_combined_requirements() {
+        let req = VersionReq::parse("1")
+            .unwrap()
+            .and(VersionReq::at_least("1.4").unwrap());
+        assert_eq!(req.to_string(), "1, >=1.4");
+        assert!(req.matches(&release(1, 4, 0)));
+        assert!(!req.matches(&release(1, 3, 0)));
+        assert!(!req.matches(&release(2, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_requirements() {
+        for invalid in ["", ">=", "latest", "1.2.3.4", ">=1.4,", "1.x.3"] {
+            assert!(VersionReq::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -6,9 +6,50 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
+    // Key data for the keys these tests serve and write: well-formed key
+    // blobs, as keys from the server are checked, but not real keys.
+    const EXAMPLE_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIIb/updwnvzLbFdQ/1++zgyX+yHPyx8vCuFbCZ0QO25O";
+    const ALICE_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOV9uP6UfA9Oj0BPW47nya9SLgTL7s8FNQBjmVye/udC";
+    const BOB_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEF56Z1e4Qalm5c7OYvxNgCk=";
+    const BOB_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIOpGruofUeSz9cPWyVOVqOaVbq65sijQ/u2n/sDW8+rl";
+    const CAROL_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEG4paAhVVjZJQyDFD7tusyM=";
+    const CONTRACTOR_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIHCHSNBAJCPk9BZVw8rk1JtuG+8+LTtYa3trpJvdShWm";
+    const FORMER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAw7b5BWx72tkCDb01qk5WTYWokgTZdqnRPyr5438BzX";
+    const NEW_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHGjIrY4qNeuxjvc9Y27CJA=";
+    const OLD_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIKgbVtQOv3INVqwPcv6PgJffaSvhaM0paHE4J0LnMTU2";
+    const REVOKED_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEEqQcu7jRP9x9NQ7dFY7RfA=";
+    const LOCAL_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIBkrsQFGdGJnKE/NhNs2rTMJAE1joV+VzAhayfJ9V0i9";
+    const LOCAL_RSA_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEFBkqIwiGKr5wvXv6W1THhc=";
+    const SESSION_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIKOd7IjTfz8HvFqCL2m+Vyo+fwp0XBf0wWm9VqT0Ohaz";
+    const BREAKGLASS_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIHsRIfIjuXa9dW1J0DZhZT6Tq5xyvq7e7lu0Tu9lEbNu";
+    const SHARED_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIDT0zlctBtE4dvD5xWRWETNNMv4JFyKH01f68akTE6M7";
+    const STAGING_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIO2QXaq1dwY5jPuAhLM+DSzxJr9wxBS+LrDFTIPGWWqo";
+    const AUTHENTICATED_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIAlbh9nVTZRO/Tz/KzKKKZDndb6uzrExuseXG7YVoJLs";
+    const SIGNED_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIIhr3ALaFqoDEFtjewQmGQtiwIoetCtuRwTrQi8ki+/E";
+    const CI_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAILvF4Qbk3pFTKh+anonXVB7FLCdR0G9vL9TFewwt3+4s";
+    const LAPTOP_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIJSoPffv3TGxOH0AKi8QZUGsnOcDum1tjy3GUXnzQxfA";
+    const GITHUB_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIA1i/RZKmwSYYR2IXw+GV//hqIQjQjoCucMMAzkuoQ7z";
+    const NEW_HOST_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHSQNDqKXysLO+Dac6J4rFk=";
+    const NEW_HOST_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIN0dg1XECBj4eYwci10Wvd/ZBv00GsNCYP6g2FqloqhQ";
+    const OLD_HOST_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAEHFKNv+Amtg0ni7Rf83C/lk=";
+
     fn get_cmd() -> Command {
         #[allow(deprecated)]
-        Command::cargo_bin("keys").unwrap()
+        let mut cmd = Command::cargo_bin("keys").unwrap();
+        // Keep cached responses out of the real user cache directory
+        cmd.env(
+            "XDG_CACHE_HOME",
+            std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache"),
+        );
+        cmd
     }
 
     // ==================== Help and Version Tests ====================
@@ -57,19 +98,19 @@ mod tests {
             .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "keys": [
-                        {
+                        {{
                             "name": "laptop",
                             "user": "alice",
-                            "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExample",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
                             "tags": []
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         get_cmd()
@@ -87,12 +128,16 @@ mod tests {
             .mock("GET", "/keys")
             .with_status(500)
             .with_body(r#"{"error": "Internal server error"}"#)
+            .expect(2)
             .create();
 
+        // Retried once before giving up
         get_cmd()
-            .args(["--server", &server.url(), "ssh"])
+            .args(["--server", &server.url(), "--retries", "1", "ssh"])
             .assert()
-            .failure();
+            .failure()
+            .stderr(predicate::str::contains("retrying in 0.5s (1/1)"))
+            .stderr(predicate::str::contains("Server returned error code: 500"));
 
         mock.assert();
     }
@@ -104,19 +149,19 @@ mod tests {
             .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "keys": [
-                        {
+                        {{
                             "name": "laptop",
                             "user": "alice",
-                            "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExample",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
                             "tags": []
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         let temp_dir = TempDir::new().unwrap();
@@ -143,32 +188,139 @@ mod tests {
     }
 
     #[test]
-    fn test_ssh_write_with_force_flag() {
+    fn test_ssh_write_handles_expiring_keys() {
         let mut server = Server::new();
         let mock = server
             .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(
-                r#"{
+                format!(r#"{{
                     "version": "1.0.0",
                     "keys": [
-                        {
+                        {{"name": "laptop", "user": "alice", "key": "ssh-ed25519 {ALICE_KEY}", "tags": []}},
+                        {{"name": "laptop", "user": "contractor", "key": "ssh-ed25519 {CONTRACTOR_KEY}", "tags": ["expires:2999-12-31"]}},
+                        {{"name": "laptop", "user": "former", "key": "ssh-ed25519 {FORMER_KEY}", "tags": ["expires:2000-01-01"]}}
+                    ]
+                }}"#),
+            )
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+            ])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "Skipping key former@laptop: it expired on 2000-01-01",
+            ));
+
+        mock.assert();
+        assert_eq!(
+            fs::read_to_string(&auth_keys_path).unwrap(),
+            format!(
+                "ssh-ed25519 {ALICE_KEY} alice@laptop\n\
+             expiry-time=\"29991231\" ssh-ed25519 {CONTRACTOR_KEY} contractor@laptop"
+            )
+        );
+    }
+
+    #[test]
+    fn test_ssh_write_enforces_policy() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"name": "laptop", "user": "alice", "key": "ssh-ed25519 {ALICE_KEY}", "tags": []}},
+                        {{"name": "old-laptop", "user": "bob", "key": "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC3o6dpyLFuyDfqhc84es4R2xNE+AhsKKqKJNxs6eyLcqIf9dezH8BD9Ye6E0BoupeZwJx9CL3wwZFmdpHEYmdLb1e7PRxx0hf/6nLRBI5+34gKukj3dZtAhZuiGOQ3sKl6iOqCTi499cRBi2TxdH2xS9n0sZCIWFLuvVzyYy+AX9F1hSTCkVhTvQKc3PJCUZHluk83ydvCyQh0wzUYDVSLkNkt03Ptu2tkj8VqTMsc8WPwBsnBwgNqK3FrD45HuFJYSObEO7ZqrHMZXOyys/jgjoAnIJ+CB5ef43PopTe+IQwqilf8JOjl7PWLPXDpnemiBkPKPy6MBGUr0F9mVEaD", "tags": []}}
+                    ]
+                }}"#),
+            )
+            .expect(2)
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        let config_path = temp_dir.path().join("config.toml");
+        let write = |strictness: &str| {
+            fs::write(
+                &config_path,
+                format!("[policy]\nmin_rsa_bits = 3072\nstrictness = \"{strictness}\"\n"),
+            )
+            .unwrap();
+            get_cmd()
+                .args([
+                    "--config",
+                    config_path.to_str().unwrap(),
+                    "--server",
+                    &server.url(),
+                    "ssh",
+                    "--write",
+                    auth_keys_path.to_str().unwrap(),
+                ])
+                .assert()
+        };
+
+        write("abort").failure().stderr(predicate::str::contains(
+            "key bob@old-laptop: the RSA key is 2048 bits, under the 3072-bit minimum",
+        ));
+        assert!(!auth_keys_path.exists());
+
+        write("skip").success().stderr(predicate::str::contains(
+            "Skipping key bob@old-laptop: the RSA key is 2048 bits",
+        ));
+        assert_eq!(
+            fs::read_to_string(&auth_keys_path).unwrap(),
+            format!("ssh-ed25519 {ALICE_KEY} alice@laptop")
+        );
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_ssh_write_with_force_flag() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
                             "name": "new-laptop",
                             "user": "bob",
-                            "key": "ssh-rsa AAAABNewKey",
+                            "key": "ssh-rsa {NEW_KEY}",
                             "tags": []
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         let temp_dir = TempDir::new().unwrap();
         let auth_keys_path = temp_dir.path().join("authorized_keys");
 
         // Create existing file with different key
-        fs::write(&auth_keys_path, "ssh-ed25519 OldKey old@machine\n").unwrap();
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {OLD_KEY} old@machine\n"),
+        )
+        .unwrap();
 
         get_cmd()
             .args([
@@ -186,8 +338,8 @@ mod tests {
 
         // Verify file was overwritten (old key removed)
         let content = fs::read_to_string(&auth_keys_path).unwrap();
-        assert!(!content.contains("OldKey"));
-        assert!(content.contains("AAAABNewKey"));
+        assert!(!content.contains("old@machine"));
+        assert!(content.contains(NEW_KEY));
     }
 
     #[test]
@@ -197,26 +349,30 @@ mod tests {
             .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "keys": [
-                        {
+                        {{
                             "name": "new-laptop",
                             "user": "bob",
-                            "key": "ssh-rsa AAAABNewKey",
+                            "key": "ssh-rsa {NEW_KEY}",
                             "tags": []
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         let temp_dir = TempDir::new().unwrap();
         let auth_keys_path = temp_dir.path().join("authorized_keys");
 
         // Create existing file with different key
-        fs::write(&auth_keys_path, "ssh-ed25519 OldKey old@machine\n").unwrap();
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {OLD_KEY} old@machine\n"),
+        )
+        .unwrap();
 
         // Without --force, should preserve existing keys
         get_cmd()
@@ -234,43 +390,42 @@ mod tests {
 
         // Verify both keys are present
         let content = fs::read_to_string(&auth_keys_path).unwrap();
-        assert!(content.contains("OldKey"));
-        assert!(content.contains("AAAABNewKey"));
+        assert!(content.contains("old@machine"));
+        assert!(content.contains(NEW_KEY));
     }
 
-    // ==================== PGP Subcommand Tests ====================
-
     #[test]
-    fn test_pgp_help() {
-        get_cmd()
-            .args(["pgp", "--help"])
-            .assert()
-            .success()
-            .stdout(predicate::str::contains("PGP keys"));
-    }
+    fn test_ssh_filter_flags_are_sent_as_query() {
+        use mockito::Matcher;
 
-    #[test]
-    fn test_pgp_fetch_success() {
         let mut server = Server::new();
         let mock = server
-            .mock("GET", "/pgp")
+            .mock("GET", "/keys")
+            // Repeated flags become repeated query parameters, in order.
+            .match_query(Matcher::Exact(
+                "user=alice&allOf=prod&oneOf=web&oneOf=db&noneOf=retired".into(),
+            ))
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
-                    "version": "1.0.0",
-                    "keys": [
-                        {
-                            "name": "alice",
-                            "key": "-----BEGIN PGP PUBLIC KEY BLOCK-----\nmQINB...\n-----END PGP PUBLIC KEY BLOCK-----"
-                        }
-                    ]
-                }"#,
-            )
+            .with_body(r#"{"version": "1.0.0", "keys": []}"#)
             .create();
 
         get_cmd()
-            .args(["--server", &server.url(), "pgp"])
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--user",
+                "alice",
+                "--all-of",
+                "prod",
+                "--one-of",
+                "web",
+                "--one-of",
+                "db",
+                "--none-of",
+                "retired",
+            ])
             .assert()
             .success();
 
@@ -278,98 +433,448 @@ mod tests {
     }
 
     #[test]
-    fn test_pgp_fetch_server_error() {
+    fn test_ssh_dry_run_requires_write() {
+        get_cmd()
+            .args(["--server", "http://127.0.0.1:59999", "ssh", "--dry-run"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--write"));
+    }
+
+    #[test]
+    fn test_ssh_write_managed_mode() {
         let mut server = Server::new();
         let mock = server
-            .mock("GET", "/pgp")
-            .with_status(500)
-            .with_body(r#"{"error": "Internal server error"}"#)
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "new-laptop",
+                            "user": "bob",
+                            "key": "ssh-rsa {NEW_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
             .create();
 
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(
+            &auth_keys_path,
+            format!(
+                "# local\nssh-ed25519 {LOCAL_KEY} me@machine\n\
+                 # BEGIN keys-managed {}\nssh-rsa {REVOKED_KEY} old@laptop\n# END keys-managed\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+
         get_cmd()
-            .args(["--server", &server.url(), "pgp"])
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--managed",
+            ])
             .assert()
-            .failure();
+            .success();
 
         mock.assert();
+
+        // The revoked key is gone from the block; local lines are untouched.
+        let content = fs::read_to_string(&auth_keys_path).unwrap();
+        assert!(content.starts_with(&format!("# local\nssh-ed25519 {LOCAL_KEY} me@machine\n")));
+        assert!(content.contains(&format!("ssh-rsa {NEW_KEY} bob@new-laptop")));
+        assert!(!content.contains(REVOKED_KEY));
     }
 
     #[test]
-    fn test_pgp_import_help_mentions_gnupg() {
+    fn test_ssh_managed_conflicts_with_force() {
         get_cmd()
-            .args(["pgp", "--help"])
+            .args([
+                "--server",
+                "http://127.0.0.1:59999",
+                "ssh",
+                "--write",
+                "/tmp/unused",
+                "--managed",
+                "--force",
+            ])
             .assert()
-            .success()
-            .stdout(predicate::str::contains("--import"))
-            .stdout(predicate::str::contains("GnuPG"));
+            .failure()
+            .stderr(predicate::str::contains("cannot be used with"));
     }
 
     #[test]
-    fn test_pgp_import_empty_keys_skips_gpg() {
-        // With no keys on the server there is nothing to import, so the command
-        // succeeds without ever invoking gpg (regardless of whether it exists).
+    fn test_ssh_write_refuses_lockout_without_override() {
         let mut server = Server::new();
         let mock = server
-            .mock("GET", "/pgp")
+            .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(r#"{"version": "1.0.0", "keys": []}"#)
+            .expect(2)
             .create();
 
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {OLD_KEY} old@machine\n"),
+        )
+        .unwrap();
+
+        // An empty server response must not wipe the file...
         get_cmd()
-            .args(["--server", &server.url(), "pgp", "--import"])
+            .env_remove("SSH_USER_AUTH")
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+            ])
             .assert()
-            .success()
-            .stdout(predicate::str::contains("nothing to import"));
+            .failure()
+            .stderr(predicate::str::contains("--allow-lockout"));
+        assert!(
+            fs::read_to_string(&auth_keys_path)
+                .unwrap()
+                .contains("old@machine")
+        );
+
+        // ...unless the override is given.
+        get_cmd()
+            .env_remove("SSH_USER_AUTH")
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+                "--allow-lockout",
+            ])
+            .assert()
+            .success();
+        assert_eq!(fs::read_to_string(&auth_keys_path).unwrap(), "");
 
         mock.assert();
     }
 
     #[test]
-    fn test_pgp_import_missing_gpg_reports_clear_error() {
-        // Simulate a machine without GnuPG by emptying PATH so the `gpg`
-        // executable cannot be found. The command should fail with a clear,
-        // actionable message rather than a raw OS error.
+    fn test_ssh_write_refuses_to_remove_session_key() {
         let mut server = Server::new();
         let mock = server
-            .mock("GET", "/pgp")
+            .mock("GET", "/keys")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "keys": [
-                        {
-                            "name": "alice",
-                            "key": "-----BEGIN PGP PUBLIC KEY BLOCK-----\nmQINB...\n-----END PGP PUBLIC KEY BLOCK-----"
-                        }
+                        {{
+                            "name": "new-laptop",
+                            "user": "bob",
+                            "key": "ssh-rsa {NEW_KEY}",
+                            "tags": []
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
-        let empty_path_dir = TempDir::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {SESSION_KEY} me@laptop\n"),
+        )
+        .unwrap();
+
+        // The file sshd writes when `ExposeAuthInfo` is enabled.
+        let auth_info_path = temp_dir.path().join("auth_info");
+        fs::write(
+            &auth_info_path,
+            format!("publickey ssh-ed25519 {SESSION_KEY}\n"),
+        )
+        .unwrap();
 
         get_cmd()
-            .args(["--server", &server.url(), "pgp", "--import"])
-            .env("PATH", empty_path_dir.path())
+            .env("SSH_USER_AUTH", &auth_info_path)
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+            ])
             .assert()
             .failure()
-            .stderr(predicate::str::contains("GnuPG"));
+            .stderr(predicate::str::contains("current SSH session"));
 
         mock.assert();
+        assert!(
+            fs::read_to_string(&auth_keys_path)
+                .unwrap()
+                .contains("me@laptop")
+        );
     }
 
-    // A real (throwaway) armored PGP public key, used as a fixture so the import
-    // path can be exercised end-to-end against a real `gpg`. The matching secret
-    // key was discarded after export, so this public key is inert.
-    const TEST_PUBKEY: &str = r#"-----BEGIN PGP PUBLIC KEY BLOCK-----
-
-mDMEaiwB6BYJKwYBBAHaRw8BAQdADBZYOTZAPrC3Wzg6zQmjdu7fPagvQ6J41VwQ
-IL3q26S0KUtleXMgQ0xJIFRlc3QgPGtleXMtY2xpLXRlc3RAZXhhbXBsZS5jb20+
-iJAEExYKADgWIQStE2crp0wmxG/ak23CQ93b0/wpCAUCaiwB6AIbAwULCQgHAgYV
-CgkICwIEFgIDAQIeAQIXgAAKCRDCQ93b0/wpCBsFAQCGQ20lzVGFe1YcH/50UvJo
-iHhghTKX72RlhG2SOLfSFgD/XoteUSqlGRTSgn0j572QUGEDTO9AhFtLkLLMbpur
+    #[test]
+    fn test_ssh_write_respects_must_keep_from_config() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "new-laptop",
+                            "user": "bob",
+                            "key": "ssh-rsa {NEW_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {BREAKGLASS_KEY} admin@console\n"),
+        )
+        .unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "server_url = \"{}\"\n\n[ssh]\nmust_keep = [\"ssh-ed25519 {BREAKGLASS_KEY}\"]\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .env_remove("SSH_USER_AUTH")
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("must-keep key"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_ssh_write_max_removals_aborts_with_summary() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {ALICE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        let existing_content = format!(
+            "ssh-ed25519 {ALICE_KEY} alice@laptop\n\
+                                ssh-rsa {BOB_KEY} bob@desktop\n\
+                                ssh-rsa {CAROL_KEY} carol@desktop\n"
+        );
+        fs::write(&auth_keys_path, &existing_content).unwrap();
+
+        get_cmd()
+            .env_remove("SSH_USER_AUTH")
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+                "--max-removals",
+                "1",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("more than --max-removals 1"))
+            .stderr(predicate::str::contains("bob@desktop"))
+            .stderr(predicate::str::contains("carol@desktop"));
+
+        mock.assert();
+        assert_eq!(
+            fs::read_to_string(&auth_keys_path).unwrap(),
+            existing_content
+        );
+    }
+
+    // ==================== PGP Subcommand Tests ====================
+
+    #[test]
+    fn test_pgp_help() {
+        get_cmd()
+            .args(["pgp", "--help"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("PGP keys"));
+    }
+
+    #[test]
+    fn test_pgp_fetch_success() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/pgp")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "version": "1.0.0",
+                    "keys": [
+                        {
+                            "name": "alice",
+                            "key": "-----BEGIN PGP PUBLIC KEY BLOCK-----\nmQINB...\n-----END PGP PUBLIC KEY BLOCK-----"
+                        }
+                    ]
+                }"#,
+            )
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "pgp"])
+            .assert()
+            .success();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_pgp_fetch_server_error() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/pgp")
+            .with_status(500)
+            .with_body(r#"{"error": "Internal server error"}"#)
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "--retries", "0", "pgp"])
+            .assert()
+            .failure();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_pgp_import_help_mentions_gnupg() {
+        get_cmd()
+            .args(["pgp", "--help"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("--import"))
+            .stdout(predicate::str::contains("GnuPG"));
+    }
+
+    #[test]
+    fn test_pgp_import_empty_keys_skips_gpg() {
+        // With no keys on the server there is nothing to import, so the command
+        // succeeds without ever invoking gpg (regardless of whether it exists).
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/pgp")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"version": "1.0.0", "keys": []}"#)
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "pgp", "--import"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("nothing to import"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_pgp_import_missing_gpg_reports_clear_error() {
+        // Simulate a machine without GnuPG by emptying PATH so the `gpg`
+        // executable cannot be found. The command should fail with a clear,
+        // actionable message rather than a raw OS error.
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/pgp")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "version": "1.0.0",
+                    "keys": [
+                        {
+                            "name": "alice",
+                            "key": "-----BEGIN PGP PUBLIC KEY BLOCK-----\nmQINB...\n-----END PGP PUBLIC KEY BLOCK-----"
+                        }
+                    ]
+                }"#,
+            )
+            .create();
+
+        let empty_path_dir = TempDir::new().unwrap();
+
+        get_cmd()
+            .args(["--server", &server.url(), "pgp", "--import"])
+            .env("PATH", empty_path_dir.path())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("GnuPG"));
+
+        mock.assert();
+    }
+
+    // A real (throwaway) armored PGP public key, used as a fixture so the import
+    // path can be exercised end-to-end against a real `gpg`. The matching secret
+    // key was discarded after export, so this public key is inert.
+    const TEST_PUBKEY: &str = r#"-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEaiwB6BYJKwYBBAHaRw8BAQdADBZYOTZAPrC3Wzg6zQmjdu7fPagvQ6J41VwQ
+IL3q26S0KUtleXMgQ0xJIFRlc3QgPGtleXMtY2xpLXRlc3RAZXhhbXBsZS5jb20+
+iJAEExYKADgWIQStE2crp0wmxG/ak23CQ93b0/wpCAUCaiwB6AIbAwULCQgHAgYV
+CgkICwIEFgIDAQIeAQIXgAAKCRDCQ93b0/wpCBsFAQCGQ20lzVGFe1YcH/50UvJo
+iHhghTKX72RlhG2SOLfSFgD/XoteUSqlGRTSgn0j572QUGEDTO9AhFtLkLLMbpur
 QQq4OARqLAHoEgorBgEEAZdVAQUBAQdAMKr4RAVl9lRZdPtAdz5oQ+e6qjJMUBsi
 gAy1LK6cD1EDAQgHiHgEGBYKACAWIQStE2crp0wmxG/ak23CQ93b0/wpCAUCaiwB
 6AIbDAAKCRDCQ93b0/wpCNKFAQDnBlIkWzorIdSgTBTgX1zwf76zhPp+sB1EX47q
@@ -475,7 +980,7 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
             .create();
 
         get_cmd()
-            .args(["--server", &server.url(), "known-hosts"])
+            .args(["--server", &server.url(), "--retries", "0", "known-hosts"])
             .assert()
             .failure();
 
@@ -538,29 +1043,33 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
             .mock("GET", "/known_hosts")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "knownHosts": [
-                        {
+                        {{
                             "hosts": ["new.example.com"],
                             "keys": [
-                                {
+                                {{
                                     "type": "ssh-rsa",
-                                    "key": "AAAABNewHostKey"
-                                }
+                                    "key": "{NEW_HOST_KEY}"
+                                }}
                             ]
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         let temp_dir = TempDir::new().unwrap();
         let known_hosts_path = temp_dir.path().join("known_hosts");
 
         // Existing file with a local-only entry
-        fs::write(&known_hosts_path, "old.example.com ssh-rsa OldHostKey\n").unwrap();
+        fs::write(
+            &known_hosts_path,
+            format!("old.example.com ssh-rsa {OLD_HOST_KEY}\n"),
+        )
+        .unwrap();
 
         // Without --force, should preserve existing entries
         get_cmd()
@@ -577,8 +1086,8 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
         mock.assert();
 
         let content = fs::read_to_string(&known_hosts_path).unwrap();
-        assert!(content.contains("old.example.com ssh-rsa OldHostKey"));
-        assert!(content.contains("new.example.com ssh-rsa AAAABNewHostKey"));
+        assert!(content.contains(&format!("old.example.com ssh-rsa {OLD_HOST_KEY}")));
+        assert!(content.contains(&format!("new.example.com ssh-rsa {NEW_HOST_KEY}")));
     }
 
     #[test]
@@ -588,29 +1097,33 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
             .mock("GET", "/known_hosts")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(
-                r#"{
+            .with_body(format!(
+                r#"{{
                     "version": "1.0.0",
                     "knownHosts": [
-                        {
+                        {{
                             "hosts": ["new.example.com"],
                             "keys": [
-                                {
+                                {{
                                     "type": "ssh-rsa",
-                                    "key": "AAAABNewHostKey"
-                                }
+                                    "key": "{NEW_HOST_KEY}"
+                                }}
                             ]
-                        }
+                        }}
                     ]
-                }"#,
-            )
+                }}"#
+            ))
             .create();
 
         let temp_dir = TempDir::new().unwrap();
         let known_hosts_path = temp_dir.path().join("known_hosts");
 
         // Existing file with a local-only entry
-        fs::write(&known_hosts_path, "old.example.com ssh-rsa OldHostKey\n").unwrap();
+        fs::write(
+            &known_hosts_path,
+            format!("old.example.com ssh-rsa {OLD_HOST_KEY}\n"),
+        )
+        .unwrap();
 
         // With --force, the file is replaced entirely
         get_cmd()
@@ -629,27 +1142,315 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
 
         let content = fs::read_to_string(&known_hosts_path).unwrap();
         assert!(!content.contains("old.example.com"));
-        assert!(content.contains("new.example.com ssh-rsa AAAABNewHostKey"));
+        assert!(content.contains(&format!("new.example.com ssh-rsa {NEW_HOST_KEY}")));
     }
 
-    // ==================== Init Subcommand Tests ====================
-
     #[test]
-    fn test_init_help() {
-        get_cmd()
-            .args(["init", "--help"])
-            .assert()
-            .success()
-            .stdout(predicate::str::contains("config"));
-    }
+    fn test_known_hosts_write_dry_run_prints_diff() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "knownHosts": [
+                        {{
+                            "hosts": ["new.example.com"],
+                            "keys": [{{"type": "ssh-ed25519", "key": "{NEW_HOST_ED25519_KEY}"}}]
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
 
-    #[test]
-    fn test_init_creates_config() {
         let temp_dir = TempDir::new().unwrap();
+        let known_hosts_path = temp_dir.path().join("known_hosts");
+        fs::write(
+            &known_hosts_path,
+            format!("old.example.com ssh-rsa {OLD_HOST_KEY}\n"),
+        )
+        .unwrap();
 
-        // Point HOME and XDG_CONFIG_HOME at a writable temp directory so the
-        // default config location is both writable (e.g. Nix builds) and
-        // deterministic regardless of the host's own XDG settings.
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "known-hosts",
+                "--write",
+                known_hosts_path.to_str().unwrap(),
+                "--force",
+                "--dry-run",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "-old.example.com ssh-rsa {OLD_HOST_KEY}"
+            )))
+            .stdout(predicate::str::contains(format!(
+                "+new.example.com ssh-ed25519 {NEW_HOST_ED25519_KEY}"
+            )))
+            .stdout(predicate::str::contains("Dry run"));
+
+        mock.assert();
+
+        // Nothing was written.
+        let content = fs::read_to_string(&known_hosts_path).unwrap();
+        assert_eq!(content, format!("old.example.com ssh-rsa {OLD_HOST_KEY}\n"));
+    }
+
+    #[test]
+    fn test_known_hosts_write_managed_mode_warns_about_duplicates() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "knownHosts": [
+                        {{
+                            "hosts": ["github.com"],
+                            "keys": [{{"type": "ssh-ed25519", "key": "{GITHUB_KEY}"}}]
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let known_hosts_path = temp_dir.path().join("known_hosts");
+        fs::write(
+            &known_hosts_path,
+            format!("github.com ssh-ed25519 {GITHUB_KEY}\n"),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "known-hosts",
+                "--write",
+                known_hosts_path.to_str().unwrap(),
+                "--managed",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("duplicate a server entry"));
+
+        mock.assert();
+
+        let content = fs::read_to_string(&known_hosts_path).unwrap();
+        assert!(content.contains(&format!("# BEGIN keys-managed {}", server.url())));
+    }
+
+    // ==================== Restore Subcommand Tests ====================
+
+    #[test]
+    fn test_restore_latest_backup_after_write() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "new-laptop",
+                            "user": "bob",
+                            "key": "ssh-rsa {NEW_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-ed25519 {OLD_KEY} old@machine\n"),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+            ])
+            .assert()
+            .success();
+        mock.assert();
+
+        get_cmd()
+            .args(["restore", auth_keys_path.to_str().unwrap(), "--list"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("(latest)"));
+
+        get_cmd()
+            .args(["restore", auth_keys_path.to_str().unwrap()])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Restored"));
+
+        let content = fs::read_to_string(&auth_keys_path).unwrap();
+        assert_eq!(content, format!("ssh-ed25519 {OLD_KEY} old@machine\n"));
+    }
+
+    #[test]
+    fn test_restore_without_backups_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        fs::write(&auth_keys_path, "ssh-ed25519 Key me@machine\n").unwrap();
+
+        get_cmd()
+            .args(["restore", auth_keys_path.to_str().unwrap()])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("No backups found"));
+    }
+
+    // ==================== Sync Subcommand Tests ====================
+
+    #[test]
+    fn test_sync_applies_every_target() {
+        let mut server = Server::new();
+        let keys_mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {ALICE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+        let known_hosts_mock = server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "knownHosts": [
+                        {{
+                            "hosts": ["github.com"],
+                            "keys": [{{"type": "ssh-ed25519", "key": "{GITHUB_KEY}"}}]
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        let managed_keys_path = temp_dir.path().join("managed_authorized_keys");
+        let known_hosts_path = temp_dir.path().join("known_hosts");
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                r#"server_url = "{}"
+
+[[targets]]
+kind = "ssh"
+path = "{}"
+
+[[targets]]
+kind = "ssh"
+path = "{}"
+mode = "managed"
+
+[[targets]]
+kind = "known-hosts"
+path = "{}"
+"#,
+                server.url(),
+                auth_keys_path.display(),
+                managed_keys_path.display(),
+                known_hosts_path.display()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .env_remove("SSH_USER_AUTH")
+            .args(["--config", config_path.to_str().unwrap(), "sync"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Synced all 3 targets"));
+
+        keys_mock.assert();
+        known_hosts_mock.assert();
+        assert!(
+            fs::read_to_string(&auth_keys_path)
+                .unwrap()
+                .contains(ALICE_KEY)
+        );
+        assert!(
+            fs::read_to_string(&managed_keys_path)
+                .unwrap()
+                .contains("# BEGIN keys-managed")
+        );
+        assert!(
+            fs::read_to_string(&known_hosts_path)
+                .unwrap()
+                .contains(GITHUB_KEY)
+        );
+    }
+
+    #[test]
+    fn test_sync_without_targets_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "server_url = \"http://127.0.0.1:1\"\n").unwrap();
+
+        get_cmd()
+            .args(["--config", config_path.to_str().unwrap(), "sync"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("No sync targets configured"));
+    }
+
+    // ==================== Init Subcommand Tests ====================
+
+    #[test]
+    fn test_init_help() {
+        get_cmd()
+            .args(["init", "--help"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("config"));
+    }
+
+    #[test]
+    fn test_init_creates_config() {
+        let temp_dir = TempDir::new().unwrap();
+
+        // Point HOME and XDG_CONFIG_HOME at a writable temp directory so the
+        // default config location is both writable (e.g. Nix builds) and
+        // deterministic regardless of the host's own XDG settings.
         let xdg_config = temp_dir.path().join(".config");
         get_cmd()
             .arg("init")
@@ -773,21 +1574,1233 @@ pitFQwD/S8QRI+0h0qW8PGanUrFX+VeGErdCwKwfPWsBMbovDgc=
     }
 
     #[test]
-    fn test_nonexistent_config_file_errors() {
-        // An explicitly specified --config path that doesn't exist should fail
-        // fast, even when --server is supplied, rather than silently falling
-        // back to defaults. The server is never contacted.
+    fn test_config_rejects_unknown_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            "server_url = \"http://localhost:8000\"\ntimeout = 5\n",
+        )
+        .unwrap();
+
+        get_cmd()
+            .args(["--config", config_path.to_str().unwrap(), "ssh"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("unknown field `timeout`"));
+    }
+
+    #[test]
+    fn test_config_ssh_defaults_apply_and_flags_override() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::Exact("noneOf=retired".to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {ALICE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(2)
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "server_url = \"{}\"\n\n[ssh]\npath = \"{}\"\nmode = \"managed\"\nnone_of = [\"retired\"]\n",
+                server.url(),
+                auth_keys_path.display()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &auth_keys_path,
+            format!("ssh-rsa {LOCAL_RSA_KEY} me@host\n"),
+        )
+        .unwrap();
+
+        // `--write` without a path uses `[ssh] path`, in `[ssh] mode`.
+        get_cmd()
+            .env_remove("SSH_USER_AUTH")
+            .args(["--config", config_path.to_str().unwrap(), "ssh", "--write"])
+            .assert()
+            .success();
+        let content = fs::read_to_string(&auth_keys_path).unwrap();
+        assert!(content.contains("# BEGIN keys-managed"));
+
+        // `--force` overrides the configured mode.
         get_cmd()
+            .env_remove("SSH_USER_AUTH")
             .args([
                 "--config",
-                "/nonexistent/config.toml",
-                "--server",
-                "http://localhost:8000",
+                config_path.to_str().unwrap(),
                 "ssh",
+                "--write",
+                "--force",
+            ])
+            .assert()
+            .success();
+        let content = fs::read_to_string(&auth_keys_path).unwrap();
+        assert_eq!(content, format!("ssh-ed25519 {ALICE_KEY} alice@laptop"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_write_without_path_or_config_default_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "server_url = \"http://127.0.0.1:1\"\n").unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "known-hosts",
+                "--write",
             ])
             .assert()
             .failure()
-            .stderr(predicate::str::contains("not found"));
+            .stderr(predicate::str::contains("[known_hosts] path"));
+    }
+
+    #[test]
+    fn test_output_flag_overrides_config() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {ALICE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!("server_url = \"{}\"\noutput = \"raw\"\n", server.url()),
+        )
+        .unwrap();
+
+        // Tables are printed even though stdout isn't a terminal.
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--output",
+                "table",
+                "ssh",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Keys Server Version:"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_ssh_json_output_includes_tags() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {ALICE_KEY}",
+                            "tags": ["prod", "oak"]
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let output = get_cmd()
+            .args(["--server", &server.url(), "--output", "json", "ssh"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": "1.0.0",
+                "keys": [
+                    {
+                        "key": format!("ssh-ed25519 {ALICE_KEY}"),
+                        "user": "alice",
+                        "name": "laptop",
+                        "tags": ["prod", "oak"],
+                        "fingerprint": "SHA256:xu6E83eTVNq2wrOGfXIXMNzdKJHoHGXhYzmNr6OfWbg"
+                    }
+                ]
+            })
+        );
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_known_hosts_yaml_output_includes_flags() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "knownHosts": [
+                        {{
+                            "name": "github",
+                            "hosts": ["github.com"],
+                            "keys": [
+                                {{"type": "ssh-ed25519", "key": "{GITHUB_KEY}", "cert-authority": true}}
+                            ]
+                        }}
+                    ]
+                }}"#),
+            )
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "--output", "yaml", "known-hosts"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "knownHosts:\n- name: \"github\"\n  hosts:\n  - \"github.com\"\n",
+            ))
+            .stdout(predicate::str::contains("    cert-authority: true\n"))
+            .stdout(predicate::str::contains(
+                "    fingerprint: \"SHA256:INRx2tBrT2tSCzp0i6GqDk/Z05BykfHS2Fse9bQ6wPc\"\n",
+            ));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_ssh_fingerprint_selects_key() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"name": "laptop", "user": "alice", "key": "ssh-ed25519 {ALICE_KEY}", "tags": []}},
+                        {{"name": "desktop", "user": "bob", "key": "ssh-ed25519 {GITHUB_KEY}", "tags": []}}
+                    ]
+                }}"#),
+            )
+            .expect(2)
+            .create();
+
+        // As sshd logs it: "Accepted publickey for bob ... ED25519 SHA256:..."
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "--output",
+                "table",
+                "ssh",
+                "--fingerprint",
+                "SHA256:INRx2tBrT2tSCzp0i6GqDk/Z05BykfHS2Fse9bQ6wPc",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("desktop"))
+            .stdout(predicate::str::contains(
+                "SHA256:INRx2tBrT2tSCzp0i6GqDk/Z05BykfHS2Fse9bQ6wPc",
+            ))
+            .stdout(predicate::str::contains("laptop").not());
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "--output",
+                "table",
+                "--fingerprint-hash",
+                "md5",
+                "ssh",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("FINGERPRINT"))
+            .stdout(predicate::str::contains("MD5:"));
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "ssh",
+                "--fingerprint",
+                "INRx2tBr",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("expected SHA256:"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_repeated_server_flag_merges_servers() {
+        let mut team = Server::new();
+        let team_mock = team
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"name": "laptop", "user": "alice", "key": "ssh-ed25519 {SHARED_KEY}", "tags": []}}
+                    ]
+                }}"#),
+            )
+            .create();
+        let mut glass = Server::new();
+        let glass_mock = glass
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"name": "console", "user": "admin", "key": "ssh-ed25519 {SHARED_KEY}", "tags": []}},
+                        {{"name": "console", "user": "admin", "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKmaVKNFszc5vabksblFiLD/KV130Vil1oOW+t0n6wGY", "tags": []}}
+                    ]
+                }}"#),
+            )
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let auth_keys_path = temp_dir.path().join("authorized_keys");
+
+        get_cmd()
+            .args([
+                "--server",
+                &team.url(),
+                "--server",
+                &glass.url(),
+                "ssh",
+                "--write",
+                auth_keys_path.to_str().unwrap(),
+                "--force",
+            ])
+            .env_remove("SSH_USER_AUTH")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(format!(
+                "Conflict: key ssh-ed25519 {SHARED_KEY} is alice@laptop"
+            )));
+
+        assert_eq!(
+            fs::read_to_string(&auth_keys_path).unwrap(),
+            format!(
+                "ssh-ed25519 {SHARED_KEY} alice@laptop (from {}, {})\n\
+                 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKmaVKNFszc5vabksblFiLD/KV130Vil1oOW+t0n6wGY admin@console (from {})",
+                team.url(),
+                glass.url(),
+                glass.url()
+            )
+        );
+        team_mock.assert();
+        glass_mock.assert();
+    }
+
+    #[test]
+    fn test_profile_flag_selects_server() {
+        let mut staging = Server::new();
+        let mock = staging
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {STAGING_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        // The default profile points at a server that isn't running, so the
+        // command only succeeds if `--profile` wins.
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "default_profile = \"production\"\n\n\
+                 [profiles.production]\nserver_url = \"http://127.0.0.1:1\"\n\n\
+                 [profiles.staging]\nserver_url = \"{}\"\n",
+                staging.url()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--profile",
+                "staging",
+                "ssh",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "ssh-ed25519 {STAGING_KEY}"
+            )));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_auth_token_command_is_sent() {
+        let mut server = Server::new();
+        let denied = server
+            .mock("GET", "/keys")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(401)
+            .create();
+        let mock = server
+            .mock("GET", "/keys")
+            .match_header("authorization", "Bearer abc123")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {AUTHENTICATED_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "server_url = \"{}\"\n\n[auth]\ntoken_command = \"echo abc123\"\n\n\
+                 [profiles.anonymous]\n[profiles.anonymous.auth]\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args(["--config", config_path.to_str().unwrap(), "ssh"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "ssh-ed25519 {AUTHENTICATED_KEY}"
+            )));
+        mock.assert();
+
+        // Without credentials the 401 is explained
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--profile",
+                "anonymous",
+                "ssh",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "requires authentication (401); set a token",
+            ));
+        denied.assert();
+    }
+
+    #[test]
+    fn test_unknown_profile_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "[profiles.staging]\n").unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--profile",
+                "prod",
+                "ssh",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Unknown profile \"prod\" (available profiles: staging)",
+            ));
+    }
+
+    #[test]
+    fn test_config_profiles_lists_profiles() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            "default_profile = \"production\"\n\n\
+             [profiles.production]\nserver_url = \"https://keys.example.com\"\n\n\
+             [profiles.staging]\nserver_url = \"https://keys.staging.example.com\"\n",
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "config",
+                "profiles",
+            ])
+            .assert()
+            .success()
+            .stdout("production\nstaging\n");
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--output",
+                "table",
+                "--profile",
+                "staging",
+                "config",
+                "profiles",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Selected profile: staging"))
+            .stdout(predicate::str::contains("https://keys.staging.example.com"))
+            .stdout(predicate::str::contains("default"));
+    }
+
+    #[test]
+    fn test_nonexistent_config_file_errors() {
+        // An explicitly specified --config path that doesn't exist should fail
+        // fast, even when --server is supplied, rather than silently falling
+        // back to defaults. The server is never contacted.
+        get_cmd()
+            .args([
+                "--config",
+                "/nonexistent/config.toml",
+                "--server",
+                "http://localhost:8000",
+                "ssh",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("not found"));
+    }
+
+    // ==================== Cache Tests ====================
+
+    #[test]
+    fn test_offline_uses_cached_response() {
+        let cache_dir = TempDir::new().unwrap();
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "ssh"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success();
+
+        // The server isn't asked again
+        get_cmd()
+            .args(["--server", &server.url(), "--offline", "ssh"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "ssh-ed25519 {EXAMPLE_KEY} alice@laptop"
+            )))
+            .stderr(predicate::str::contains("Using the cached response"));
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fallback_to_cache_when_server_fails() {
+        let cache_dir = TempDir::new().unwrap();
+        let mut server = Server::new();
+        server
+            .mock("GET", "/known_hosts")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "knownHosts": [
+                        {{
+                            "name": "GitHub",
+                            "hosts": ["github.com"],
+                            "keys": [
+                                {{
+                                    "type": "ssh-ed25519",
+                                    "key": "{EXAMPLE_KEY}"
+                                }}
+                            ]
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "known-hosts"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success();
+
+        server.mock("GET", "/known_hosts").with_status(503).create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "--retries", "0", "known-hosts"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Server returned error code: 503"));
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "--retries",
+                "0",
+                "--fallback-to-cache",
+                "known-hosts",
+            ])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "github.com ssh-ed25519 {EXAMPLE_KEY}"
+            )))
+            .stderr(predicate::str::contains("Using the cached response"));
+    }
+
+    #[test]
+    fn test_ssh_write_skips_unchanged_file() {
+        let cache_dir = TempDir::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("authorized_keys");
+        let mut server = Server::new();
+        server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", "\"v1\"")
+            .with_header("x-keys-version", "1.0.0")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+
+        let server_url = server.url();
+        let write = || {
+            get_cmd()
+                .args([
+                    "--server",
+                    &server_url,
+                    "ssh",
+                    "--write",
+                    file_path.to_str().unwrap(),
+                    "--managed",
+                ])
+                .env("XDG_CACHE_HOME", cache_dir.path())
+                .assert()
+                .success()
+        };
+        write().stdout(predicate::str::contains("Synced managed block"));
+
+        let not_modified = server
+            .mock("GET", "/keys")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .with_header("etag", "\"v1\"")
+            .expect(2)
+            .create();
+
+        // Nothing changed, so the file isn't backed up or rewritten
+        write()
+            .stdout(predicate::str::contains(
+                "is unchanged since version 1.0.0; nothing to do",
+            ))
+            .stdout(predicate::str::contains("Backed up").not());
+
+        // A file changed locally since is synced again, from the cached response
+        let contents = fs::read_to_string(&file_path).unwrap();
+        fs::write(&file_path, format!("# note\n{contents}")).unwrap();
+        write().stdout(predicate::str::contains("unchanged").not());
+        assert!(
+            fs::read_to_string(&file_path)
+                .unwrap()
+                .contains(&format!("ssh-ed25519 {EXAMPLE_KEY} alice@laptop"))
+        );
+
+        not_modified.assert();
+    }
+
+    #[test]
+    fn test_ssh_write_skips_unchanged_file_without_validators() {
+        let cache_dir = TempDir::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("authorized_keys");
+        let mut server = Server::new();
+        // As the bundled server answers: no ETag or Last-Modified, only the
+        // version of its data
+        let mock = server
+            .mock("GET", "/keys")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("x-keys-version", "1.0.0")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(2)
+            .create();
+
+        let server_url = server.url();
+        let write = || {
+            get_cmd()
+                .args([
+                    "--server",
+                    &server_url,
+                    "ssh",
+                    "--write",
+                    file_path.to_str().unwrap(),
+                    "--managed",
+                ])
+                .env("XDG_CACHE_HOME", cache_dir.path())
+                .assert()
+                .success()
+        };
+        write().stdout(predicate::str::contains("Synced managed block"));
+
+        // The same body again, so the file isn't backed up or rewritten
+        write()
+            .stdout(predicate::str::contains(
+                "is unchanged since version 1.0.0; nothing to do",
+            ))
+            .stdout(predicate::str::contains("Backed up").not());
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_offline_without_cached_response_fails() {
+        let cache_dir = TempDir::new().unwrap();
+
+        get_cmd()
+            .args(["--server", "http://127.0.0.1:1", "--offline", "pgp"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "nothing has been cached for it yet",
+            ));
+    }
+
+    // ==================== Version Tests ====================
+
+    #[test]
+    fn test_expect_version_mismatch_fails_before_writing() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("authorized_keys");
+        let mut server = Server::new();
+        server
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("x-keys-version", "v1.3.0")
+            .with_body(format!(
+                r#"{{
+                    "version": "v1.3.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "--expect-version",
+                ">=1.4",
+                "ssh",
+                "--write",
+                file_path.to_str().unwrap(),
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "reports version v1.3.0, which doesn't satisfy the expected version >=1.4",
+            ));
+        assert!(!file_path.exists());
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "--expect-version",
+                "1.3",
+                "ssh",
+                "--write",
+                file_path.to_str().unwrap(),
+            ])
+            .assert()
+            .success();
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_signed_responses_are_required_before_writing() {
+        // Signed with `ssh-keygen -Y sign -n keys` by the trusted key
+        let body = format!(
+            r#"{{"version":"1.0.0","keys":[{{"name":"laptop","user":"alice","key":"ssh-ed25519 {SIGNED_KEY}","tags":[]}}]}}"#
+        );
+        let signature = "U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgf2x9t2Dm116TJ+DOedazs8Bgqf863CzqHXmX2xrNndQAAAAEa2V5cwAAAAAAAAAGc2hhNTEyAAAAUwAAAAtzc2gtZWQyNTUxOQAAAECy5dbzv62He0zrhfYpS7VduDumgHeJBuKBDY2LpN69H30zs1Ui1GyHAxb0ssEFFzowx7efLXUp6dYlALzlmgIA";
+        let trusted_key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U keys-server";
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("authorized_keys");
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!("[signatures]\ntrusted_keys = [\"{trusted_key}\"]\n"),
+        )
+        .unwrap();
+
+        let mut unsigned = Server::new();
+        unsigned
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(&body)
+            .create();
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--server",
+                &unsigned.url(),
+                "ssh",
+                "--write",
+                file_path.to_str().unwrap(),
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "it isn't signed (there's no x-keys-signature header)",
+            ));
+        assert!(!file_path.exists());
+
+        let mut signed = Server::new();
+        signed
+            .mock("GET", "/keys")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("x-keys-signature", signature)
+            .with_body(&body)
+            .create();
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "--server",
+                &signed.url(),
+                "ssh",
+                "--write",
+                file_path.to_str().unwrap(),
+            ])
+            .assert()
+            .success();
+        assert!(
+            fs::read_to_string(&file_path)
+                .unwrap()
+                .contains(&format!("ssh-ed25519 {SIGNED_KEY}"))
+        );
+    }
+
+    #[test]
+    fn test_authorized_keys_command_prints_only_the_users_keys() {
+        let mut server = Server::new();
+        // A server that ignores the `user` query must not hand out bob's key
+        let mock = server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "user".to_string(),
+                "alice".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U",
+                            "tags": ["prod"]
+                        }},
+                        {{
+                            "name": "desktop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": ["prod"]
+                        }},
+                        {{
+                            "name": "laptop",
+                            "user": "bob",
+                            "key": "ssh-ed25519 {BOB_ED25519_KEY}",
+                            "tags": ["prod"]
+                        }}
+                    ]
+                }}"#),
+            )
+            .expect(2)
+            .create();
+
+        get_cmd()
+            .args(["--server", &server.url(), "authorized-keys-command", "alice"])
+            .assert()
+            .success()
+            .stdout(
+                format!("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U alice@laptop\n\
+                 ssh-ed25519 {EXAMPLE_KEY} alice@desktop\n"),
+            );
+
+        // With the offered key's type and fingerprint, only that key
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "authorized-keys-command",
+                "alice",
+                "ssh-ed25519",
+                "SHA256:KNrHOHommfdyysg6MTzlrNDv9FA9Ka2X+gWyfhROnys",
+            ])
+            .assert()
+            .success()
+            .stdout(
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH9sfbdg5tdekyfgznnWs7PAYKn/Otws6h15l9sazZ3U alice@laptop\n",
+            );
+        mock.assert();
+    }
+
+    #[test]
+    fn test_authorized_keys_command_applies_config_tags() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("user".to_string(), "alice".to_string()),
+                mockito::Matcher::UrlEncoded("noneOf".to_string(), "retired".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "server_url = \"{}\"\n\n[authorized_keys_command]\nnone_of = [\"retired\"]\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "authorized-keys-command",
+                "alice",
+            ])
+            .assert()
+            .success()
+            .stdout(format!("ssh-ed25519 {EXAMPLE_KEY} alice@laptop\n"));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_authorized_keys_command_applies_tag_options() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "user".to_string(),
+                "deploy".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                format!(r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{"name": "ci", "user": "deploy", "key": "ssh-ed25519 {CI_KEY}", "tags": ["ci", "jump"]}},
+                        {{"name": "laptop", "user": "deploy", "key": "ssh-ed25519 {LAPTOP_KEY}", "tags": []}}
+                    ]
+                }}"#),
+            )
+            .create();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "server_url = \"{}\"\n\n[ssh.tag_options]\nci = 'restrict,command=\"/usr/local/bin/deploy\"'\njump = 'permitopen=\"*:22\"'\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+
+        get_cmd()
+            .args([
+                "--config",
+                config_path.to_str().unwrap(),
+                "authorized-keys-command",
+                "deploy",
+            ])
+            .assert()
+            .success()
+            .stdout(
+                format!("restrict,command=\"/usr/local/bin/deploy\",permitopen=\"*:22\" ssh-ed25519 {CI_KEY} deploy@ci\n\
+                 ssh-ed25519 {LAPTOP_KEY} deploy@laptop\n"),
+            );
+        mock.assert();
+    }
+
+    #[test]
+    fn test_authorized_keys_command_falls_back_to_cache() {
+        let cache_dir = TempDir::new().unwrap();
+        let mut server = Server::new();
+        server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{
+                    "version": "1.0.0",
+                    "keys": [
+                        {{
+                            "name": "laptop",
+                            "user": "alice",
+                            "key": "ssh-ed25519 {EXAMPLE_KEY}",
+                            "tags": []
+                        }}
+                    ]
+                }}"#
+            ))
+            .expect(1)
+            .create();
+
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "authorized-keys-command",
+                "alice",
+            ])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success();
+
+        // Without --fallback-to-cache, and without retrying
+        let failing = server
+            .mock("GET", "/keys")
+            .match_query(mockito::Matcher::Any)
+            .with_status(503)
+            .expect(2)
+            .create();
+        get_cmd()
+            .args([
+                "--server",
+                &server.url(),
+                "authorized-keys-command",
+                "alice",
+            ])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .success()
+            .stdout(format!("ssh-ed25519 {EXAMPLE_KEY} alice@laptop\n"))
+            .stderr(predicate::str::contains("Using the cached response"));
+
+        // Nothing is cached for bob, so only the error is printed, on stderr
+        get_cmd()
+            .args(["--server", &server.url(), "authorized-keys-command", "bob"])
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr(predicate::str::contains("nothing has been cached"));
+        failing.assert();
+    }
+
+    #[test]
+    fn test_expect_version_rejects_invalid_requirement() {
+        get_cmd()
+            .args(["--expect-version", "latest", "ssh"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Invalid version requirement"));
+    }
+
+    #[test]
+    fn test_version_shows_client_and_server_versions() {
+        let mut server = Server::new();
+        server
+            .mock("GET", "/")
+            .with_status(200)
+            .with_header("x-keys-version", "1.4.2")
+            .with_body("Welcome")
+            .create();
+
+        get_cmd()
+            .args(["version", "--server", &server.url()])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "Client: {}",
+                env!("CARGO_PKG_VERSION")
+            )))
+            .stdout(predicate::str::contains("Server: 1.4.2"));
+
+        get_cmd()
+            .args([
+                "version",
+                "--server",
+                &server.url(),
+                "--expect-version",
+                "^2",
+            ])
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("Server: 1.4.2"))
+            .stderr(predicate::str::contains(
+                "doesn't satisfy the expected version ^2",
+            ));
     }
 
     // ==================== Error Handling Tests ====================